@@ -54,6 +54,9 @@ fat_reserved_entries            dw 3
 ; (default value generated by `mkfs.vfat -v -F16` from makefile)
 sectors_per_cluster             dw 4
 
+; the amount of clusters loaded by the last call of load_file
+loaded_clusters_amount          dw 0
+
 ;-----------------------------------------------------------------------------
 ; Displays every character from the given address, until 0 is found
 ;-----------------------------------------------------------------------------
@@ -191,10 +194,13 @@ read_sectors:
 ; DS: data segment of the file name to find
 ; SI: the address of the string of the file name to find (DS:SI)
 ; ES:BX: location where the whole file must be loaded
+; the amount of loaded clusters is stored into [loaded_clusters_amount]
 ;-----------------------------------------------------------------------------
 
 load_file:
 
+        mov word [loaded_clusters_amount], 0
+
         ; push on the stack the functions arguments required later
         ; but stored for now into registers required right now
         push bx
@@ -324,6 +330,8 @@ load_file:
 
         call read_sectors
 
+        inc word [loaded_clusters_amount]
+
         ; check if others clusters have to be loaded,
         ; the FAT content must be read
 
@@ -348,10 +356,13 @@ load_file:
         pop bx
         pop ds      ; get back previous data segment and loading offset
 
-        ; one cluster has been loaded and bx contains the offset to the beginning of that cluster,
-        ; every cluster is four sectors long (2048 bytes), so if another cluster has to be loaded
-        ; for the current file, then it has to be loaded 2048 bytes after
-        add bx, 2048
+        ; one cluster has been loaded at es:bx, every cluster is four sectors long (2048 bytes),
+        ; so if another cluster has to be loaded for the current file, then it has to be loaded
+        ; 2048 bytes after; the segment is moved forward (2048 / 16 = 0x80) instead of the offset,
+        ; so the file can be larger than 64 KBytes
+        mov ax, es
+        add ax, 0x0080
+        mov es, ax
 
         ; check if the cluster is the end of the file
         cmp dx, 0xFFFF
@@ -1,6 +1,6 @@
 ;-----------------------------------------------------------------------------
 ; stage2 section
-; - get RAM amount and store it
+; - get the memory map and store it
 ; - loads the Global Descriptor Table
 ; - loads the kernel from disk to memory
 ; - enable A20 for 32 bits-long addresses
@@ -142,6 +142,15 @@ gdt:
     dw gdt_end - gdt_start - 1      ; the size of the GDT
     dd gdt_start                    ; the starting address of the GDT
 
+gdt_higher_half:
+
+    ; the same GDT, accessed through the kernel mapping (0xC0000000 + physical address),
+    ; loaded once paging is enabled, so the GDT stays accessible
+    ; when the kernel removes the bootstrap identity mapping
+
+    dw gdt_end - gdt_start - 1
+    dd gdt_start + 0xC0000000
+
 start:
 
     ; reset the stack, forget all the remaining stacked data,
@@ -157,56 +166,68 @@ start:
     ; (has to be done before as we use BIOS interrupts for now)
     mov si, kernel
 
-    ; the kernel will be loaded right after stage2
-    ; stage2 is loaded at 0x07E00, it uses 4 sectors of 512 bytes,
-    ; so we load the kernel 2048 bytes after (4 * 512),
-    ; so the kernel is loaded at 0x08600 (0x0860:0x0000)
-    mov bx, 0x0860
+    ; the kernel file is loaded at 0x20000 (0x2000:0x0000), after the root directory,
+    ; the FAT (still read while the file is loaded) and the memory map buffer;
+    ; this area (up to the stack of stage3 at 0x9FFF0) is only used by the kernel
+    ; once it has been copied to its final location (kernel timers and frames bitmap),
+    ; so the kernel file can be up to 0x7F000 bytes long (508 KBytes)
+    mov bx, 0x2000
     mov es, bx
     xor bx, bx
 
     call load_file
 
-    ; get the memory size in order to be displayed during the kernel loading process;
-    ; we do it here into stage2 as we can simply use the dedicated BIOS interrupt
-    mov ah, 0x88            ; function to get the amount of extended memory in KB (after 1MB)
-    int 0x15                ; BIOS interrupt call
-
-    ; store the RAM amount for kernel usage into 0x1180A (0x1180:0x000A).
-    push ds
-    mov bx, 0x1180
-    mov ds, bx
-    mov [0x000A], ax
-    pop ds
-
-    ; get memory map in order to be displayed during the kernel loading process;
+    ; get memory map in order to be used by the kernel (the kernel computes the RAM amount from it);
     ; we do it here into stage2 as we can simply use the dedicated BIOS interrupt;
-    ; stores the map entries at 0x1180C (0x1180:0x000C).
-    ; FIXME: the interrupt does not return any value into the buffer for now...
+    ; stores the amount of entries at 0x1180A (0x1180:0x000A)
+    ; and the map entries at 0x1180C (0x1180:0x000C)
     mov bx, 0x1180
     mov es, bx
     mov di, 0x000C
+    mov word [es:0x000A], 0
 
     xor ebx, ebx            ; starting offset of memory to map, should be 0x0 for the first call
 
     .READ_MEMORY_MAP:
 
+        ; the BIOS returns 20 bytes long entries if it does not support ACPI 3.0,
+        ; so the extended attributes (last 4 bytes) are set to 1 (valid entry) before the call
+        mov dword [es:di + 20], 1
+
         mov eax, 0x0000E820     ; function to get the current memory mapping
         mov ecx, 24             ; every entry into the buffer is 24 bytes long
         mov edx, 0x534D4150     ; contains "SMAP" keyword, this value is mandatory
         int 0x15
 
-        jc mem_map_error        ; displays error if memory mapping cannot be handled (cf = 1)
+        jc .READ_MEMORY_MAP_CARRY
 
         cmp eax, 0x534D4150     ; displays error if output EAX is not the expected fixed value (same as input EDX)
         jne mem_map_error
 
+        mov eax, [es:di + 8]    ; entries with a length of 0 are ignored
+        or eax, [es:di + 12]    ; (the length is 64 bits long)
+        jz .NEXT_MEMORY_MAP_ENTRY
+
+        inc word [es:0x000A]
+        add di, 24              ; every entry into the buffer is 24 bytes long, go to the next entry
+
+        cmp word [es:0x000A], 128   ; the kernel reads 128 entries at most
+        je .READ_MEMORY_MAP_END
+
+    .NEXT_MEMORY_MAP_ENTRY:
+
         cmp ebx, 0              ; check if the last descriptor has been returned
         je .READ_MEMORY_MAP_END
 
-        add di, 24              ; every entry into the buffer is 24 bytes long, go to the next entry
         jmp .READ_MEMORY_MAP
 
+    .READ_MEMORY_MAP_CARRY:
+
+        ; some BIOS set the carry flag (cf = 1) instead of ebx = 0 after the last entry,
+        ; displays error if memory mapping cannot be handled at all
+        cmp word [es:0x000A], 0
+        je mem_map_error
+
     .READ_MEMORY_MAP_END:
 
     ; it is mandatory to clear every BIOS interrupt before loading GDT
@@ -249,6 +270,7 @@ start:
 ; - ensure the data segment value is correct
 ; - reset the stack for kernel
 ; - copy tke kernel to its final memory location (0x100000)
+; - enable paging, the kernel is mapped in the higher half (0xC0000000)
 ; - execute the kernel
 ;-----------------------------------------------------------------------------
 
@@ -269,21 +291,64 @@ end:
     mov ss, bx
     mov esp, 0x9FFF0
 
-    ; copy the kernel from 0x8600 to 0x100000 as we can now use 32 bits long addresses
-    mov esi, 0x8600         ; kernel source base address
+    ; copy the kernel from 0x20000 to 0x100000 as we can now use 32 bits long addresses
+    mov esi, 0x20000        ; kernel source base address
     mov edi, 0x100000       ; kernel destination base address
-    mov ecx, 5120           ; movsd copy a double-word from ds:esi to es:edi,
-                            ; the kernel is 10 clusters long, so 40 sectors long, so 20480 bytes long (512 * 4 * 10),
-                            ; (note that the kernel file might be a little bit smaller, but larger than 8 clusters anyway)
-                            ; 20480 bytes is equivalent to 5120 double words (20480 / 4 = 5120),
-                            ; so movsd has to be repeated 5120 times to copy the whole kernel
+    movzx ecx, word [loaded_clusters_amount]
+    shl ecx, 9              ; movsd copy a double-word from ds:esi to es:edi,
+                            ; every loaded cluster is 4 sectors long, so 2048 bytes long (512 * 4),
+                            ; so 512 double words long (2048 / 4 = 512): movsd has to be repeated
+                            ; (clusters amount * 512) times to copy the whole kernel file
+                            ; (the end of the file after the kernel image, such as the symbols,
+                            ; is copied too but never used)
     cld                     ; set DF to 0 (if DF = 0, then movsd increments si and di, otherwise it decrements)
     rep movsd               ; movsd copy one double word from ds:esi to es:edi and add 4 to si and di,
-                            ; we repeat the operation 512 times to copy the kernel
+                            ; we repeat the operation ecx times to copy the kernel
+
+    ; clear the pages directory (0x110000) and the first page table (0x111000),
+    ; 2048 entries of 4 bytes
+    mov edi, 0x110000
+    mov ecx, 2048
+    xor eax, eax
+    rep stosd
+
+    ; the first page table maps the first 4 MBytes (1024 pages of 4096 bytes),
+    ; every entry is the page physical address with the flags present (bit 0) and writable (bit 1)
+    mov edi, 0x111000
+    mov eax, 0x3
+    mov ecx, 1024
+
+    .MAP_PAGE:
+
+        stosd
+        add eax, 0x1000
+        loop .MAP_PAGE
+
+    ; the first page table is used twice:
+    ; by the directory entry 0 (identity mapping of 0x0 - 0x400000,
+    ; required to keep executing this code once paging is enabled),
+    ; and by the directory entry 768 (mapping of 0xC0000000 - 0xC0400000 for the kernel);
+    ; the last directory entry points to the directory itself (recursive mapping)
+    mov dword [0x110000], 0x111003
+    mov dword [0x110000 + 768 * 4], 0x111003
+    mov dword [0x110000 + 1023 * 4], 0x110003
+
+    ; load the pages directory address into cr3 and enable paging (bit 31 of cr0)
+    mov eax, 0x110000
+    mov cr3, eax
+
+    mov eax, cr0
+    or eax, 0x80000000
+    mov cr0, eax
+
+    ; the GDT is now accessed through the higher half mapping
+    lgdt [gdt_higher_half]
 
+    ; the kernel stack is accessed through the higher half mapping too
+    mov esp, 0xC009FFF0
 
-    ; execute the kernel (loaded in 0x100000);
+    ; execute the kernel (loaded in 0x100000, linked at 0xC0100000);
     ; jump 4096 bytes after as the kernel binary is in ELF format,
     ; so the real executable code starts 4096 bytes (0x1000) after
     ; the beginning of the file
-    jmp 0x8:0x101000
+    jmp 0x8:0xC0101000
@@ -1,94 +1,410 @@
-#![feature(lang_items, llvm_asm)]
+#![feature(lang_items, llvm_asm, alloc_error_handler)]
 #![no_std]
 #![no_main]
 
+extern crate alloc;
 extern crate video;
 extern crate hal;
 
+use core::alloc::Layout;
 use core::panic::PanicInfo;
+use core::time::Duration;
 
 use video::{
     print,
+    printb,
     printi32,
-    printi32hex,
     clear_screen,
 };
 
+#[cfg(any(feature = "heap_debug", feature = "memory_test"))]
+use video::printi32hex;
+
 use hal::{
     disable_interrupts,
     enable_interrupts,
+    wait_for_interrupt,
     load_idt,
-    is_intel_cpu,
+    is_supported_cpu,
+    get_cpu_info,
+    CPU_FEATURES,
     initialize_pic,
+    initialize_apic,
     initialize_pit,
     initialize_keyboard,
+    initialize_deferred_work,
+    has_deferred_work,
+    run_deferred_work,
     get_ticks_amount,
+    sleep_ms,
+    Instant,
+    initialize_rtc,
+    read_date_time,
+    initialize_tsc,
+    get_tsc_frequency,
+    initialize_speaker,
+    play_notes,
+    Note,
+    DateTime,
     get_ram_amount,
-    get_memory_map,
     load_pagination,
-    MemoryArea,
+    initialize_frame_allocator,
+    get_memory_statistics,
+    get_owned_frames_amount,
+    MEMORY_OWNERS,
+    initialize_heap,
+    initialize_slab_allocator,
+    initialize_kernel_stacks,
+    switch_to_kernel_stack,
+    initialize_gdt,
+    initialize_page_fault_handler,
+    initialize_copy_on_write,
+    initialize_ata,
+    initialize_swap,
+    KernelHeap,
+    get_irq_vector,
+    get_irq_name,
+    get_exception_name,
+    get_interrupts_amount,
+    get_spurious_irqs_amount,
+    set_scan_code_handler,
+    INTERRUPTS_VECTORS_AMOUNT,
+};
+
+#[cfg(feature = "heap_debug")]
+use hal::{
+    get_heap_allocations_amount,
+    get_heap_allocation,
+};
+
+#[cfg(feature = "memory_test")]
+use hal::{
+    test_memory,
+    get_faulty_ranges_amount,
+    get_faulty_range,
 };
 
+/// The kernel heap, used by the `alloc` crate collections.
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap;
+
 /// Halts the system, defined here as it might be required multiple times.
 fn halt() {
     unsafe { llvm_asm!("hlt"); }
 }
 
-/// Displays the memory mapping on screen (from data loaded by Stage2).
-fn print_memory_map() {
+/// Displays the physical memory statistics and the amount of page frames of every owner.
+///
+/// Args:
+///
+/// `offset` - the character offset of the first line
+fn print_memory_statistics(offset: u32) {
+
+    const CHARACTERS_BETWEEN_LINES: u32 = 80;
+    const VALUE_COLUMN: u32 = 24;
+    const SECOND_COLUMN: u32 = 40;
+    const SECOND_VALUE_COLUMN: u32 = 64;
+    const BYTES_PER_KILOBYTE: u32 = 1024;
+
+    let statistics = get_memory_statistics();
+
+    print(offset, "Usable frames:");
+    printi32(offset + VALUE_COLUMN, statistics.usable_frames);
+    print(offset + SECOND_COLUMN, "Page tables:");
+    printi32(offset + SECOND_VALUE_COLUMN, statistics.page_tables_amount);
+
+    let line = offset + CHARACTERS_BETWEEN_LINES;
+    print(line, "Used frames:");
+    printi32(line + VALUE_COLUMN, statistics.used_frames);
+    print(line + SECOND_COLUMN, "Heap size (KBytes):");
+    printi32(line + SECOND_VALUE_COLUMN, statistics.heap_size / BYTES_PER_KILOBYTE);
+
+    let line = line + CHARACTERS_BETWEEN_LINES;
+    print(line, "Free frames:");
+    printi32(line + VALUE_COLUMN, statistics.free_frames);
+    print(line + SECOND_COLUMN, "Heap allocated (bytes):");
+    printi32(line + SECOND_VALUE_COLUMN, statistics.heap_allocated_bytes);
+
+    /* one empty line between the statistics and the owners table */
+    let mut line = line + CHARACTERS_BETWEEN_LINES * 2;
+    print(line, "Owner");
+    print(line + VALUE_COLUMN, "Frames");
+    print(line + SECOND_COLUMN, "KBytes");
+
+    const FRAME_KILOBYTES_SIZE: u32 = 4;
+
+    for owner in MEMORY_OWNERS.iter() {
+
+        line += CHARACTERS_BETWEEN_LINES;
+
+        let frames_amount = get_owned_frames_amount(*owner);
+        print(line, owner.get_name());
+        printi32(line + VALUE_COLUMN, frames_amount);
+        printi32(line + SECOND_COLUMN, frames_amount * FRAME_KILOBYTES_SIZE);
+    }
+}
 
-    print(480, "Memory map:");
-    print(640, "Base address");
-    print(664, "Area length");
-    print(688, "Area type");
+/// Tests the usable memory before the page frames allocator takes it (memory test mode only),
+/// displays the amount of faulty ranges (they are never allocated).
+#[cfg(feature = "memory_test")]
+fn run_memory_test() {
 
-    const MEMORY_AREA_ITEMS_AMOUNT: usize = 10;
-    let areas: [MemoryArea; MEMORY_AREA_ITEMS_AMOUNT] = get_memory_map();
+    print(400, "Testing the memory...");
 
-    let mut cursor_position: u32 = 720;
-    let mut line_cursor_position: u32 = 720;
+    let faulty_ranges_amount = test_memory();
+    print(1520, "Faulty memory ranges (F2):");
+    printi32(1547, faulty_ranges_amount);
+}
 
-    const CHARACTERS_WIDTH_BETWEEN_COLUMNS: u32 = 24;
+/// Displays the faulty memory ranges found by the memory test (memory test mode only).
+///
+/// Args:
+///
+/// `offset` - the character offset of the first line
+#[cfg(feature = "memory_test")]
+fn print_faulty_ranges(offset: u32) {
 
-    for (index, area) in areas.iter().enumerate() {
+    const CHARACTERS_BETWEEN_LINES: u32 = 80;
+    const END_COLUMN: u32 = 16;
+    const DISPLAYED_RANGES_MAXIMUM_AMOUNT: u32 = 8;
 
-        let base_address = area.get_base_address();
+    print(offset, "Faulty memory ranges:");
+    printi32(offset + 22, get_faulty_ranges_amount());
 
-        /* arbitrary address limit, we ignore every area that starts after 16MBytes;
-           in factm it might be an entry that the BIOS (or even Bochs) added
-           but it can be ignored safely */
-        const BASE_ADDRESS_LIMIT: u32 = 17000000;
+    for index in 0..DISPLAYED_RANGES_MAXIMUM_AMOUNT {
 
-        /* there is no memory area item left if the base address is equal to 0
-           and if some previous iterations have already occured */
-        if index != 0 && base_address == 0 ||
-            base_address > BASE_ADDRESS_LIMIT {
-            break;
-        }
+        let (start, end) = match get_faulty_range(index) {
+            Some(range) => range,
+            None => break,
+        };
+
+        let line = offset + (index + 1) * CHARACTERS_BETWEEN_LINES;
+        printi32hex(line, start);
+        printi32hex(line + END_COLUMN, end);
+    }
+}
+
+/// Displays the memory statistics on a clean screen.
+fn print_memory_report() {
+
+    clear_screen();
+    print(0, "Memory (F2 to refresh):");
+    print_memory_statistics(160);
+
+    /* one empty line after the owners table */
+    #[cfg(feature = "memory_test")]
+    print_faulty_ranges(1280);
+}
 
-        cursor_position = line_cursor_position;
+/// Displays the outstanding allocations of the kernel heap (debug mode only),
+/// from the most recent one, with the address of the code that requested them
+/// (only for `heap_alloc`, 0 for the allocations of the `alloc` crate).
+#[cfg(feature = "heap_debug")]
+fn print_heap_allocations_report() {
+
+    clear_screen();
+    print(0, "Heap allocations (F3 to refresh):");
+    printi32(40, get_heap_allocations_amount());
+    print(80, "Address");
+    print(96, "Size");
+    print(112, "Call site");
+
+    const CHARACTERS_BETWEEN_LINES: u32 = 80;
+    const CHARACTERS_WIDTH_BETWEEN_COLUMNS: u32 = 16;
+    const LINES_AMOUNT: u32 = 23;
+
+    for index in 0..LINES_AMOUNT {
+
+        let allocation = match get_heap_allocation(index) {
+            Some(allocation) => allocation,
+            None => break,
+        };
+
+        let line_cursor_position = 160 + index * CHARACTERS_BETWEEN_LINES;
+        printi32hex(line_cursor_position, allocation.address);
+        printi32(line_cursor_position + CHARACTERS_WIDTH_BETWEEN_COLUMNS, allocation.size);
         printi32hex(
-            cursor_position,
-            base_address
+            line_cursor_position + CHARACTERS_WIDTH_BETWEEN_COLUMNS * 2,
+            allocation.call_site,
         );
+    }
+}
+
+/* two short ascending notes played when the kernel is initialized */
+const BOOT_NOTES: [Note; 3] = [
+    Note { frequency: 523, duration: Duration::from_millis(100) },
+    Note { frequency: 0, duration: Duration::from_millis(30) },
+    Note { frequency: 784, duration: Duration::from_millis(150) },
+];
+
+/// Displays a number on two digits (with a leading zero if required).
+///
+/// Args:
+///
+/// `offset` - the character offset
+/// `value` - the value to display, from 0 to 99
+fn print_two_digits(offset: u32, value: u8) {
+
+    const ASCII_OFFSET: u8 = 48;
+
+    unsafe {
+        printb(0xC00B8000 + offset * 2, value / 10 + ASCII_OFFSET);
+        printb(0xC00B8000 + (offset + 1) * 2, value % 10 + ASCII_OFFSET);
+    }
+}
+
+/// Displays the given date and time in format YYYY-MM-DD HH:MM:SS.
+///
+/// Args:
+///
+/// `offset` - the character offset
+/// `date_time` - the date and time to display
+fn print_date_time(offset: u32, date_time: &DateTime) {
+
+    printi32(offset, date_time.get_year() as u32);
+    print(offset + 4, "-");
+    print_two_digits(offset + 5, date_time.get_month());
+    print(offset + 7, "-");
+    print_two_digits(offset + 8, date_time.get_day());
+
+    print_two_digits(offset + 11, date_time.get_hours());
+    print(offset + 13, ":");
+    print_two_digits(offset + 14, date_time.get_minutes());
+    print(offset + 16, ":");
+    print_two_digits(offset + 17, date_time.get_seconds());
+}
+
+/// Displays the amount of occurrences of every raised interrupt vector
+/// (similar to /proc/interrupts on Linux).
+fn print_interrupts_report() {
+
+    clear_screen();
+    print(0, "Interrupts (F1 to refresh):");
+    print(80, "Vector");
+    print(96, "Amount");
+    print(112, "Name");
+
+    const IRQS_AMOUNT: usize = 16;
+    const EXCEPTIONS_AMOUNT: usize = 32;
+    const LOCAL_APIC_SPURIOUS_VECTOR: usize = 255;
+    const CHARACTERS_BETWEEN_LINES: u32 = 80;
+    const CHARACTERS_WIDTH_BETWEEN_COLUMNS: u32 = 16;
+
+    /* the two last lines are used for the spurious IRQs */
+    const LAST_LINE_POSITION: u32 = 1760;
+
+    let irq_vector_base = get_irq_vector(0) as usize;
+    let mut line_cursor_position: u32 = 160;
+
+    for vector in 0..INTERRUPTS_VECTORS_AMOUNT {
+
+        let amount = get_interrupts_amount(vector);
+        if amount == 0 {
+            continue;
+        }
+
+        if line_cursor_position == LAST_LINE_POSITION {
+            break;
+        }
+
+        let mut cursor_position = line_cursor_position;
+        printi32(cursor_position, vector as u32);
 
         cursor_position += CHARACTERS_WIDTH_BETWEEN_COLUMNS;
-        printi32(
-            cursor_position,
-            area.get_length()
-        );
+        printi32(cursor_position, amount);
 
         cursor_position += CHARACTERS_WIDTH_BETWEEN_COLUMNS;
 
-        const USUABLE: &str = "Usuable";
-        const RESERVED: &str = "Reserved";
+        if vector < EXCEPTIONS_AMOUNT {
+            print(cursor_position, get_exception_name(vector));
+        } else if vector >= irq_vector_base && vector < irq_vector_base + IRQS_AMOUNT {
+            print(cursor_position, "IRQ");
+            printi32(cursor_position + 4, (vector - irq_vector_base) as u32);
+            print(cursor_position + 8, get_irq_name((vector - irq_vector_base) as u8));
+        } else if vector == LOCAL_APIC_SPURIOUS_VECTOR {
+            print(cursor_position, "Local APIC spurious");
+        }
+
+        line_cursor_position += CHARACTERS_BETWEEN_LINES;
+    }
+
+    print(LAST_LINE_POSITION, "Spurious IRQ7:");
+    printi32(LAST_LINE_POSITION + CHARACTERS_WIDTH_BETWEEN_COLUMNS, get_spurious_irqs_amount(7));
+    print(LAST_LINE_POSITION + CHARACTERS_BETWEEN_LINES, "Spurious IRQ15:");
+    printi32(
+        LAST_LINE_POSITION + CHARACTERS_BETWEEN_LINES + CHARACTERS_WIDTH_BETWEEN_COLUMNS,
+        get_spurious_irqs_amount(15),
+    );
+}
+
+/// Displays the CPU identification and the list of its features.
+fn print_cpu_report() {
+
+    clear_screen();
+    print(0, "CPU (F4 to refresh):");
+
+    const VALUE_COLUMN: u32 = 16;
+    const CHARACTERS_BETWEEN_LINES: u32 = 80;
+    const CHARACTERS_WIDTH_BETWEEN_COLUMNS: u32 = 16;
+    const FEATURES_PER_LINE: u32 = 5;
+
+    let info = get_cpu_info();
+
+    print(160, "Vendor:");
+    print(160 + VALUE_COLUMN, info.get_vendor());
+    print(240, "Brand:");
+    print(240 + VALUE_COLUMN, info.get_brand());
+    print(320, "Family:");
+    printi32(320 + VALUE_COLUMN, info.get_family());
+    print(400, "Model:");
+    printi32(400 + VALUE_COLUMN, info.get_model());
+    print(480, "Stepping:");
+    printi32(480 + VALUE_COLUMN, info.get_stepping());
+
+    print(640, "Features:");
+
+    let mut position: u32 = 0;
+
+    for &(feature, name) in CPU_FEATURES.iter() {
+
+        if !info.has_features(feature) {
+            continue;
+        }
+
         print(
-            cursor_position,
-            if area.is_usuable() { USUABLE } else { RESERVED }
+            720 + (position / FEATURES_PER_LINE) * CHARACTERS_BETWEEN_LINES +
+                (position % FEATURES_PER_LINE) * CHARACTERS_WIDTH_BETWEEN_COLUMNS,
+            name,
         );
 
-        const CHARACTERS_BETWEEN_LINES: u32 = 80;
-        line_cursor_position += CHARACTERS_BETWEEN_LINES;
+        position += 1;
+    }
+}
+
+/// Handles the keyboard commands, called for every received scan code.
+///
+/// Args:
+///
+/// `scan_code` - the received scan code
+fn handle_scan_code(scan_code: u8) {
+
+    /* scan codes sent when the F1, F2, F3 and F4 keys are pressed */
+    const F1_PRESSED: u8 = 0x3B;
+    const F2_PRESSED: u8 = 0x3C;
+    const F3_PRESSED: u8 = 0x3D;
+    const F4_PRESSED: u8 = 0x3E;
+
+    if scan_code == F1_PRESSED {
+        print_interrupts_report();
+    } else if scan_code == F2_PRESSED {
+        print_memory_report();
+    } else if scan_code == F3_PRESSED {
+
+        /* the heap allocations are only tracked in debug mode */
+        #[cfg(feature = "heap_debug")]
+        print_heap_allocations_report();
+    } else if scan_code == F4_PRESSED {
+        print_cpu_report();
     }
 }
 
@@ -101,52 +417,144 @@ pub fn _start() -> ! {
 
     load_idt();
 
-    if !is_intel_cpu() {
-        print(160, "CPU type is not supported ! (Intel only)");
+    /* the CPU features are checked, whatever the CPU vendor */
+    if !is_supported_cpu() {
+        print(160, "CPU type is not supported ! (i686 CPU required)");
+        halt();
+    }
+
+    let ram_amount = get_ram_amount();
+
+    print(240, "Usable RAM amount (KBytes):");
+    const BYTES_PER_KILOBYTE: u64 = 1024;
+    printi32(320, (ram_amount / BYTES_PER_KILOBYTE) as u32);
+
+    /* the first MByte and the ACPI tables at the end of the memory are not usable,
+       so a machine with 4 MBytes of RAM has a bit more than 3 MBytes of usable RAM */
+    const MINIMUM_RAM_AMOUNT: u64 = 0x300000;
+    if ram_amount < MINIMUM_RAM_AMOUNT {
+        print(400, "SmallOS requires at least 4 MBytes of RAM !");
+        halt();
+    }
+
+    /* the memory is tested before the page frames allocator takes it,
+       the faulty ranges are reserved by the allocator */
+    #[cfg(feature = "memory_test")]
+    run_memory_test();
+
+    /* the kernel pages directory is finalized first,
+       the APIC initialization maps its registers */
+    initialize_frame_allocator();
+
+    if !load_pagination() {
+        print(400, "Cannot protect the kernel sections !");
+        halt();
+    }
+
+    initialize_page_fault_handler();
+
+    if !initialize_copy_on_write() {
+        print(400, "Cannot initialize the copy-on-write support !");
         halt();
     }
 
-    let ram_amount = unsafe {
-        get_ram_amount()
-    };
+    if !initialize_heap() {
+        print(400, "Cannot initialize the kernel heap !");
+        halt();
+    }
 
-    print(240, "Detected RAM amount (bytes):");
-    printi32(320, ram_amount);
+    initialize_slab_allocator();
 
-    const REQUIRED_RAM_AMOUNT: u32 = 15360000;
-    if ram_amount != REQUIRED_RAM_AMOUNT {
-        print(400, "SmallOS requires 15360 KBytes in RAM exactly !");
+    if !initialize_kernel_stacks() {
+        print(400, "Cannot map the kernel stack !");
         halt();
     }
 
+    initialize_gdt();
+
+    /* the stack set by Stage3 is abandoned, the initialization continues
+       on the kernel stack (a stack overflow raises a double fault) */
+    switch_to_kernel_stack(initialize_kernel);
+}
+
+/// Second part of the kernel initialization, executed on the kernel stack.
+fn initialize_kernel() -> ! {
+
     unsafe { disable_interrupts(); }
-    initialize_pic();
+
+    /* the first 32 IDT indices are used by the CPU exceptions,
+       so the IRQs are plugged right after */
+    const IRQS_VECTOR_BASE: u8 = 0x20;
+    initialize_pic(IRQS_VECTOR_BASE);
+
+    /* the IRQs are routed through the I/O APIC if the CPU has a local APIC,
+       the 8259A PIC initialized just before is kept otherwise */
+    if initialize_apic(IRQS_VECTOR_BASE) {
+        print(400, "Interrupts controller: APIC");
+    } else {
+        print(400, "Interrupts controller: 8259A PIC");
+    }
+
+    initialize_deferred_work();
     initialize_pit();
+    initialize_rtc();
     unsafe { enable_interrupts(); }
 
-    print_memory_map();
+    /* without hard drive, the pageable areas are still mapped on demand
+       but their pages are never swapped */
+    initialize_ata();
+    initialize_swap();
 
-    load_pagination();
+    if initialize_tsc() {
+        const HERTZ_PER_KILOHERTZ: u64 = 1000;
+        print(1920, "TSC frequency (kHz):");
+        printi32(1944, (get_tsc_frequency() / HERTZ_PER_KILOHERTZ) as u32);
+    }
 
-    print(1600, "Current time tick:");
+    initialize_speaker();
+    play_notes(&BOOT_NOTES);
 
-    loop {
+    print_memory_statistics(480);
 
-        let ticks = unsafe { get_ticks_amount() };
+    print(1600, "Current time tick:");
+    print(1760, "Current date:");
 
-        const TICKS_AMOUNT_TO_WAIT: u32 = 5000;
-        if ticks > TICKS_AMOUNT_TO_WAIT {
-            break;
-        }
+    /* displays the ticks amount during 50 seconds,
+       the CPU is halted between two refreshes; the deferred work
+       (timers callbacks such as the boot notes) runs at every refresh */
+    const WAITING_DURATION_MILLISECONDS: u64 = 50000;
+    const REFRESH_PERIOD_MILLISECONDS: u64 = 10;
+    let start = Instant::now();
 
-        printi32(1680, ticks);
+    while start.elapsed() < Duration::from_millis(WAITING_DURATION_MILLISECONDS) {
+
+        printi32(1680, unsafe { get_ticks_amount() });
+        print_date_time(1840, &read_date_time());
+        run_deferred_work();
+        sleep_ms(REFRESH_PERIOD_MILLISECONDS);
     }
 
     clear_screen();
 
     initialize_keyboard();
+    set_scan_code_handler(handle_scan_code);
 
-    loop {}
+    /* idle loop: runs the work deferred by the interrupt routines,
+       then halts the CPU until the next interrupt; the interrupts are disabled
+       during the check, so a work deferred right after it wakes the CPU up */
+    loop {
+
+        run_deferred_work();
+
+        unsafe { disable_interrupts(); }
+
+        if has_deferred_work() {
+            unsafe { enable_interrupts(); }
+            continue;
+        }
+
+        unsafe { wait_for_interrupt(); }
+    }
 }
 
 /// Defines how to unwind the stack allocated objects on panic. This function is required when no standard library is used, but as the kernel is bare-metal for now, we keep things simple and do not take any specific action to unwind the stack on panic.
@@ -160,3 +568,15 @@ pub extern fn panic_fmt(_info: &PanicInfo) -> ! {
 
     loop {}
 }
+
+/// Called when the kernel heap cannot allocate memory (no free page frame anymore),
+/// the system is halted.
+#[alloc_error_handler]
+fn alloc_error(_layout: Layout) -> ! {
+
+    print(160, "Kernel heap out of memory !");
+
+    loop {
+        halt();
+    }
+}
@@ -0,0 +1,554 @@
+//! Local APIC and I/O APIC (Advanced Programmable Interrupt Controller) management
+
+use cpu::{
+    read_msr,
+    write_msr,
+    get_cpu_features,
+    CpuFeatures,
+};
+
+use pic::disable_pic;
+use interrupts::{
+    set_interrupt_handler,
+    InterruptFrame,
+};
+
+use frames::FRAME_BYTES_SIZE;
+
+use paging::{
+    identity_map,
+    PageFlags,
+};
+
+/* kernel global variables (check README.md):
+   1 if the IRQs are routed through the APIC, 0 if they use the 8259A PIC,
+   the physical address of the local APIC registers,
+   the physical address of the I/O APIC registers */
+const APIC_ENABLED_ADDRESS: u32 = 0xC0013010;
+const LOCAL_APIC_BASE_ADDRESS: u32 = 0xC0013014;
+const IO_APIC_BASE_ADDRESS: u32 = 0xC0013018;
+
+/* the IA32_APIC_BASE MSR:
+   bit 8: 1 if the current processor is the bootstrap processor,
+   bit 11: 1 to enable the local APIC, 0 to disable it,
+   bits 12-35: physical base address of the local APIC registers */
+const APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_MSR_ENABLE: u64 = 1 << 11;
+const APIC_BASE_MSR_ADDRESS_MASK: u64 = 0xFFFFF000;
+
+/* local APIC registers offsets from the local APIC base address,
+   every register is 32 bits long and aligned on 16 bytes */
+const LOCAL_APIC_ID_REGISTER: u32 = 0x20;
+const LOCAL_APIC_TASK_PRIORITY_REGISTER: u32 = 0x80;
+const LOCAL_APIC_EOI_REGISTER: u32 = 0xB0;
+const LOCAL_APIC_SPURIOUS_VECTOR_REGISTER: u32 = 0xF0;
+
+/* the local APIC sends its spurious interrupts to the last IDT index */
+const LOCAL_APIC_SPURIOUS_VECTOR: u8 = 0xFF;
+
+/* the I/O APIC registers are not directly accessible:
+   the register index is written into IOREGSEL (base address),
+   then the register value is read or written through IOWIN (base address + 0x10) */
+const IO_APIC_DEFAULT_BASE: u32 = 0xFEC00000;
+const IO_APIC_REGISTER_SELECT: u32 = 0x00;
+const IO_APIC_REGISTER_WINDOW: u32 = 0x10;
+
+/* I/O APIC registers indices:
+   0x01: version register (bits 16-23: index of the last redirection entry),
+   0x10: first redirection entry, every entry uses two registers (low and high 32 bits) */
+const IO_APIC_VERSION_REGISTER: u32 = 0x01;
+const IO_APIC_REDIRECTION_TABLE_REGISTER: u32 = 0x10;
+
+/* the ISA IRQs are connected to the I/O APIC pins with the same number,
+   except the PIT (IRQ0) which is connected to the pin 2 on PC compatible machines
+   (the pin 0 is used by the 8259A PIC output) */
+const PIT_IRQ: u8 = 0;
+const PIT_IO_APIC_PIN: u8 = 2;
+
+/* the IRQ2 is only used to cascade the 8259A PICs,
+   it is not connected to any device */
+const CASCADE_IRQ: u8 = 2;
+
+/* every PIC handles 8 IRQ lines, there are 16 ISA IRQs */
+const ISA_IRQS_AMOUNT: u8 = 16;
+
+/// Delivery mode of one I/O APIC redirection entry.
+#[derive(Copy, Clone, PartialEq)]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    LowestPriority = 0b001,
+    SystemManagement = 0b010,
+    NonMaskable = 0b100,
+    Init = 0b101,
+    External = 0b111,
+}
+
+/// One I/O APIC redirection entry, indicates how the I/O APIC forwards
+/// the interrupts of one of its input pins to the local APIC(s).
+///
+/// The 64 bits entry has the following format:
+/// bits 0-7: IDT index of the interrupt,
+/// bits 8-10: delivery mode,
+/// bit 11: destination mode (0: physical, the destination is an APIC id, 1: logical),
+/// bit 12: delivery status (read only, 1 if the interrupt is waiting to be sent),
+/// bit 13: pin polarity (0: active high, 1: active low),
+/// bit 14: remote IRR (read only, level triggered interrupts only),
+/// bit 15: trigger mode (0: edge, 1: level),
+/// bit 16: mask (1 to ignore the interrupts of this pin),
+/// bits 56-63: destination (local APIC id in physical mode)
+#[derive(Copy, Clone)]
+pub struct RedirectionEntry {
+    vector: u8,
+    delivery_mode: DeliveryMode,
+    logical_destination: bool,
+    active_low: bool,
+    level_triggered: bool,
+    masked: bool,
+    destination: u8,
+}
+
+impl RedirectionEntry {
+
+    /// Constructor of a redirection entry, fixed delivery, edge triggered, active high,
+    /// sent to the given local APIC, unmasked.
+    ///
+    /// Args:
+    ///
+    /// `vector` - the IDT index of the interrupt
+    /// `destination` - the destination local APIC id
+    ///
+    /// Returns:
+    ///
+    /// a new redirection entry
+    pub fn new(vector: u8, destination: u8) -> RedirectionEntry {
+        RedirectionEntry {
+            vector: vector,
+            delivery_mode: DeliveryMode::Fixed,
+            logical_destination: false,
+            active_low: false,
+            level_triggered: false,
+            masked: false,
+            destination: destination,
+        }
+    }
+
+    /// Getter of the IDT index of the interrupt.
+    ///
+    /// Returns:
+    ///
+    /// the IDT index
+    pub fn get_vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// Indicates if the entry is masked.
+    ///
+    /// Returns:
+    ///
+    /// true if the interrupts of the pin are ignored
+    pub fn is_masked(&self) -> bool {
+        self.masked
+    }
+
+    /// Sets the delivery mode.
+    ///
+    /// Args:
+    ///
+    /// `delivery_mode` - the delivery mode
+    pub fn set_delivery_mode(&mut self, delivery_mode: DeliveryMode) {
+        self.delivery_mode = delivery_mode;
+    }
+
+    /// Sets the destination mode.
+    ///
+    /// Args:
+    ///
+    /// `logical` - true for logical destination, false for physical destination (APIC id)
+    pub fn set_logical_destination(&mut self, logical: bool) {
+        self.logical_destination = logical;
+    }
+
+    /// Sets the pin polarity.
+    ///
+    /// Args:
+    ///
+    /// `active_low` - true if the pin is active low, false if active high
+    pub fn set_active_low(&mut self, active_low: bool) {
+        self.active_low = active_low;
+    }
+
+    /// Sets the trigger mode.
+    ///
+    /// Args:
+    ///
+    /// `level_triggered` - true for level triggered, false for edge triggered
+    pub fn set_level_triggered(&mut self, level_triggered: bool) {
+        self.level_triggered = level_triggered;
+    }
+
+    /// Masks or unmasks the entry.
+    ///
+    /// Args:
+    ///
+    /// `masked` - true to ignore the interrupts of the pin
+    pub fn set_masked(&mut self, masked: bool) {
+        self.masked = masked;
+    }
+
+    /// Returns the two 32 bits registers values of the entry.
+    ///
+    /// Returns:
+    ///
+    /// the low register value and the high register value
+    fn to_registers(&self) -> (u32, u32) {
+
+        let mut low = self.vector as u32 | ((self.delivery_mode as u32) << 8);
+
+        if self.logical_destination {
+            low |= 1 << 11;
+        }
+
+        if self.active_low {
+            low |= 1 << 13;
+        }
+
+        if self.level_triggered {
+            low |= 1 << 15;
+        }
+
+        if self.masked {
+            low |= 1 << 16;
+        }
+
+        (low, (self.destination as u32) << 24)
+    }
+
+    /// Creates an entry from the two 32 bits registers values.
+    ///
+    /// Args:
+    ///
+    /// `low` - the low register value
+    /// `high` - the high register value
+    ///
+    /// Returns:
+    ///
+    /// the redirection entry
+    fn from_registers(low: u32, high: u32) -> RedirectionEntry {
+
+        let delivery_mode = match (low >> 8) & 0b111 {
+            0b001 => DeliveryMode::LowestPriority,
+            0b010 => DeliveryMode::SystemManagement,
+            0b100 => DeliveryMode::NonMaskable,
+            0b101 => DeliveryMode::Init,
+            0b111 => DeliveryMode::External,
+            _ => DeliveryMode::Fixed,
+        };
+
+        RedirectionEntry {
+            vector: low as u8,
+            delivery_mode: delivery_mode,
+            logical_destination: low & (1 << 11) != 0,
+            active_low: low & (1 << 13) != 0,
+            level_triggered: low & (1 << 15) != 0,
+            masked: low & (1 << 16) != 0,
+            destination: (high >> 24) as u8,
+        }
+    }
+}
+
+/// Indicates if the CPU has a local APIC.
+///
+/// Returns:
+///
+/// true if the local APIC is available
+pub fn has_local_apic() -> bool {
+    get_cpu_features().contains(CpuFeatures::APIC)
+}
+
+/// Indicates if the IRQs are routed through the I/O APIC instead of the 8259A PIC.
+///
+/// Returns:
+///
+/// true if the APIC is used
+pub fn is_apic_enabled() -> bool {
+    unsafe { *(APIC_ENABLED_ADDRESS as *const u8) == 1 }
+}
+
+/// Returns the physical address of the local APIC registers.
+///
+/// Returns:
+///
+/// the local APIC base address
+pub fn get_local_apic_base() -> u32 {
+    unsafe { *(LOCAL_APIC_BASE_ADDRESS as *const u32) }
+}
+
+/// Returns the physical address of the I/O APIC registers.
+///
+/// Returns:
+///
+/// the I/O APIC base address
+pub fn get_io_apic_base() -> u32 {
+    unsafe { *(IO_APIC_BASE_ADDRESS as *const u32) }
+}
+
+/// Reads one local APIC register.
+///
+/// Args:
+///
+/// `register` - the register offset
+///
+/// Returns:
+///
+/// the register value
+fn read_local_apic(register: u32) -> u32 {
+    unsafe { *((get_local_apic_base() + register) as *const u32) }
+}
+
+/// Writes one local APIC register.
+///
+/// Args:
+///
+/// `register` - the register offset
+/// `value` - the value to write
+fn write_local_apic(register: u32, value: u32) {
+    unsafe { *((get_local_apic_base() + register) as *mut u32) = value; }
+}
+
+/// Reads one I/O APIC register.
+///
+/// Args:
+///
+/// `register` - the register index
+///
+/// Returns:
+///
+/// the register value
+fn read_io_apic(register: u32) -> u32 {
+
+    let base = get_io_apic_base();
+
+    unsafe {
+        *((base + IO_APIC_REGISTER_SELECT) as *mut u32) = register;
+        *((base + IO_APIC_REGISTER_WINDOW) as *const u32)
+    }
+}
+
+/// Writes one I/O APIC register.
+///
+/// Args:
+///
+/// `register` - the register index
+/// `value` - the value to write
+fn write_io_apic(register: u32, value: u32) {
+
+    let base = get_io_apic_base();
+
+    unsafe {
+        *((base + IO_APIC_REGISTER_SELECT) as *mut u32) = register;
+        *((base + IO_APIC_REGISTER_WINDOW) as *mut u32) = value;
+    }
+}
+
+/// Returns the local APIC id of the current processor.
+///
+/// Returns:
+///
+/// the local APIC id (bits 24-31 of the ID register)
+pub fn get_local_apic_id() -> u8 {
+    (read_local_apic(LOCAL_APIC_ID_REGISTER) >> 24) as u8
+}
+
+/// Returns the amount of redirection entries (input pins) of the I/O APIC.
+///
+/// Returns:
+///
+/// the redirection entries amount
+pub fn get_redirection_entries_amount() -> u8 {
+    ((read_io_apic(IO_APIC_VERSION_REGISTER) >> 16) as u8) + 1
+}
+
+/// Returns the redirection entry of the given I/O APIC pin.
+///
+/// Args:
+///
+/// `pin` - the I/O APIC input pin
+///
+/// Returns:
+///
+/// the redirection entry
+pub fn get_redirection_entry(pin: u8) -> RedirectionEntry {
+
+    let register = IO_APIC_REDIRECTION_TABLE_REGISTER + (pin as u32) * 2;
+
+    RedirectionEntry::from_registers(
+        read_io_apic(register),
+        read_io_apic(register + 1),
+    )
+}
+
+/// Sets the redirection entry of the given I/O APIC pin.
+///
+/// Args:
+///
+/// `pin` - the I/O APIC input pin
+/// `entry` - the redirection entry
+pub fn set_redirection_entry(pin: u8, entry: &RedirectionEntry) {
+
+    let register = IO_APIC_REDIRECTION_TABLE_REGISTER + (pin as u32) * 2;
+    let (low, high) = entry.to_registers();
+
+    /* the entry is masked while it is modified, in order to prevent
+       an interrupt to be sent with a partially written entry */
+    write_io_apic(register, low | (1 << 16));
+    write_io_apic(register + 1, high);
+    write_io_apic(register, low);
+}
+
+/// Returns the I/O APIC pin connected to the given ISA IRQ.
+///
+/// Args:
+///
+/// `irq` - the ISA IRQ line (from 0 to 15)
+///
+/// Returns:
+///
+/// the I/O APIC pin
+fn get_irq_pin(irq: u8) -> u8 {
+
+    if irq == PIT_IRQ {
+        return PIT_IO_APIC_PIN;
+    }
+
+    irq
+}
+
+/// Masks the given ISA IRQ into the I/O APIC.
+///
+/// Args:
+///
+/// `irq` - the ISA IRQ line (from 0 to 15)
+pub fn mask_io_apic_irq(irq: u8) {
+
+    let pin = get_irq_pin(irq);
+    let mut entry = get_redirection_entry(pin);
+    entry.set_masked(true);
+    set_redirection_entry(pin, &entry);
+}
+
+/// Unmasks the given ISA IRQ into the I/O APIC.
+///
+/// Args:
+///
+/// `irq` - the ISA IRQ line (from 0 to 15)
+pub fn unmask_io_apic_irq(irq: u8) {
+
+    let pin = get_irq_pin(irq);
+    let mut entry = get_redirection_entry(pin);
+    entry.set_masked(false);
+    set_redirection_entry(pin, &entry);
+}
+
+/// Signals the end of the current interrupt to the local APIC.
+pub fn send_local_apic_eoi() {
+    write_local_apic(LOCAL_APIC_EOI_REGISTER, 0);
+}
+
+/// Interrupt routine of the local APIC spurious interrupts.
+/// Spurious interrupts must not be acknowledged.
+///
+/// Args:
+///
+/// `_frame` - the interrupted context
+fn handle_local_apic_spurious_interrupt(_frame: &mut InterruptFrame) {
+}
+
+/// Switches the IRQs handling from the 8259A PIC to the local APIC and the I/O APIC.
+/// The PIC must have been initialized before (with the same vector base),
+/// so any interrupt it raised before being disabled goes to a known IDT index.
+/// Every ISA IRQ is redirected to the IDT index `vector_base + irq` and is masked,
+/// every driver has to unmask the line it uses (`unmask_irq` forwards to the I/O APIC).
+///
+/// Args:
+///
+/// `vector_base` - the IDT index of IRQ0
+///
+/// Returns:
+///
+/// false if the CPU has no local APIC (the PIC is kept), true otherwise
+pub fn initialize_apic(vector_base: u8) -> bool {
+
+    unsafe { *(APIC_ENABLED_ADDRESS as *mut u8) = 0; }
+
+    if !has_local_apic() {
+        return false;
+    }
+
+    /* get the local APIC registers base address (usually 0xFEE00000) */
+    let apic_base_msr = unsafe { read_msr(APIC_BASE_MSR) };
+
+    unsafe {
+        *(LOCAL_APIC_BASE_ADDRESS as *mut u32) =
+            (apic_base_msr & APIC_BASE_MSR_ADDRESS_MASK) as u32;
+
+        /* the I/O APIC address should be read from the ACPI MADT table,
+           we use the default address of PC compatible machines for now */
+        *(IO_APIC_BASE_ADDRESS as *mut u32) = IO_APIC_DEFAULT_BASE;
+    }
+
+    /* the registers are at the end of the physical addresses space, they are identity mapped;
+       device registers must never be read from the cache, nor executed */
+    let device_page_flags = PageFlags::WRITABLE |
+        PageFlags::WRITE_THROUGH |
+        PageFlags::CACHE_DISABLED |
+        PageFlags::NO_EXECUTE;
+
+    if !identity_map(get_local_apic_base(), FRAME_BYTES_SIZE, device_page_flags) ||
+        !identity_map(get_io_apic_base(), FRAME_BYTES_SIZE, device_page_flags) {
+        return false;
+    }
+
+    disable_pic();
+
+    /* enable the local APIC through the MSR (it might have been disabled by the BIOS) */
+    unsafe { write_msr(APIC_BASE_MSR, apic_base_msr | APIC_BASE_MSR_ENABLE); }
+
+    set_interrupt_handler(
+        LOCAL_APIC_SPURIOUS_VECTOR as usize,
+        handle_local_apic_spurious_interrupt,
+    );
+
+    /* software enable the local APIC (bit 8 of the spurious interrupt vector register)
+       and set the IDT index of its spurious interrupts (bits 0-7) */
+    const LOCAL_APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+    write_local_apic(
+        LOCAL_APIC_SPURIOUS_VECTOR_REGISTER,
+        LOCAL_APIC_SOFTWARE_ENABLE | LOCAL_APIC_SPURIOUS_VECTOR as u32,
+    );
+
+    /* accept every interrupt whatever its priority */
+    write_local_apic(LOCAL_APIC_TASK_PRIORITY_REGISTER, 0);
+
+    /* mask every I/O APIC pin, then create the entries of the ISA IRQs */
+    let destination = get_local_apic_id();
+    for pin in 0..get_redirection_entries_amount() {
+        let mut entry = RedirectionEntry::new(0, destination);
+        entry.set_masked(true);
+        set_redirection_entry(pin, &entry);
+    }
+
+    for irq in 0..ISA_IRQS_AMOUNT {
+
+        if irq == CASCADE_IRQ {
+            continue;
+        }
+
+        let mut entry = RedirectionEntry::new(vector_base + irq, destination);
+        entry.set_masked(true);
+        set_redirection_entry(get_irq_pin(irq), &entry);
+    }
+
+    unsafe { *(APIC_ENABLED_ADDRESS as *mut u8) = 1; }
+
+    true
+}
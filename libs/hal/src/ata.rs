@@ -0,0 +1,316 @@
+//! ATA hard drive driver (PIO mode, primary bus, master drive)
+//!
+//! The sectors are transferred through the data port of the controller, one word after the other,
+//! the driver polls the status register instead of waiting for the IRQ14.
+//! The sectors are addressed with 28 bits LBA (128 GBytes at most).
+
+use io::{
+    inb,
+    outb,
+    inw,
+    outw,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   the amount of sectors of the drive, 0 if there is no drive */
+const ATA_SECTORS_AMOUNT_ADDRESS: u32 = 0xC00130B8;
+
+pub const SECTOR_BYTES_SIZE: u32 = 512;
+
+/* the primary bus I/O ports:
+   data (16 bits), error (read), sectors count, LBA bits 0-7, LBA bits 8-15, LBA bits 16-23,
+   drive selection and LBA bits 24-27, status (read) or command (write) */
+const DATA_PORT: u16 = 0x1F0;
+const ERROR_PORT: u16 = 0x1F1;
+const SECTORS_COUNT_PORT: u16 = 0x1F2;
+const LBA_LOW_PORT: u16 = 0x1F3;
+const LBA_MIDDLE_PORT: u16 = 0x1F4;
+const LBA_HIGH_PORT: u16 = 0x1F5;
+const DRIVE_PORT: u16 = 0x1F6;
+const COMMAND_PORT: u16 = 0x1F7;
+const STATUS_PORT: u16 = 0x1F7;
+
+/* the primary bus device control register:
+   bit 1: disables the IRQ14 when set */
+const DEVICE_CONTROL_PORT: u16 = 0x3F6;
+const DISABLE_INTERRUPTS: u8 = 0b00000010;
+
+/* the drive selection register:
+   bits 5 and 7 are always set, bit 6 for LBA addressing, bit 4 is 0 for the master drive */
+const MASTER_DRIVE_LBA: u8 = 0b11100000;
+
+/* the status register:
+   bit 0: error, bit 3: the drive is ready to transfer data, bit 7: the drive is busy */
+const STATUS_ERROR: u8 = 0b00000001;
+const STATUS_DATA_REQUEST: u8 = 0b00001000;
+const STATUS_BUSY: u8 = 0b10000000;
+
+const READ_SECTORS_COMMAND: u8 = 0x20;
+const WRITE_SECTORS_COMMAND: u8 = 0x30;
+const CACHE_FLUSH_COMMAND: u8 = 0xE7;
+const IDENTIFY_COMMAND: u8 = 0xEC;
+
+/* 256 words per sector */
+const SECTOR_WORDS_AMOUNT: u32 = SECTOR_BYTES_SIZE / 2;
+
+/* 28 bits LBA */
+const MAXIMUM_SECTORS_AMOUNT: u32 = 1 << 28;
+
+/// Waits 400 ns, so the drive has time to update its status after a command
+/// (reading the status register takes about 100 ns).
+fn wait_status() {
+
+    for _ in 0..4 {
+        unsafe { inb(STATUS_PORT); }
+    }
+}
+
+/// Waits until the drive is not busy anymore.
+///
+/// Returns:
+///
+/// the status register value
+fn wait_not_busy() -> u8 {
+
+    loop {
+        let status = unsafe { inb(STATUS_PORT) };
+
+        if status & STATUS_BUSY == 0 {
+            return status;
+        }
+    }
+}
+
+/// Waits until the drive is ready to transfer one sector.
+///
+/// Returns:
+///
+/// false if the drive reports an error
+fn wait_data_request() -> bool {
+
+    loop {
+        let status = wait_not_busy();
+
+        if status & STATUS_ERROR != 0 {
+            return false;
+        }
+
+        if status & STATUS_DATA_REQUEST != 0 {
+            return true;
+        }
+    }
+}
+
+/// Selects the master drive and sends the sectors address and amount, then the command.
+///
+/// Args:
+///
+/// `lba` - the first sector address
+/// `amount` - the amount of sectors (0 for 256 sectors)
+/// `command` - the command
+fn send_command(lba: u32, amount: u8, command: u8) {
+
+    unsafe {
+        outb(DRIVE_PORT, MASTER_DRIVE_LBA | ((lba >> 24) & 0x0F) as u8);
+        wait_status();
+
+        outb(SECTORS_COUNT_PORT, amount);
+        outb(LBA_LOW_PORT, lba as u8);
+        outb(LBA_MIDDLE_PORT, (lba >> 8) as u8);
+        outb(LBA_HIGH_PORT, (lba >> 16) as u8);
+        outb(COMMAND_PORT, command);
+    }
+
+    wait_status();
+}
+
+/// Detects the master drive of the primary bus (IDENTIFY command),
+/// disables its interrupts (the driver polls the status register).
+///
+/// Returns:
+///
+/// false if there is no ATA drive
+pub fn initialize_ata() -> bool {
+
+    unsafe {
+        *(ATA_SECTORS_AMOUNT_ADDRESS as *mut u32) = 0;
+        outb(DEVICE_CONTROL_PORT, DISABLE_INTERRUPTS);
+    }
+
+    /* a floating bus (no drive at all) returns 0xFF */
+    if unsafe { inb(STATUS_PORT) } == 0xFF {
+        return false;
+    }
+
+    send_command(0, 0, IDENTIFY_COMMAND);
+
+    /* the status is 0 if the drive does not exist */
+    if unsafe { inb(STATUS_PORT) } == 0 {
+        return false;
+    }
+
+    wait_not_busy();
+
+    /* ATAPI and SATA drives set the LBA middle and high registers */
+    if unsafe { inb(LBA_MIDDLE_PORT) != 0 || inb(LBA_HIGH_PORT) != 0 } {
+        return false;
+    }
+
+    if !wait_data_request() {
+        return false;
+    }
+
+    /* the IDENTIFY data is 256 words long,
+       the words 60 and 61 contain the amount of sectors addressable with 28 bits LBA */
+    const LBA_SECTORS_LOW_WORD: u32 = 60;
+    const LBA_SECTORS_HIGH_WORD: u32 = 61;
+
+    let mut sectors_amount: u32 = 0;
+
+    for index in 0..SECTOR_WORDS_AMOUNT {
+
+        let word = unsafe { inw(DATA_PORT) } as u32;
+
+        if index == LBA_SECTORS_LOW_WORD {
+            sectors_amount |= word;
+        } else if index == LBA_SECTORS_HIGH_WORD {
+            sectors_amount |= word << 16;
+        }
+    }
+
+    unsafe { *(ATA_SECTORS_AMOUNT_ADDRESS as *mut u32) = sectors_amount; }
+
+    sectors_amount != 0
+}
+
+/// Returns the amount of sectors of the drive.
+///
+/// Returns:
+///
+/// the sectors amount, 0 if there is no drive
+pub fn get_ata_sectors_amount() -> u32 {
+    unsafe { *(ATA_SECTORS_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Checks the sectors range of a transfer.
+///
+/// Args:
+///
+/// `lba` - the first sector address
+/// `amount` - the amount of sectors
+///
+/// Returns:
+///
+/// true if every sector is on the drive
+fn is_valid_range(lba: u32, amount: u8) -> bool {
+
+    let end = lba as u64 + amount as u64;
+    amount != 0 && end <= get_ata_sectors_amount() as u64 && end <= MAXIMUM_SECTORS_AMOUNT as u64
+}
+
+/// Reads sectors from the drive.
+///
+/// Args:
+///
+/// `lba` - the first sector address
+/// `amount` - the amount of sectors (from 1 to 255)
+/// `address` - the address of the buffer (at least `amount * 512` bytes long)
+///
+/// Returns:
+///
+/// false if the sectors are not on the drive or if the drive reports an error
+pub fn read_sectors(lba: u32, amount: u8, address: u32) -> bool {
+
+    if !is_valid_range(lba, amount) {
+        return false;
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    send_command(lba, amount, READ_SECTORS_COMMAND);
+
+    let mut result = true;
+
+    for sector in 0..amount as u32 {
+
+        if !wait_data_request() {
+            result = false;
+            break;
+        }
+
+        let sector_address = address + sector * SECTOR_BYTES_SIZE;
+
+        for word in 0..SECTOR_WORDS_AMOUNT {
+            unsafe { *((sector_address + word * 2) as *mut u16) = inw(DATA_PORT); }
+        }
+
+        wait_status();
+    }
+
+    unsafe { restore_interrupts(flags); }
+
+    result
+}
+
+/// Writes sectors on the drive, the drive cache is flushed after the transfer.
+///
+/// Args:
+///
+/// `lba` - the first sector address
+/// `amount` - the amount of sectors (from 1 to 255)
+/// `address` - the address of the data (at least `amount * 512` bytes long)
+///
+/// Returns:
+///
+/// false if the sectors are not on the drive or if the drive reports an error
+pub fn write_sectors(lba: u32, amount: u8, address: u32) -> bool {
+
+    if !is_valid_range(lba, amount) {
+        return false;
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    send_command(lba, amount, WRITE_SECTORS_COMMAND);
+
+    let mut result = true;
+
+    for sector in 0..amount as u32 {
+
+        if !wait_data_request() {
+            result = false;
+            break;
+        }
+
+        let sector_address = address + sector * SECTOR_BYTES_SIZE;
+
+        for word in 0..SECTOR_WORDS_AMOUNT {
+            unsafe { outw(DATA_PORT, *((sector_address + word * 2) as *const u16)); }
+        }
+
+        wait_status();
+    }
+
+    if result {
+        send_command(0, 0, CACHE_FLUSH_COMMAND);
+        result = wait_not_busy() & STATUS_ERROR == 0;
+    }
+
+    unsafe { restore_interrupts(flags); }
+
+    result
+}
+
+/// Returns the error register of the drive (after a failed transfer).
+///
+/// Returns:
+///
+/// the error register value
+pub fn get_ata_error() -> u8 {
+    unsafe { inb(ERROR_PORT) }
+}
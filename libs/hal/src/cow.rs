@@ -0,0 +1,324 @@
+//! Copy-on-write pages sharing
+//!
+//! A shared page is mapped read only with the copy-on-write marker (`PageFlags::COPY_ON_WRITE`)
+//! at every virtual address sharing it. The amount of mappings of every shared page frame
+//! is kept into a references table (one byte per page frame).
+//!
+//! When a shared page is written, the CPU raises a page fault (the page is read only):
+//! the page frame is copied into a new page frame, mapped as writable at the faulting address.
+//! The last mapping of a shared frame becomes writable again without copy.
+
+use frames::{
+    alloc_frame,
+    free_frame,
+    get_frames_limit,
+    FRAME_BYTES_SIZE,
+};
+
+use paging::{
+    map,
+    unmap,
+    translate,
+    get_page_flags,
+    set_page_flags,
+    PageFlags,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* the references table, one byte per page frame (0 if the frame is not shared),
+   mapped from 0xF0000000 (1 MByte at most) */
+const FRAMES_REFERENCES_ADDRESS: u32 = 0xF0000000;
+
+/* virtual page used to copy a shared page into a new page frame */
+const COPY_PAGE_ADDRESS: u32 = 0xEFFFF000;
+
+/* a page frame is shared by 255 mappings at most */
+const MAXIMUM_REFERENCES: u8 = 255;
+
+/// Returns the amount of mappings of the given page frame.
+///
+/// Args:
+///
+/// `frame_address` - the page frame physical address
+///
+/// Returns:
+///
+/// the references counter (0 if the frame is not shared)
+fn get_references(frame_address: u32) -> &'static mut u8 {
+
+    let address = FRAMES_REFERENCES_ADDRESS + frame_address / FRAME_BYTES_SIZE;
+    unsafe { &mut *(address as *mut u8) }
+}
+
+/// Indicates if the given page frame has a references counter.
+///
+/// Args:
+///
+/// `frame_address` - the page frame physical address
+///
+/// Returns:
+///
+/// true if the frame is before the last usable frame
+fn is_referenced_frame(frame_address: u32) -> bool {
+    frame_address / FRAME_BYTES_SIZE < get_frames_limit()
+}
+
+/// Initializes the copy-on-write support: maps the references table.
+/// Must be called after the page frames allocator and paging initialization
+/// (`load_pagination` enables the write protection, so the kernel writes into shared pages
+/// fault too).
+///
+/// Returns:
+///
+/// false if the references table cannot be mapped
+pub fn initialize_copy_on_write() -> bool {
+
+    let table_length = get_frames_limit();
+    let mut address = FRAMES_REFERENCES_ADDRESS;
+
+    while address < FRAMES_REFERENCES_ADDRESS + table_length {
+
+        let frame = match alloc_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        if !map(address, frame, PageFlags::WRITABLE | PageFlags::NO_EXECUTE) {
+            free_frame(frame);
+            return false;
+        }
+
+        for offset in 0..FRAME_BYTES_SIZE / 4 {
+            unsafe { *((address + offset * 4) as *mut u32) = 0; }
+        }
+
+        address += FRAME_BYTES_SIZE;
+    }
+
+    true
+}
+
+/// Shares the page of the source address with the destination address:
+/// both addresses point to the same page frame, the page is copied on the first write
+/// (a read only page stays read only and is never copied).
+///
+/// Args:
+///
+/// `source` - the virtual address of the mapped page to share
+/// `destination` - the virtual address where the page is shared (an existing mapping
+/// is freed with `free_page` first)
+///
+/// Returns:
+///
+/// false if the source page is not mapped, if both addresses are into the same page,
+/// if the frame is shared too many times or if the destination page table cannot be allocated
+pub fn share_page(source: u32, destination: u32) -> bool {
+
+    if source & !(FRAME_BYTES_SIZE - 1) == destination & !(FRAME_BYTES_SIZE - 1) {
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    /* only the usable page frames can be shared (not the devices registers) */
+    let (frame, page_flags) = match (translate(source), get_page_flags(source)) {
+        (Some(address), Some(flags)) if is_referenced_frame(address) => {
+            (address & !(FRAME_BYTES_SIZE - 1), flags)
+        },
+        _ => {
+            unsafe { restore_interrupts(interrupts); }
+            return false;
+        }
+    };
+
+    let references = get_references(frame);
+
+    if *references == MAXIMUM_REFERENCES {
+        unsafe { restore_interrupts(interrupts); }
+        return false;
+    }
+
+    /* the writable pages become read only, the copy-on-write marker is kept
+       if the page is already shared */
+    let shared_flags = if page_flags.contains(PageFlags::WRITABLE) ||
+        page_flags.contains(PageFlags::COPY_ON_WRITE) {
+        (page_flags & !PageFlags::WRITABLE) | PageFlags::COPY_ON_WRITE
+    } else {
+        page_flags
+    };
+
+    /* the frame previously mapped at the destination is released (or its references
+       amount is decremented), so it does not leak */
+    free_page(destination);
+
+    if !map(destination, frame, shared_flags) {
+        unsafe { restore_interrupts(interrupts); }
+        return false;
+    }
+
+    set_page_flags(source, shared_flags);
+
+    /* a frame that is not shared yet is mapped once */
+    *references = if *references == 0 { 2 } else { *references + 1 };
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/// Shares every page of an area with another area (check `share_page`).
+///
+/// Args:
+///
+/// `source` - the virtual address of the first page to share
+/// `destination` - the virtual address where the first page is shared
+/// `length` - the area length in bytes
+///
+/// Returns:
+///
+/// false if one page cannot be shared (the previous pages stay shared)
+pub fn share_pages(source: u32, destination: u32, length: u32) -> bool {
+
+    let mut offset: u32 = 0;
+
+    while offset < length {
+
+        if !share_page(source + offset, destination + offset) {
+            return false;
+        }
+
+        offset += FRAME_BYTES_SIZE;
+    }
+
+    true
+}
+
+/// Copies the shared page at the given address into a new page frame (if the page frame
+/// is still shared), called by the page fault handler when a read only page is written.
+///
+/// Args:
+///
+/// `address` - the written virtual address
+///
+/// Returns:
+///
+/// false if the page is not a copy-on-write page or if there is no free page frame
+pub fn handle_copy_on_write(address: u32) -> bool {
+
+    let page = address & !(FRAME_BYTES_SIZE - 1);
+
+    let flags = match get_page_flags(page) {
+        Some(flags) => flags,
+        None => return false,
+    };
+
+    if !flags.contains(PageFlags::COPY_ON_WRITE) {
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let frame = translate(page).unwrap_or(0);
+    let references = get_references(frame);
+    let writable_flags = (flags & !PageFlags::COPY_ON_WRITE) | PageFlags::WRITABLE;
+
+    /* the last mapping of the frame gets the frame back */
+    if *references <= 1 {
+        *references = 0;
+        set_page_flags(page, writable_flags);
+
+        unsafe { restore_interrupts(interrupts); }
+        return true;
+    }
+
+    let copy = match alloc_frame() {
+        Some(copy) => copy,
+        None => {
+            unsafe { restore_interrupts(interrupts); }
+            return false;
+        }
+    };
+
+    if !map(COPY_PAGE_ADDRESS, copy, PageFlags::WRITABLE | PageFlags::NO_EXECUTE) {
+        free_frame(copy);
+        unsafe { restore_interrupts(interrupts); }
+        return false;
+    }
+
+    for offset in 0..FRAME_BYTES_SIZE / 4 {
+        unsafe {
+            *((COPY_PAGE_ADDRESS + offset * 4) as *mut u32) = *((page + offset * 4) as *const u32);
+        }
+    }
+
+    unmap(COPY_PAGE_ADDRESS);
+    map(page, copy, writable_flags);
+
+    *references -= 1;
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/// Unmaps the page at the given address and frees its page frame,
+/// unless the page frame is still shared with another mapping
+/// (the frames out of the usable memory are never freed).
+///
+/// Args:
+///
+/// `address` - the virtual address of the page
+///
+/// Returns:
+///
+/// false if the page is not mapped
+pub fn free_page(address: u32) -> bool {
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let frame = match unmap(address) {
+        Some(frame) => frame,
+        None => {
+            unsafe { restore_interrupts(interrupts); }
+            return false;
+        }
+    };
+
+    if is_referenced_frame(frame) {
+
+        let references = get_references(frame);
+
+        if *references > 1 {
+            *references -= 1;
+        } else {
+            *references = 0;
+            free_frame(frame);
+        }
+    }
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/// Returns the amount of mappings of the given page frame.
+///
+/// Args:
+///
+/// `frame_address` - the page frame physical address
+///
+/// Returns:
+///
+/// the amount of mappings sharing the frame, 0 if the frame is not shared
+pub fn get_frame_references(frame_address: u32) -> u8 {
+
+    if !is_referenced_frame(frame_address) {
+        return 0;
+    }
+
+    *get_references(frame_address)
+}
@@ -0,0 +1,544 @@
+//! CPU identification and Model Specific Registers access routines
+//!
+//! The CPU is identified with the cpuid instruction: vendor string (leaf 0), version and features
+//! (leaf 1), extended features (leaf 0x80000001), brand string (leaves 0x80000002 to 0x80000004)
+//! and power management features (leaf 0x80000007). The kernel checks the features it uses,
+//! never the vendor, so it runs on every x86 CPU (Intel, AMD, VIA...) with the required features.
+
+use core::ops::BitOr;
+
+use core::str;
+
+/// Executes the cpuid instruction for the given leaf.
+///
+/// Args:
+///
+/// `leaf` - the requested information (value of eax when calling cpuid)
+///
+/// Returns:
+///
+/// the values of eax, ebx, ecx and edx returned by cpuid
+pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+
+    let mut eax: u32 = 0;
+    let mut ebx: u32 = 0;
+    let mut ecx: u32 = 0;
+    let mut edx: u32 = 0;
+
+    /* some leaves have sub-leaves selected by ecx,
+       we always request the first one */
+    unsafe {
+        llvm_asm!("cpuid"
+            : "={eax}" (eax), "={ebx}" (ebx), "={ecx}" (ecx), "={edx}" (edx)
+            : "{eax}" (leaf), "{ecx}" (0)
+            :: "intel"
+        );
+    }
+
+    (eax, ebx, ecx, edx)
+}
+
+/// Reads the given MSR (Model Specific Register).
+///
+/// Args:
+///
+/// `register` - the MSR index
+///
+/// Returns:
+///
+/// the 64 bits value of the register
+pub unsafe fn read_msr(register: u32) -> u64 {
+
+    let mut low: u32 = 0;
+    let mut high: u32 = 0;
+
+    /* rdmsr reads the register selected by ecx
+       into edx (high bits) and eax (low bits) */
+    llvm_asm!("rdmsr"
+        : "={eax}" (low), "={edx}" (high)
+        : "{ecx}" (register)
+        :: "intel"
+    );
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// Writes into the given MSR (Model Specific Register).
+///
+/// Args:
+///
+/// `register` - the MSR index
+/// `value` - the 64 bits value to write
+pub unsafe fn write_msr(register: u32, value: u64) {
+
+    llvm_asm!("wrmsr"
+        :: "{ecx}" (register), "{eax}" (value as u32), "{edx}" ((value >> 32) as u32)
+        :: "intel"
+    );
+}
+
+/* cpuid leaves:
+   highest basic leaf and vendor string, version and features,
+   highest extended leaf, extended features, brand string (three leaves),
+   advanced power management features */
+const VENDOR_LEAF: u32 = 0;
+const FEATURES_LEAF: u32 = 1;
+const HIGHEST_EXTENDED_LEAF: u32 = 0x80000000;
+const EXTENDED_FEATURES_LEAF: u32 = 0x80000001;
+const BRAND_STRING_FIRST_LEAF: u32 = 0x80000002;
+const BRAND_STRING_LAST_LEAF: u32 = 0x80000004;
+const POWER_MANAGEMENT_LEAF: u32 = 0x80000007;
+
+const VENDOR_BYTES_SIZE: usize = 12;
+const BRAND_BYTES_SIZE: usize = 48;
+
+/// Features of the CPU.
+///
+/// bits 0-31: the edx register of the cpuid leaf 1 (same bits),
+/// bits 32-39: the ecx register of the cpuid leaf 1 (SSE3 to AVX, x2APIC, hypervisor),
+/// bits 40-43: the edx register of the cpuid leaf 0x80000001 (NX, 1 GByte pages, rdtscp, long mode),
+/// bit 44: the edx register of the cpuid leaf 0x80000007 (invariant TSC)
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct CpuFeatures(u64);
+
+impl CpuFeatures {
+
+    pub const FPU: CpuFeatures = CpuFeatures(1 << 0);
+    pub const VME: CpuFeatures = CpuFeatures(1 << 1);
+    pub const DE: CpuFeatures = CpuFeatures(1 << 2);
+    pub const PSE: CpuFeatures = CpuFeatures(1 << 3);
+    pub const TSC: CpuFeatures = CpuFeatures(1 << 4);
+    pub const MSR: CpuFeatures = CpuFeatures(1 << 5);
+    pub const PAE: CpuFeatures = CpuFeatures(1 << 6);
+    pub const MCE: CpuFeatures = CpuFeatures(1 << 7);
+    pub const CX8: CpuFeatures = CpuFeatures(1 << 8);
+    pub const APIC: CpuFeatures = CpuFeatures(1 << 9);
+    pub const SEP: CpuFeatures = CpuFeatures(1 << 11);
+    pub const MTRR: CpuFeatures = CpuFeatures(1 << 12);
+    pub const PGE: CpuFeatures = CpuFeatures(1 << 13);
+    pub const MCA: CpuFeatures = CpuFeatures(1 << 14);
+    pub const CMOV: CpuFeatures = CpuFeatures(1 << 15);
+    pub const PAT: CpuFeatures = CpuFeatures(1 << 16);
+    pub const PSE36: CpuFeatures = CpuFeatures(1 << 17);
+    pub const CLFLUSH: CpuFeatures = CpuFeatures(1 << 19);
+    pub const MMX: CpuFeatures = CpuFeatures(1 << 23);
+    pub const FXSR: CpuFeatures = CpuFeatures(1 << 24);
+    pub const SSE: CpuFeatures = CpuFeatures(1 << 25);
+    pub const SSE2: CpuFeatures = CpuFeatures(1 << 26);
+    pub const HTT: CpuFeatures = CpuFeatures(1 << 28);
+    pub const SSE3: CpuFeatures = CpuFeatures(1 << 32);
+    pub const SSSE3: CpuFeatures = CpuFeatures(1 << 33);
+    pub const SSE4_1: CpuFeatures = CpuFeatures(1 << 34);
+    pub const SSE4_2: CpuFeatures = CpuFeatures(1 << 35);
+    pub const POPCNT: CpuFeatures = CpuFeatures(1 << 36);
+    pub const AVX: CpuFeatures = CpuFeatures(1 << 37);
+    pub const X2APIC: CpuFeatures = CpuFeatures(1 << 38);
+    pub const HYPERVISOR: CpuFeatures = CpuFeatures(1 << 39);
+    pub const NX: CpuFeatures = CpuFeatures(1 << 40);
+    pub const PAGE_1GB: CpuFeatures = CpuFeatures(1 << 41);
+    pub const RDTSCP: CpuFeatures = CpuFeatures(1 << 42);
+    pub const LONG_MODE: CpuFeatures = CpuFeatures(1 << 43);
+    pub const INVARIANT_TSC: CpuFeatures = CpuFeatures(1 << 44);
+
+    /// Returns features without any feature set.
+    ///
+    /// Returns:
+    ///
+    /// the empty features
+    pub fn empty() -> CpuFeatures {
+        CpuFeatures(0)
+    }
+
+    /// Returns the features bits.
+    ///
+    /// Returns:
+    ///
+    /// the bits
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Indicates if every given feature is available.
+    ///
+    /// Args:
+    ///
+    /// `features` - the features to check
+    ///
+    /// Returns:
+    ///
+    /// true if every feature is available
+    pub fn contains(&self, features: CpuFeatures) -> bool {
+        self.0 & features.0 == features.0
+    }
+}
+
+impl BitOr for CpuFeatures {
+
+    type Output = CpuFeatures;
+
+    fn bitor(self, features: CpuFeatures) -> CpuFeatures {
+        CpuFeatures(self.0 | features.0)
+    }
+}
+
+/* every named feature, in display order */
+pub const CPU_FEATURES: [(CpuFeatures, &'static str); 36] = [
+    (CpuFeatures::FPU, "FPU"),
+    (CpuFeatures::VME, "VME"),
+    (CpuFeatures::DE, "DE"),
+    (CpuFeatures::PSE, "PSE"),
+    (CpuFeatures::TSC, "TSC"),
+    (CpuFeatures::MSR, "MSR"),
+    (CpuFeatures::PAE, "PAE"),
+    (CpuFeatures::MCE, "MCE"),
+    (CpuFeatures::CX8, "CX8"),
+    (CpuFeatures::APIC, "APIC"),
+    (CpuFeatures::SEP, "SEP"),
+    (CpuFeatures::MTRR, "MTRR"),
+    (CpuFeatures::PGE, "PGE"),
+    (CpuFeatures::MCA, "MCA"),
+    (CpuFeatures::CMOV, "CMOV"),
+    (CpuFeatures::PAT, "PAT"),
+    (CpuFeatures::PSE36, "PSE36"),
+    (CpuFeatures::CLFLUSH, "CLFLUSH"),
+    (CpuFeatures::MMX, "MMX"),
+    (CpuFeatures::FXSR, "FXSR"),
+    (CpuFeatures::SSE, "SSE"),
+    (CpuFeatures::SSE2, "SSE2"),
+    (CpuFeatures::HTT, "HTT"),
+    (CpuFeatures::SSE3, "SSE3"),
+    (CpuFeatures::SSSE3, "SSSE3"),
+    (CpuFeatures::SSE4_1, "SSE4.1"),
+    (CpuFeatures::SSE4_2, "SSE4.2"),
+    (CpuFeatures::POPCNT, "POPCNT"),
+    (CpuFeatures::AVX, "AVX"),
+    (CpuFeatures::X2APIC, "x2APIC"),
+    (CpuFeatures::HYPERVISOR, "Hypervisor"),
+    (CpuFeatures::NX, "NX"),
+    (CpuFeatures::PAGE_1GB, "1GB pages"),
+    (CpuFeatures::RDTSCP, "RDTSCP"),
+    (CpuFeatures::LONG_MODE, "Long mode"),
+    (CpuFeatures::INVARIANT_TSC, "Invariant TSC"),
+];
+
+/* the features of the ecx register of the leaf 1 (register bit, feature) */
+const FEATURES_LEAF_ECX_FEATURES: [(u32, CpuFeatures); 8] = [
+    (0, CpuFeatures::SSE3),
+    (9, CpuFeatures::SSSE3),
+    (19, CpuFeatures::SSE4_1),
+    (20, CpuFeatures::SSE4_2),
+    (21, CpuFeatures::X2APIC),
+    (23, CpuFeatures::POPCNT),
+    (28, CpuFeatures::AVX),
+    (31, CpuFeatures::HYPERVISOR),
+];
+
+/* the features of the edx register of the leaf 0x80000001 (register bit, feature) */
+const EXTENDED_FEATURES_LEAF_EDX_FEATURES: [(u32, CpuFeatures); 4] = [
+    (20, CpuFeatures::NX),
+    (26, CpuFeatures::PAGE_1GB),
+    (27, CpuFeatures::RDTSCP),
+    (29, CpuFeatures::LONG_MODE),
+];
+
+/* the features of the edx register of the leaf 0x80000007 (register bit, feature) */
+const POWER_MANAGEMENT_LEAF_EDX_FEATURES: [(u32, CpuFeatures); 1] = [
+    (8, CpuFeatures::INVARIANT_TSC),
+];
+
+/* the kernel is compiled for i686 processors: the compiler might use the x87 FPU
+   and the conditional moves (cmov) */
+const REQUIRED_CPU_FEATURES: CpuFeatures = CpuFeatures(
+    CpuFeatures::FPU.0 | CpuFeatures::CMOV.0
+);
+
+/// Returns the features of a register from a table of register bits.
+///
+/// Args:
+///
+/// `register` - the register value returned by cpuid
+/// `table` - the register bit of every feature
+///
+/// Returns:
+///
+/// the available features of the table
+fn get_register_features(register: u32, table: &[(u32, CpuFeatures)]) -> CpuFeatures {
+
+    table
+        .iter()
+        .filter(|&&(bit, _)| register & (1 << bit) != 0)
+        .fold(CpuFeatures::empty(), |features, &(_, feature)| features | feature)
+}
+
+/// Indicates if the cpuid instruction is supported: the bit 21 (ID) of EFLAGS
+/// can be modified only if the CPU supports cpuid (every CPU since the late 486).
+///
+/// Returns:
+///
+/// true if cpuid is supported
+fn has_cpuid() -> bool {
+
+    const EFLAGS_ID: u32 = 1 << 21;
+
+    let mut changed_flags: u32 = 0;
+
+    /* the ID flag is inverted, the new EFLAGS value is read back,
+       then the original EFLAGS value is restored */
+    unsafe {
+        llvm_asm!("
+            pushfd
+            pop eax
+            mov ecx, eax
+            xor eax, 0x200000
+            push eax
+            popfd
+            pushfd
+            pop eax
+            push ecx
+            popfd
+            xor eax, ecx
+            " : "={eax}" (changed_flags) :: "ecx", "memory" : "intel", "volatile"
+        );
+    }
+
+    changed_flags & EFLAGS_ID != 0
+}
+
+/// Returns the highest extended leaf of cpuid.
+///
+/// Returns:
+///
+/// the highest extended leaf, 0 if there is no extended leaf
+fn get_highest_extended_leaf() -> u32 {
+
+    let (highest_leaf, _, _, _) = cpuid(HIGHEST_EXTENDED_LEAF);
+
+    /* CPUs without extended leaves return the data of the highest basic leaf */
+    if highest_leaf < HIGHEST_EXTENDED_LEAF {
+        return 0;
+    }
+
+    highest_leaf
+}
+
+/// Returns the features of the CPU (from the leaves 1, 0x80000001 and 0x80000007).
+///
+/// Returns:
+///
+/// the available features
+pub fn get_cpu_features() -> CpuFeatures {
+
+    let (highest_leaf, _, _, _) = cpuid(VENDOR_LEAF);
+    if highest_leaf < FEATURES_LEAF {
+        return CpuFeatures::empty();
+    }
+
+    let (_, _, ecx, edx) = cpuid(FEATURES_LEAF);
+    let mut features = CpuFeatures(edx as u64) |
+        get_register_features(ecx, &FEATURES_LEAF_ECX_FEATURES);
+
+    let highest_extended_leaf = get_highest_extended_leaf();
+
+    if highest_extended_leaf >= EXTENDED_FEATURES_LEAF {
+        let (_, _, _, edx) = cpuid(EXTENDED_FEATURES_LEAF);
+        features = features | get_register_features(edx, &EXTENDED_FEATURES_LEAF_EDX_FEATURES);
+    }
+
+    if highest_extended_leaf >= POWER_MANAGEMENT_LEAF {
+        let (_, _, _, edx) = cpuid(POWER_MANAGEMENT_LEAF);
+        features = features | get_register_features(edx, &POWER_MANAGEMENT_LEAF_EDX_FEATURES);
+    }
+
+    features
+}
+
+/// Indicates if the CPU can run the kernel: cpuid must be supported and the CPU must have
+/// the features of the i686 processors used by the compiled code, whatever its vendor.
+///
+/// Returns:
+///
+/// true if the CPU is supported
+pub fn is_supported_cpu() -> bool {
+    has_cpuid() && get_cpu_features().contains(REQUIRED_CPU_FEATURES)
+}
+
+/// Copies the bytes of a register returned by cpuid (little endian) into a string buffer.
+///
+/// Args:
+///
+/// `buffer` - the string buffer
+/// `offset` - the offset of the register bytes into the buffer
+/// `register` - the register value
+fn copy_register_bytes(buffer: &mut [u8], offset: usize, register: u32) {
+
+    for index in 0..4 {
+        buffer[offset + index] = (register >> (index * 8)) as u8;
+    }
+}
+
+/// Returns a string from the bytes returned by cpuid,
+/// without the null characters and the spaces at the beginning and at the end.
+///
+/// Args:
+///
+/// `bytes` - the string bytes
+///
+/// Returns:
+///
+/// the string, empty if the bytes are not a valid string
+fn get_trimmed_string(bytes: &[u8]) -> &str {
+
+    match str::from_utf8(bytes) {
+        Ok(string) => string.trim_matches(|character| character == '\0' || character == ' '),
+        Err(_) => "",
+    }
+}
+
+/// The identification of the CPU.
+#[derive(Copy, Clone)]
+pub struct CpuInfo {
+    vendor: [u8; VENDOR_BYTES_SIZE],
+    brand: [u8; BRAND_BYTES_SIZE],
+    family: u32,
+    model: u32,
+    stepping: u32,
+    features: CpuFeatures,
+}
+
+impl CpuInfo {
+
+    /// Returns the vendor string ("GenuineIntel", "AuthenticAMD"...).
+    ///
+    /// Returns:
+    ///
+    /// the vendor string
+    pub fn get_vendor(&self) -> &str {
+        get_trimmed_string(&self.vendor)
+    }
+
+    /// Returns the brand string ("Intel(R) Core(TM) i7..." for instance).
+    ///
+    /// Returns:
+    ///
+    /// the brand string, empty if the CPU does not provide it
+    pub fn get_brand(&self) -> &str {
+        get_trimmed_string(&self.brand)
+    }
+
+    /// Returns the family of the CPU (extended family included).
+    ///
+    /// Returns:
+    ///
+    /// the family
+    pub fn get_family(&self) -> u32 {
+        self.family
+    }
+
+    /// Returns the model of the CPU (extended model included).
+    ///
+    /// Returns:
+    ///
+    /// the model
+    pub fn get_model(&self) -> u32 {
+        self.model
+    }
+
+    /// Returns the stepping (revision) of the CPU.
+    ///
+    /// Returns:
+    ///
+    /// the stepping
+    pub fn get_stepping(&self) -> u32 {
+        self.stepping
+    }
+
+    /// Returns the features of the CPU.
+    ///
+    /// Returns:
+    ///
+    /// the available features
+    pub fn get_features(&self) -> CpuFeatures {
+        self.features
+    }
+
+    /// Indicates if the CPU has every given feature.
+    ///
+    /// Args:
+    ///
+    /// `features` - the features to check
+    ///
+    /// Returns:
+    ///
+    /// true if every feature is available
+    pub fn has_features(&self, features: CpuFeatures) -> bool {
+        self.features.contains(features)
+    }
+}
+
+/// Returns the identification of the CPU, must be called only if the CPU is supported
+/// (check `is_supported_cpu`).
+///
+/// Returns:
+///
+/// the vendor, brand, version and features of the CPU
+pub fn get_cpu_info() -> CpuInfo {
+
+    let mut info = CpuInfo {
+        vendor: [0; VENDOR_BYTES_SIZE],
+        brand: [0; BRAND_BYTES_SIZE],
+        family: 0,
+        model: 0,
+        stepping: 0,
+        features: get_cpu_features(),
+    };
+
+    /* the vendor string is stored into ebx, edx and ecx (in this order) */
+    let (highest_leaf, ebx, ecx, edx) = cpuid(VENDOR_LEAF);
+    copy_register_bytes(&mut info.vendor, 0, ebx);
+    copy_register_bytes(&mut info.vendor, 4, edx);
+    copy_register_bytes(&mut info.vendor, 8, ecx);
+
+    /* the version is stored into eax:
+       bits 0-3: stepping, bits 4-7: model, bits 8-11: family,
+       bits 16-19: extended model, bits 20-27: extended family;
+       the extended family is added to the family 15 only,
+       the extended model is the high part of the model for the families 6 and 15 */
+    if highest_leaf >= FEATURES_LEAF {
+
+        let (version, _, _, _) = cpuid(FEATURES_LEAF);
+
+        const EXTENDED_FAMILY_BASE: u32 = 15;
+        const EXTENDED_MODEL_FAMILY: u32 = 6;
+
+        let family = (version >> 8) & 0xF;
+        let model = (version >> 4) & 0xF;
+
+        info.stepping = version & 0xF;
+        info.family = family;
+        info.model = model;
+
+        if family == EXTENDED_FAMILY_BASE {
+            info.family += (version >> 20) & 0xFF;
+        }
+
+        if family == EXTENDED_FAMILY_BASE || family == EXTENDED_MODEL_FAMILY {
+            info.model |= ((version >> 16) & 0xF) << 4;
+        }
+    }
+
+    /* the brand string is 48 bytes long, 16 bytes per leaf (eax, ebx, ecx and edx) */
+    if get_highest_extended_leaf() >= BRAND_STRING_LAST_LEAF {
+
+        for leaf in BRAND_STRING_FIRST_LEAF..BRAND_STRING_LAST_LEAF + 1 {
+
+            let (eax, ebx, ecx, edx) = cpuid(leaf);
+            let offset = ((leaf - BRAND_STRING_FIRST_LEAF) * 16) as usize;
+
+            for (index, &register) in [eax, ebx, ecx, edx].iter().enumerate() {
+                copy_register_bytes(&mut info.brand, offset + index * 4, register);
+            }
+        }
+    }
+
+    info
+}
@@ -0,0 +1,203 @@
+//! Deferred interrupt work (bottom halves)
+//!
+//! Interrupt routines only do the minimum in interrupt context
+//! and enqueue the remaining work, executed later with interrupts enabled
+//! (from the kernel boot wait loop, then from the kernel idle loop).
+//!
+//! The queue is a bounded lock-free ring: every slot has a sequence number
+//! indicating if it is free for the producer of a given position or ready
+//! for the consumer. Producers (interrupt routines, that can be nested)
+//! reserve a position with an atomic compare and swap, so an interrupt routine
+//! interrupted while enqueuing never blocks the nested one.
+//! There is only one consumer (`run_deferred_work`).
+
+use core::mem;
+use core::sync::atomic::{
+    AtomicU32,
+    Ordering,
+};
+
+/* kernel global variables (check README.md):
+   position of the next item to run (consumer),
+   position of the next item to enqueue (producers),
+   amount of items dropped because the queue was full,
+   the queue slots */
+const DEFERRED_WORK_HEAD_ADDRESS: u32 = 0xC0013100;
+const DEFERRED_WORK_TAIL_ADDRESS: u32 = 0xC0013104;
+const DEFERRED_WORK_DROPPED_ADDRESS: u32 = 0xC0013108;
+const DEFERRED_WORK_SLOTS_ADDRESS: u32 = 0xC0013110;
+
+/* the amount of slots must be a power of 2,
+   so a position is converted into a slot index with a simple mask */
+const DEFERRED_WORK_SLOTS_AMOUNT: u32 = 64;
+
+/// A deferred work function, called with the argument given when it was enqueued.
+pub type DeferredWork = fn(u32);
+
+/// One slot of the deferred work queue.
+#[repr(C)]
+struct DeferredWorkSlot {
+
+    /* equal to the position if the slot is free for the producer of this position,
+       equal to the position + 1 if the item is ready for the consumer */
+    sequence: AtomicU32,
+
+    /* address of the function to call */
+    work: u32,
+
+    argument: u32,
+}
+
+/// Returns the slot of the given position.
+///
+/// Args:
+///
+/// `position` - the position into the queue
+///
+/// Returns:
+///
+/// the slot
+fn get_slot(position: u32) -> &'static mut DeferredWorkSlot {
+
+    let index = position & (DEFERRED_WORK_SLOTS_AMOUNT - 1);
+    let address = DEFERRED_WORK_SLOTS_ADDRESS +
+        index * mem::size_of::<DeferredWorkSlot>() as u32;
+
+    unsafe { &mut *(address as *mut DeferredWorkSlot) }
+}
+
+/// Returns one atomic variable of the queue.
+///
+/// Args:
+///
+/// `address` - the variable address
+///
+/// Returns:
+///
+/// the atomic variable
+fn get_atomic(address: u32) -> &'static AtomicU32 {
+    unsafe { &*(address as *const AtomicU32) }
+}
+
+/// Initializes the deferred work queue, must be called before enabling interrupts.
+pub fn initialize_deferred_work() {
+
+    get_atomic(DEFERRED_WORK_HEAD_ADDRESS).store(0, Ordering::SeqCst);
+    get_atomic(DEFERRED_WORK_TAIL_ADDRESS).store(0, Ordering::SeqCst);
+    get_atomic(DEFERRED_WORK_DROPPED_ADDRESS).store(0, Ordering::SeqCst);
+
+    for position in 0..DEFERRED_WORK_SLOTS_AMOUNT {
+        get_slot(position).sequence.store(position, Ordering::SeqCst);
+    }
+}
+
+/// Enqueues a work to run later with interrupts enabled.
+/// Can be called from any interrupt routine (even nested ones).
+///
+/// Args:
+///
+/// `work` - the function to call
+/// `argument` - the argument given to the function
+///
+/// Returns:
+///
+/// false if the queue is full (the work is dropped and counted)
+pub fn defer_work(work: DeferredWork, argument: u32) -> bool {
+
+    let tail = get_atomic(DEFERRED_WORK_TAIL_ADDRESS);
+    let mut position = tail.load(Ordering::Relaxed);
+
+    loop {
+
+        let slot = get_slot(position);
+        let sequence = slot.sequence.load(Ordering::Acquire);
+
+        if sequence == position {
+
+            /* the slot is free, reserve it, another producer
+               might have reserved it in the meantime */
+            match tail.compare_exchange(
+                position,
+                position.wrapping_add(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    slot.work = work as u32;
+                    slot.argument = argument;
+                    slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                    return true;
+                },
+                Err(current) => position = current,
+            }
+
+        } else if (sequence.wrapping_sub(position) as i32) < 0 {
+
+            /* the slot still contains the item enqueued one lap before */
+            get_atomic(DEFERRED_WORK_DROPPED_ADDRESS).fetch_add(1, Ordering::Relaxed);
+            return false;
+
+        } else {
+            position = tail.load(Ordering::Relaxed);
+        }
+    }
+}
+
+/// Indicates if some deferred work is ready to run.
+///
+/// Returns:
+///
+/// true if at least one work is ready
+pub fn has_deferred_work() -> bool {
+
+    let position = get_atomic(DEFERRED_WORK_HEAD_ADDRESS).load(Ordering::Relaxed);
+    get_slot(position).sequence.load(Ordering::Acquire) == position.wrapping_add(1)
+}
+
+/// Runs every ready deferred work, in the order they were enqueued.
+/// Must be called with interrupts enabled, from one single execution flow
+/// (the kernel boot wait loop, then the kernel idle loop).
+///
+/// Returns:
+///
+/// the amount of executed works
+pub fn run_deferred_work() -> u32 {
+
+    let head = get_atomic(DEFERRED_WORK_HEAD_ADDRESS);
+    let mut executed: u32 = 0;
+
+    loop {
+
+        let position = head.load(Ordering::Relaxed);
+        let slot = get_slot(position);
+
+        /* stops if the next item is not ready, even if items after it are ready
+           (the producer of this item has been interrupted while enqueuing) */
+        if slot.sequence.load(Ordering::Acquire) != position.wrapping_add(1) {
+            return executed;
+        }
+
+        let work: DeferredWork = unsafe { mem::transmute(slot.work as usize) };
+        let argument = slot.argument;
+
+        /* the slot is released before running the work,
+           so the work itself can enqueue new items */
+        slot.sequence.store(
+            position.wrapping_add(DEFERRED_WORK_SLOTS_AMOUNT),
+            Ordering::Release,
+        );
+        head.store(position.wrapping_add(1), Ordering::Relaxed);
+
+        work(argument);
+        executed += 1;
+    }
+}
+
+/// Returns the amount of works dropped because the queue was full.
+///
+/// Returns:
+///
+/// the amount of dropped works
+pub fn get_dropped_deferred_work_amount() -> u32 {
+    get_atomic(DEFERRED_WORK_DROPPED_ADDRESS).load(Ordering::Relaxed)
+}
@@ -0,0 +1,539 @@
+//! Physical page frames allocator
+//!
+//! Every page frame (4096 bytes) of the physical addresses space is represented
+//! by one bit of a bitmap (1 if the frame is used or not usable, 0 if it is free).
+//! The bitmap covers the whole 32 bits physical addresses space (1048576 frames),
+//! so it is 131072 bytes long.
+//!
+//! The bitmap is built from the usable memory areas of the memory map,
+//! the frames used by the kernel structures (IDT, kernel image, pages tables...)
+//! are marked as used, as well as the faulty ranges found by the boot memory test.
+//! The reserved areas and the frames out of the usable areas (below the last usable frame)
+//! are also recorded as frames ranges, so their frames are never freed
+//! (even the frames that were already allocated when the area has been reserved).
+
+use {
+    get_memory_map,
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+#[cfg(feature = "memory_test")]
+use memory_test::{
+    get_faulty_ranges_amount,
+    get_faulty_range,
+};
+
+/* kernel global variables (check README.md):
+   the frames bitmap,
+   the amount of usable frames,
+   the amount of free frames,
+   the index of the frame where the next search starts,
+   the index of the frame following the last usable frame,
+   the amount of reserved frames ranges,
+   the reserved frames ranges (first frame and frame following the last one) */
+const FRAMES_BITMAP_ADDRESS: u32 = 0xC0040000;
+const USABLE_FRAMES_AMOUNT_ADDRESS: u32 = 0xC0013090;
+const FREE_FRAMES_AMOUNT_ADDRESS: u32 = 0xC0013094;
+const NEXT_FRAME_ADDRESS: u32 = 0xC0013098;
+const FRAMES_LIMIT_ADDRESS: u32 = 0xC001309C;
+const RESERVED_RANGES_AMOUNT_ADDRESS: u32 = 0xC00130E4;
+const RESERVED_RANGES_ADDRESS: u32 = 0xC0013800;
+
+const RESERVED_RANGES_MAX_AMOUNT: u32 = 64;
+
+pub const FRAME_BYTES_SIZE: u32 = 4096;
+
+/* amount of frames of the 32 bits physical addresses space */
+const FRAMES_AMOUNT: u32 = 1048576;
+
+/* every bitmap word contains the bits of 32 frames */
+const FRAMES_PER_WORD: u32 = 32;
+
+/* physical areas that must never be allocated (start address, end address excluded):
+   - IVT, BIOS data, boot sector, stage2, root directory, FAT, IDT and kernel global variables,
+   - kernel timers and frames bitmap,
+   - kernel stack,
+   - video memory, video BIOS and BIOS,
+   - kernel image, pages directory and pages tables */
+pub const RESERVED_AREAS: [(u32, u32); 5] = [
+    (0x0, 0x15000),
+    (0x20000, 0x60000),
+    (0x60000, 0xA0000),
+    (0xA0000, 0x100000),
+    (0x100000, 0x113000),
+];
+
+/// Returns the bitmap word containing the bit of the given frame, and the bit mask of the frame.
+///
+/// Args:
+///
+/// `frame` - the frame index
+///
+/// Returns:
+///
+/// the bitmap word and the frame bit mask
+fn get_frame_bit(frame: u32) -> (&'static mut u32, u32) {
+
+    let address = FRAMES_BITMAP_ADDRESS + (frame / FRAMES_PER_WORD) * 4;
+    let mask = 1 << (frame % FRAMES_PER_WORD);
+
+    unsafe { (&mut *(address as *mut u32), mask) }
+}
+
+/// Indicates if the given frame is used (or not usable).
+///
+/// Args:
+///
+/// `frame` - the frame index
+///
+/// Returns:
+///
+/// true if the frame is used
+fn is_used(frame: u32) -> bool {
+
+    let (word, mask) = get_frame_bit(frame);
+    *word & mask != 0
+}
+
+/// Marks the given frame as used or free, updates the free frames amount.
+///
+/// Args:
+///
+/// `frame` - the frame index
+/// `used` - true to mark the frame as used
+fn set_used(frame: u32, used: bool) {
+
+    let (word, mask) = get_frame_bit(frame);
+
+    if (*word & mask != 0) == used {
+        return;
+    }
+
+    unsafe {
+        if used {
+            *word |= mask;
+            *(FREE_FRAMES_AMOUNT_ADDRESS as *mut u32) -= 1;
+        } else {
+            *word &= !mask;
+            *(FREE_FRAMES_AMOUNT_ADDRESS as *mut u32) += 1;
+        }
+    }
+}
+
+/// Returns the reserved frames range at the given index.
+///
+/// Args:
+///
+/// `index` - the range index
+///
+/// Returns:
+///
+/// the first frame of the range and the frame following the last one
+fn read_reserved_range(index: u32) -> (u32, u32) {
+
+    let address = RESERVED_RANGES_ADDRESS + index * 8;
+    unsafe { (*(address as *const u32), *((address + 4) as *const u32)) }
+}
+
+/// Updates the reserved frames range at the given index.
+///
+/// Args:
+///
+/// `index` - the range index
+/// `first_frame` - the first frame of the range
+/// `last_frame` - the frame following the last frame of the range
+fn write_reserved_range(index: u32, first_frame: u32, last_frame: u32) {
+
+    let address = RESERVED_RANGES_ADDRESS + index * 8;
+
+    unsafe {
+        *(address as *mut u32) = first_frame;
+        *((address + 4) as *mut u32) = last_frame;
+    }
+}
+
+/// Returns the amount of reserved frames ranges.
+///
+/// Returns:
+///
+/// the reserved ranges amount
+fn get_reserved_ranges_amount() -> u32 {
+    unsafe { *(RESERVED_RANGES_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Adds a frames range to the reserved ranges, merges it with an overlapping or adjacent range.
+///
+/// Args:
+///
+/// `first_frame` - the first frame of the range
+/// `last_frame` - the frame following the last frame of the range
+///
+/// Returns:
+///
+/// false if every range is used (the range is not added)
+fn add_reserved_range(first_frame: u32, last_frame: u32) -> bool {
+
+    let amount = get_reserved_ranges_amount();
+
+    for index in 0..amount {
+
+        let (range_first, range_last) = read_reserved_range(index);
+
+        if first_frame <= range_last && last_frame >= range_first {
+            write_reserved_range(
+                index,
+                if first_frame < range_first { first_frame } else { range_first },
+                if last_frame > range_last { last_frame } else { range_last },
+            );
+            return true;
+        }
+    }
+
+    if amount == RESERVED_RANGES_MAX_AMOUNT {
+        return false;
+    }
+
+    write_reserved_range(amount, first_frame, last_frame);
+    unsafe { *(RESERVED_RANGES_AMOUNT_ADDRESS as *mut u32) = amount + 1; }
+
+    true
+}
+
+/// Indicates if one of the given frames is into a reserved range.
+///
+/// Args:
+///
+/// `first_frame` - the first frame
+/// `last_frame` - the frame following the last frame
+///
+/// Returns:
+///
+/// true if at least one frame is reserved
+fn is_reserved(first_frame: u32, last_frame: u32) -> bool {
+
+    (0..get_reserved_ranges_amount()).any(|index| {
+        let (range_first, range_last) = read_reserved_range(index);
+        first_frame < range_last && last_frame > range_first
+    })
+}
+
+/// Returns the index of the frame following the last usable frame.
+///
+/// Returns:
+///
+/// the frames limit
+pub fn get_frames_limit() -> u32 {
+    unsafe { *(FRAMES_LIMIT_ADDRESS as *const u32) }
+}
+
+/// Initializes the frames bitmap from the usable memory areas of the memory map.
+/// Must be called before any page table is allocated (`load_pagination` uses the allocator).
+pub fn initialize_frame_allocator() {
+
+    const BITMAP_WORDS_AMOUNT: u32 = FRAMES_AMOUNT / FRAMES_PER_WORD;
+
+    for index in 0..BITMAP_WORDS_AMOUNT {
+        unsafe { *((FRAMES_BITMAP_ADDRESS + index * 4) as *mut u32) = 0xFFFFFFFF; }
+    }
+
+    unsafe {
+        *(USABLE_FRAMES_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(FREE_FRAMES_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(NEXT_FRAME_ADDRESS as *mut u32) = 0;
+        *(FRAMES_LIMIT_ADDRESS as *mut u32) = 0;
+        *(RESERVED_RANGES_AMOUNT_ADDRESS as *mut u32) = 0;
+    }
+
+    /* the frame following the last usable frame of the previous usable areas
+       (the memory map areas are sorted by base address) */
+    let mut usable_end: u32 = 0;
+
+    for area in get_memory_map().get_areas().iter() {
+
+        if !area.is_usuable() {
+            continue;
+        }
+
+        /* only the frames entirely into the area are usable,
+           the frames after the 32 bits addresses space are ignored */
+        let first_frame = (area.get_base_address() + FRAME_BYTES_SIZE as u64 - 1) /
+            FRAME_BYTES_SIZE as u64;
+        let last_frame = area.get_end_address() / FRAME_BYTES_SIZE as u64;
+
+        if first_frame >= FRAMES_AMOUNT as u64 {
+            continue;
+        }
+
+        let last_frame = if last_frame > FRAMES_AMOUNT as u64 {
+            FRAMES_AMOUNT
+        } else {
+            last_frame as u32
+        };
+
+        let first_frame = first_frame as u32;
+        if first_frame >= last_frame {
+            continue;
+        }
+
+        /* the frames between two usable areas (ACPI, devices memory, holes...) are used
+           into the bitmap, they are recorded as reserved so they can never be freed */
+        if first_frame > usable_end {
+            add_reserved_range(usable_end, first_frame);
+        }
+
+        usable_end = last_frame;
+
+        for frame in first_frame..last_frame {
+
+            if !is_used(frame) {
+                continue;
+            }
+
+            set_used(frame, false);
+            unsafe { *(USABLE_FRAMES_AMOUNT_ADDRESS as *mut u32) += 1; }
+        }
+
+        if last_frame > get_frames_limit() {
+            unsafe { *(FRAMES_LIMIT_ADDRESS as *mut u32) = last_frame; }
+        }
+    }
+
+    for &(start, end) in RESERVED_AREAS.iter() {
+        reserve_frames(start, end - start);
+    }
+
+    /* the faulty memory is never allocated */
+    #[cfg(feature = "memory_test")]
+    for index in 0..get_faulty_ranges_amount() {
+
+        if let Some((start, end)) = get_faulty_range(index) {
+            reserve_frames(start, end - start);
+        }
+    }
+}
+
+/// Marks the frames of the given physical area as used forever
+/// (the free frames are not counted as usable anymore). The frames already allocated
+/// stay allocated, but they are reserved: `free_frame` refuses them.
+///
+/// Args:
+///
+/// `address` - the area start physical address
+/// `length` - the area length in bytes
+///
+/// Returns:
+///
+/// false if there is no reserved range available (no frame is reserved in that case)
+pub fn reserve_frames(address: u32, length: u32) -> bool {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    /* every frame partially into the area is reserved */
+    let first_frame = address / FRAME_BYTES_SIZE;
+    let last_frame = ((address as u64 + length as u64 + FRAME_BYTES_SIZE as u64 - 1) /
+        FRAME_BYTES_SIZE as u64) as u32;
+
+    if !add_reserved_range(first_frame, last_frame) {
+        unsafe { restore_interrupts(flags); }
+        return false;
+    }
+
+    for frame in first_frame..last_frame {
+
+        if frame >= get_frames_limit() {
+            break;
+        }
+
+        if is_used(frame) {
+            continue;
+        }
+
+        set_used(frame, true);
+        unsafe { *(USABLE_FRAMES_AMOUNT_ADDRESS as *mut u32) -= 1; }
+    }
+
+    unsafe { restore_interrupts(flags); }
+
+    true
+}
+
+/// Allocates one page frame.
+///
+/// Returns:
+///
+/// the physical address of the frame, None if there is no free frame
+pub fn alloc_frame() -> Option<u32> {
+    alloc_contiguous(1)
+}
+
+/// Allocates contiguous page frames (for DMA buffers for instance).
+///
+/// Args:
+///
+/// `amount` - the amount of frames to allocate
+///
+/// Returns:
+///
+/// the physical address of the first frame, None if there is not enough contiguous free frames
+pub fn alloc_contiguous(amount: u32) -> Option<u32> {
+
+    if amount == 0 {
+        return None;
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let limit = get_frames_limit();
+    let hint = unsafe { *(NEXT_FRAME_ADDRESS as *const u32) };
+
+    /* the search starts from the frame following the last allocation,
+       then starts again from the first frame (most of the frames before
+       the hint are usually used) */
+    let mut found: Option<u32> = None;
+
+    for &(search_start, search_end) in [(hint, limit), (0, limit)].iter() {
+
+        let mut start = search_start;
+        let mut frame = search_start;
+
+        while frame < search_end {
+
+            /* full bitmap words are skipped at once */
+            if frame % FRAMES_PER_WORD == 0 && *get_frame_bit(frame).0 == 0xFFFFFFFF {
+                frame += FRAMES_PER_WORD;
+                start = frame;
+                continue;
+            }
+
+            if is_used(frame) {
+                frame += 1;
+                start = frame;
+                continue;
+            }
+
+            frame += 1;
+
+            if frame - start == amount {
+                found = Some(start);
+                break;
+            }
+        }
+
+        if found.is_some() {
+            break;
+        }
+    }
+
+    if let Some(start) = found {
+
+        for frame in start..start + amount {
+            set_used(frame, true);
+        }
+
+        unsafe { *(NEXT_FRAME_ADDRESS as *mut u32) = start + amount; }
+    }
+
+    unsafe { restore_interrupts(flags); }
+
+    found.map(|frame| frame * FRAME_BYTES_SIZE)
+}
+
+/// Frees one page frame.
+///
+/// Args:
+///
+/// `address` - the physical address of the frame (returned by `alloc_frame`)
+///
+/// Returns:
+///
+/// false if the frame was not allocated (or is reserved)
+pub fn free_frame(address: u32) -> bool {
+    free_contiguous(address, 1)
+}
+
+/// Frees contiguous page frames.
+///
+/// Args:
+///
+/// `address` - the physical address of the first frame (returned by `alloc_contiguous`)
+/// `amount` - the amount of frames to free
+///
+/// Returns:
+///
+/// false if one of the frames was not allocated (or is reserved), no frame is freed in that case
+pub fn free_contiguous(address: u32, amount: u32) -> bool {
+
+    if address % FRAME_BYTES_SIZE != 0 {
+        return false;
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let first_frame = address / FRAME_BYTES_SIZE;
+    let last_frame = first_frame.saturating_add(amount);
+
+    /* the frames after the last usable frame or into a reserved range (static reserved areas,
+       frames out of the usable areas, faulty memory...) are never freed */
+    let valid = last_frame <= get_frames_limit() &&
+        (first_frame..last_frame).all(|frame| is_used(frame)) &&
+        !is_reserved(first_frame, last_frame);
+
+    if valid {
+
+        for frame in first_frame..last_frame {
+            set_used(frame, false);
+        }
+
+        /* the freed frames are reused first */
+        unsafe {
+            if first_frame < *(NEXT_FRAME_ADDRESS as *const u32) {
+                *(NEXT_FRAME_ADDRESS as *mut u32) = first_frame;
+            }
+        }
+    }
+
+    unsafe { restore_interrupts(flags); }
+
+    valid
+}
+
+/// Indicates if the frame containing the given physical address is used.
+///
+/// Args:
+///
+/// `address` - the physical address
+///
+/// Returns:
+///
+/// true if the frame is allocated, reserved or not usable
+pub fn is_frame_used(address: u32) -> bool {
+    is_used(address / FRAME_BYTES_SIZE)
+}
+
+/// Returns the amount of usable frames (free or allocated, reserved frames excluded).
+///
+/// Returns:
+///
+/// the usable frames amount
+pub fn get_usable_frames_amount() -> u32 {
+    unsafe { *(USABLE_FRAMES_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the amount of free frames.
+///
+/// Returns:
+///
+/// the free frames amount
+pub fn get_free_frames_amount() -> u32 {
+    unsafe { *(FREE_FRAMES_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the amount of allocated frames.
+///
+/// Returns:
+///
+/// the allocated frames amount
+pub fn get_allocated_frames_amount() -> u32 {
+    get_usable_frames_amount() - get_free_frames_amount()
+}
@@ -0,0 +1,240 @@
+//! Kernel GDT, task state segments and double fault task
+//!
+//! The GDT loaded by Stage2 only contains the code and data segments. The kernel loads
+//! its own GDT with the same segments and two TSS (Task State Segment):
+//! the TSS of the kernel task and the TSS of the double fault task.
+//!
+//! The IDT descriptor of the double fault (vector 8) is a task gate: when a double fault occurs,
+//! the CPU saves the current state into the kernel TSS and switches to the double fault task,
+//! with its own stack. The double fault is handled even if the kernel stack is overflowed,
+//! instead of raising a triple fault (that resets the machine).
+
+use core::mem;
+
+use video::{
+    print,
+    printi32hex,
+    clear_screen,
+};
+
+use stack::{
+    is_kernel_stack_guard_page,
+    DOUBLE_FAULT_STACK_TOP,
+};
+
+use page_fault::get_page_fault_address;
+
+use create_task_gate_descriptor;
+
+/* kernel global variables (check README.md):
+   the GDT (5 descriptors of 8 bytes),
+   the GDT register,
+   the kernel TSS,
+   the double fault task TSS */
+const GDT_ADDRESS: u32 = 0xC0013500;
+const GDT_REGISTER_ADDRESS: u32 = 0xC0013540;
+const KERNEL_TSS_ADDRESS: u32 = 0xC0013580;
+const DOUBLE_FAULT_TSS_ADDRESS: u32 = 0xC0013600;
+
+/* the GDT selectors (offset of the descriptor into the GDT),
+   the code and data selectors are the same as the ones of the Stage2 GDT */
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+const KERNEL_TSS_SELECTOR: u16 = 0x18;
+const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x20;
+
+const GDT_DESCRIPTORS_AMOUNT: u32 = 5;
+
+const DOUBLE_FAULT_VECTOR: usize = 8;
+
+/* stores the required values to load the GDT with LGDT
+   (same format as the IDT register, check `IDTRegister`) */
+#[repr(packed)]
+struct GDTRegister {
+    limit: u16,
+    base: u32,
+}
+
+/// A 32 bits Task State Segment: the CPU saves the registers of the current task into its TSS
+/// and loads the registers of the new task from its TSS during a task switch.
+/// The segments selectors are 16 bits long, the upper 16 bits are reserved.
+#[repr(C)]
+struct TaskStateSegment {
+    previous_task: u32,
+    esp0: u32,
+    ss0: u32,
+    esp1: u32,
+    ss1: u32,
+    esp2: u32,
+    ss2: u32,
+    cr3: u32,
+    eip: u32,
+    eflags: u32,
+    eax: u32,
+    ecx: u32,
+    edx: u32,
+    ebx: u32,
+    esp: u32,
+    ebp: u32,
+    esi: u32,
+    edi: u32,
+    es: u32,
+    cs: u32,
+    ss: u32,
+    ds: u32,
+    fs: u32,
+    gs: u32,
+    ldt: u32,
+    trap: u16,
+    io_map_base: u16,
+}
+
+/// Returns the TSS at the given address.
+///
+/// Args:
+///
+/// `address` - the TSS address
+///
+/// Returns:
+///
+/// the TSS
+fn get_tss(address: u32) -> &'static mut TaskStateSegment {
+    unsafe { &mut *(address as *mut TaskStateSegment) }
+}
+
+/// Writes one GDT descriptor.
+///
+/// Args:
+///
+/// `index` - the descriptor index
+/// `base` - the segment base address
+/// `limit` - the segment limit (20 bits)
+/// `access` - the access byte (present, privilege level, type)
+/// `flags` - the flags (granularity, 32 bits segment), 4 bits
+fn set_gdt_descriptor(index: u32, base: u32, limit: u32, access: u8, flags: u8) {
+
+    /* bits 0 - 15: limit bits 0 - 15
+       bits 16 - 39: base bits 0 - 23
+       bits 40 - 47: access byte
+       bits 48 - 51: limit bits 16 - 19
+       bits 52 - 55: flags
+       bits 56 - 63: base bits 24 - 31 */
+    let descriptor = (limit as u64 & 0xFFFF) |
+        ((base as u64 & 0xFFFFFF) << 16) |
+        ((access as u64) << 40) |
+        (((limit as u64 >> 16) & 0xF) << 48) |
+        (((flags as u64) & 0xF) << 52) |
+        ((base as u64 >> 24) << 56);
+
+    unsafe { *((GDT_ADDRESS + index * 8) as *mut u64) = descriptor; }
+}
+
+/// Returns the current pages directory physical address.
+///
+/// Returns:
+///
+/// the CR3 value
+fn read_cr3() -> u32 {
+
+    let mut cr3: u32 = 0;
+    unsafe { llvm_asm!("mov $0, cr3" : "=r" (cr3) ::: "intel", "volatile"); }
+    cr3
+}
+
+/// Entry point of the double fault task, executed on its own stack.
+/// The kernel task state (saved by the CPU into the kernel TSS) is displayed,
+/// then the system is halted (a double fault cannot be recovered).
+fn handle_double_fault_task() -> ! {
+
+    let kernel_tss = get_tss(KERNEL_TSS_ADDRESS);
+    let fault_address = get_page_fault_address();
+
+    clear_screen();
+
+    if is_kernel_stack_guard_page(fault_address) {
+        print(0, "Error: kernel stack overflow (double fault)");
+    } else {
+        print(0, "Error: double fault");
+    }
+
+    print(160, "EIP:");
+    printi32hex(166, kernel_tss.eip);
+    print(240, "ESP:");
+    printi32hex(246, kernel_tss.esp);
+    print(320, "CR2:");
+    printi32hex(326, fault_address);
+
+    loop {
+        unsafe {
+            llvm_asm!("
+                cli
+                hlt
+                " :::: "intel", "volatile"
+            );
+        }
+    }
+}
+
+/// Loads the kernel GDT and the kernel TSS, installs the double fault task gate.
+/// Must be called after `load_idt` and after the kernel stacks are mapped.
+pub fn initialize_gdt() {
+
+    /* the access byte: present (bit 7), privilege level 0 (bits 5 - 6),
+       code or data segment (bit 4), type (bits 0 - 3):
+       code is executable and readable (1010), data is writable (0010),
+       TSS descriptors are system descriptors of type 32 bits available TSS (1001);
+       the flags: 4 KBytes granularity (bit 3) and 32 bits segment (bit 2) */
+    const CODE_ACCESS: u8 = 0b10011010;
+    const DATA_ACCESS: u8 = 0b10010010;
+    const TSS_ACCESS: u8 = 0b10001001;
+    const SEGMENT_FLAGS: u8 = 0b1100;
+
+    let tss_limit = mem::size_of::<TaskStateSegment>() as u32 - 1;
+
+    set_gdt_descriptor(0, 0, 0, 0, 0);
+    set_gdt_descriptor(1, 0, 0xFFFFF, CODE_ACCESS, SEGMENT_FLAGS);
+    set_gdt_descriptor(2, 0, 0xFFFFF, DATA_ACCESS, SEGMENT_FLAGS);
+    set_gdt_descriptor(3, KERNEL_TSS_ADDRESS, tss_limit, TSS_ACCESS, 0);
+    set_gdt_descriptor(4, DOUBLE_FAULT_TSS_ADDRESS, tss_limit, TSS_ACCESS, 0);
+
+    /* the kernel TSS is filled by the CPU when it leaves the kernel task,
+       no I/O permissions bitmap (the bitmap offset is after the TSS limit) */
+    unsafe {
+        *(KERNEL_TSS_ADDRESS as *mut TaskStateSegment) = mem::zeroed();
+        *(DOUBLE_FAULT_TSS_ADDRESS as *mut TaskStateSegment) = mem::zeroed();
+    }
+
+    let kernel_tss = get_tss(KERNEL_TSS_ADDRESS);
+    kernel_tss.io_map_base = mem::size_of::<TaskStateSegment>() as u16;
+
+    /* the double fault task runs in the kernel addresses space with interrupts disabled
+       (only the reserved bit 1 of EFLAGS is set) */
+    let double_fault_tss = get_tss(DOUBLE_FAULT_TSS_ADDRESS);
+    double_fault_tss.cr3 = read_cr3();
+    double_fault_tss.eip = (handle_double_fault_task as *const ()) as u32;
+    double_fault_tss.eflags = 0b10;
+    double_fault_tss.esp = DOUBLE_FAULT_STACK_TOP;
+    double_fault_tss.cs = KERNEL_CODE_SELECTOR as u32;
+    double_fault_tss.ds = KERNEL_DATA_SELECTOR as u32;
+    double_fault_tss.es = KERNEL_DATA_SELECTOR as u32;
+    double_fault_tss.fs = KERNEL_DATA_SELECTOR as u32;
+    double_fault_tss.gs = KERNEL_DATA_SELECTOR as u32;
+    double_fault_tss.ss = KERNEL_DATA_SELECTOR as u32;
+    double_fault_tss.io_map_base = mem::size_of::<TaskStateSegment>() as u16;
+
+    unsafe {
+        *(GDT_REGISTER_ADDRESS as *mut GDTRegister) = GDTRegister {
+            limit: (GDT_DESCRIPTORS_AMOUNT * 8 - 1) as u16,
+            base: GDT_ADDRESS,
+        };
+
+        /* the code and data segments are the same as before,
+           so the segments registers do not have to be reloaded */
+        llvm_asm!("lgdt ($0)" :: "r" (GDT_REGISTER_ADDRESS) : "memory" : "volatile");
+
+        /* the current task is the kernel task */
+        llvm_asm!("ltr $0" :: "r" (KERNEL_TSS_SELECTOR) :: "intel", "volatile");
+    }
+
+    create_task_gate_descriptor(DOUBLE_FAULT_VECTOR, DOUBLE_FAULT_TSS_SELECTOR);
+}
@@ -0,0 +1,471 @@
+//! Kernel heap (linked list allocator)
+//!
+//! The heap is a virtual area starting at 0xD0000000, its pages are mapped
+//! on page frames allocated from the page frames allocator. The free blocks of the heap
+//! are linked together, sorted by address: every free block starts with its size
+//! and the address of the next free block. Freed blocks are merged with the adjacent
+//! free blocks, so the heap does not get fragmented into small blocks.
+//!
+//! When no free block is large enough for an allocation, the heap grows:
+//! new pages are mapped at its end (up to 256 MBytes).
+//!
+//! With the `heap_debug` feature, the blocks are checked and tracked by the heap debug mode.
+
+use core::alloc::{
+    GlobalAlloc,
+    Layout,
+};
+
+use frames::{
+    alloc_frame,
+    free_frame,
+    FRAME_BYTES_SIZE,
+};
+
+use paging::{
+    map,
+    PageFlags,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+#[cfg(feature = "heap_debug")]
+use heap_debug::{
+    initialize_heap_debug,
+    get_debug_block_layout,
+    track_allocation,
+    untrack_allocation,
+    poison_block,
+};
+
+/* kernel global variables (check README.md):
+   the address following the last mapped page of the heap,
+   the address of the first free block (0 if there is no free block),
+   the amount of allocated bytes */
+const HEAP_END_ADDRESS: u32 = 0xC00130A0;
+const HEAP_FIRST_FREE_BLOCK_ADDRESS: u32 = 0xC00130A4;
+const HEAP_ALLOCATED_BYTES_ADDRESS: u32 = 0xC00130A8;
+
+/* the heap virtual area */
+pub const HEAP_START: u32 = 0xD0000000;
+const HEAP_MAXIMUM_SIZE: u32 = 0x10000000;
+
+/* the heap is 64 KBytes long at the initialization */
+const HEAP_INITIAL_SIZE: u32 = 0x10000;
+
+/* every block is large enough to store a free block header,
+   every block size and address is a multiple of the header size */
+const BLOCK_GRANULARITY: u32 = 8;
+
+/* the address the current function returns to, recorded as the allocation (or free) call site
+   in debug mode; the functions using it are never inlined in debug mode;
+   only `heap_alloc` and `heap_free` use it: the `GlobalAlloc` implementation is always called
+   by the allocator shims of the compiler (`__rust_alloc`...), so its return address
+   would be the same for every `alloc` allocation, it records 0 instead */
+#[cfg(feature = "heap_debug")]
+macro_rules! get_call_site {
+    () => { unsafe { ::core::intrinsics::return_address() as u32 } };
+}
+
+#[cfg(not(feature = "heap_debug"))]
+macro_rules! get_call_site {
+    () => { 0 };
+}
+
+/// The header of every free block.
+#[repr(C)]
+struct FreeBlock {
+    size: u32,
+    next: u32,
+}
+
+/// Returns the free block at the given address.
+///
+/// Args:
+///
+/// `address` - the free block address
+///
+/// Returns:
+///
+/// the free block header
+fn get_free_block(address: u32) -> &'static mut FreeBlock {
+    unsafe { &mut *(address as *mut FreeBlock) }
+}
+
+/// Returns the address of the first free block.
+///
+/// Returns:
+///
+/// the first free block address, 0 if there is no free block
+fn get_first_free_block() -> u32 {
+    unsafe { *(HEAP_FIRST_FREE_BLOCK_ADDRESS as *const u32) }
+}
+
+/// Updates the address of the first free block.
+///
+/// Args:
+///
+/// `address` - the first free block address, 0 if there is no free block
+fn set_first_free_block(address: u32) {
+    unsafe { *(HEAP_FIRST_FREE_BLOCK_ADDRESS as *mut u32) = address; }
+}
+
+/// Rounds up the given value to a multiple of the given alignment.
+///
+/// Args:
+///
+/// `value` - the value to round up
+/// `alignment` - the alignment (power of two)
+///
+/// Returns:
+///
+/// the aligned value
+fn align_up(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// Returns the block size and alignment used for the given layout,
+/// and the offset of the allocated memory into the block.
+///
+/// Args:
+///
+/// `layout` - the requested memory layout
+///
+/// Returns:
+///
+/// the block size, alignment and offset (the offset is always 0 without the debug mode)
+fn get_block_layout(layout: &Layout) -> (u32, u32, u32) {
+
+    let mut size = align_up(layout.size() as u32, BLOCK_GRANULARITY);
+    if size == 0 {
+        size = BLOCK_GRANULARITY;
+    }
+
+    let mut alignment = layout.align() as u32;
+    if alignment < BLOCK_GRANULARITY {
+        alignment = BLOCK_GRANULARITY;
+    }
+
+    /* the allocation record and the red zones are part of the block in debug mode */
+    #[cfg(feature = "heap_debug")]
+    let (size, offset) = get_debug_block_layout(size, alignment);
+
+    #[cfg(not(feature = "heap_debug"))]
+    let offset = 0;
+
+    (size, alignment, offset)
+}
+
+/// Inserts a free block into the free blocks list (sorted by address),
+/// merges it with the previous and the next free blocks if they are adjacent.
+///
+/// Args:
+///
+/// `address` - the block address
+/// `size` - the block size
+fn insert_free_block(address: u32, size: u32) {
+
+    let mut previous: u32 = 0;
+    let mut next = get_first_free_block();
+
+    while next != 0 && next < address {
+        previous = next;
+        next = get_free_block(next).next;
+    }
+
+    let block = get_free_block(address);
+    block.size = size;
+    block.next = next;
+
+    if next != 0 && address + size == next {
+        let next_block = get_free_block(next);
+        block.size += next_block.size;
+        block.next = next_block.next;
+    }
+
+    if previous == 0 {
+        set_first_free_block(address);
+        return;
+    }
+
+    let previous_block = get_free_block(previous);
+
+    if previous + previous_block.size == address {
+        previous_block.size += block.size;
+        previous_block.next = block.next;
+    } else {
+        previous_block.next = address;
+    }
+}
+
+/// Finds a free block large enough for the given block size and alignment (first fit),
+/// removes the allocated part from the free blocks list.
+///
+/// Args:
+///
+/// `size` - the block size
+/// `alignment` - the block alignment
+///
+/// Returns:
+///
+/// the block address, None if there is no free block large enough
+fn take_free_block(size: u32, alignment: u32) -> Option<u32> {
+
+    let mut previous: u32 = 0;
+    let mut current = get_first_free_block();
+
+    while current != 0 {
+
+        let (block_size, block_next) = {
+            let block = get_free_block(current);
+            (block.size, block.next)
+        };
+
+        let block_end = current + block_size;
+        let start = align_up(current, alignment);
+
+        if start < block_end && block_end - start >= size {
+
+            if previous == 0 {
+                set_first_free_block(block_next);
+            } else {
+                get_free_block(previous).next = block_next;
+            }
+
+            /* the free parts before and after the allocated block are inserted back */
+            if start != current {
+                insert_free_block(current, start - current);
+            }
+
+            if start + size != block_end {
+                insert_free_block(start + size, block_end - start - size);
+            }
+
+            return Some(start);
+        }
+
+        previous = current;
+        current = block_next;
+    }
+
+    None
+}
+
+/// Maps new pages at the end of the heap, so a block of the given size can be allocated.
+///
+/// Args:
+///
+/// `size` - the block size
+/// `alignment` - the block alignment
+///
+/// Returns:
+///
+/// false if the heap cannot grow (no free page frame or maximum size reached)
+fn grow_heap(size: u32, alignment: u32) -> bool {
+
+    let heap_end = get_heap_end();
+
+    /* the alignment padding might be required at the beginning of the new pages */
+    let requested = size as u64 + alignment as u64;
+    let length = (requested + FRAME_BYTES_SIZE as u64 - 1) / FRAME_BYTES_SIZE as u64 *
+        FRAME_BYTES_SIZE as u64;
+
+    if heap_end as u64 + length > HEAP_START as u64 + HEAP_MAXIMUM_SIZE as u64 {
+        return false;
+    }
+
+    let mut mapped: u32 = 0;
+
+    while (mapped as u64) < length {
+
+        let frame = match alloc_frame() {
+            Some(frame) => frame,
+            None => break,
+        };
+
+        if !map(heap_end + mapped, frame, PageFlags::WRITABLE | PageFlags::NO_EXECUTE) {
+            free_frame(frame);
+            break;
+        }
+
+        mapped += FRAME_BYTES_SIZE;
+    }
+
+    /* the pages mapped before a failure are kept into the heap */
+    if mapped != 0 {
+        unsafe { *(HEAP_END_ADDRESS as *mut u32) = heap_end + mapped; }
+        insert_free_block(heap_end, mapped);
+    }
+
+    mapped as u64 == length
+}
+
+/// Initializes the kernel heap, must be called after paging initialization.
+///
+/// Returns:
+///
+/// false if the initial pages of the heap cannot be mapped
+pub fn initialize_heap() -> bool {
+
+    unsafe {
+        *(HEAP_END_ADDRESS as *mut u32) = HEAP_START;
+        *(HEAP_FIRST_FREE_BLOCK_ADDRESS as *mut u32) = 0;
+        *(HEAP_ALLOCATED_BYTES_ADDRESS as *mut u32) = 0;
+    }
+
+    #[cfg(feature = "heap_debug")]
+    initialize_heap_debug();
+
+    /* the initial size is a multiple of the page size, so no alignment padding is required */
+    grow_heap(HEAP_INITIAL_SIZE - BLOCK_GRANULARITY, BLOCK_GRANULARITY)
+}
+
+/// Allocates a block from the heap, the heap grows if required.
+///
+/// Args:
+///
+/// `layout` - the requested memory layout
+/// `call_site` - the address of the code requesting the allocation, 0 if unknown (debug mode only)
+///
+/// Returns:
+///
+/// the allocated memory address, None if the heap is out of memory
+/// (or if the alignment is larger than the page size)
+#[cfg_attr(not(feature = "heap_debug"), allow(unused_variables))]
+fn allocate(layout: Layout, call_site: u32) -> Option<u32> {
+
+    if layout.size() > HEAP_MAXIMUM_SIZE as usize || layout.align() > FRAME_BYTES_SIZE as usize {
+        return None;
+    }
+
+    let (size, alignment, offset) = get_block_layout(&layout);
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let mut address = take_free_block(size, alignment);
+
+    if address.is_none() && grow_heap(size, alignment) {
+        address = take_free_block(size, alignment);
+    }
+
+    if address.is_some() {
+        unsafe { *(HEAP_ALLOCATED_BYTES_ADDRESS as *mut u32) += size; }
+    }
+
+    unsafe { restore_interrupts(flags); }
+
+    let address = address.map(|block| block + offset);
+
+    #[cfg(feature = "heap_debug")]
+    {
+        if let Some(address) = address {
+            track_allocation(address, layout.size() as u32, call_site);
+        }
+    }
+
+    address
+}
+
+/// Frees a block allocated from the heap, the block is merged with the adjacent free blocks.
+///
+/// Args:
+///
+/// `address` - the allocated memory address
+/// `layout` - the memory layout used for the allocation
+/// `call_site` - the address of the code freeing the block, 0 if unknown (debug mode only)
+#[cfg_attr(not(feature = "heap_debug"), allow(unused_variables))]
+fn deallocate(address: u32, layout: Layout, call_site: u32) {
+
+    let (size, _, offset) = get_block_layout(&layout);
+    let block = address - offset;
+
+    /* the red zones are checked before the block is poisoned */
+    #[cfg(feature = "heap_debug")]
+    {
+        untrack_allocation(address, layout.size() as u32, call_site);
+        poison_block(block, size);
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    insert_free_block(block, size);
+    unsafe { *(HEAP_ALLOCATED_BYTES_ADDRESS as *mut u32) -= size; }
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Allocates a block from the heap, the heap grows if required.
+///
+/// Args:
+///
+/// `layout` - the requested memory layout
+///
+/// Returns:
+///
+/// the block address, None if the heap is out of memory
+/// (or if the alignment is larger than the page size)
+#[cfg_attr(feature = "heap_debug", inline(never))]
+pub fn heap_alloc(layout: Layout) -> Option<u32> {
+    allocate(layout, get_call_site!())
+}
+
+/// Frees a block allocated from the heap, the block is merged with the adjacent free blocks.
+///
+/// Args:
+///
+/// `address` - the block address (returned by `heap_alloc`)
+/// `layout` - the memory layout used for the allocation
+#[cfg_attr(feature = "heap_debug", inline(never))]
+pub fn heap_free(address: u32, layout: Layout) {
+    deallocate(address, layout, get_call_site!());
+}
+
+/// Returns the address following the last mapped page of the heap.
+///
+/// Returns:
+///
+/// the heap end address
+fn get_heap_end() -> u32 {
+    unsafe { *(HEAP_END_ADDRESS as *const u32) }
+}
+
+/// Returns the current size of the heap (mapped pages).
+///
+/// Returns:
+///
+/// the heap size in bytes
+pub fn get_heap_size() -> u32 {
+    get_heap_end() - HEAP_START
+}
+
+/// Returns the amount of bytes allocated from the heap (including the alignment of the blocks sizes,
+/// and the allocation records and red zones in debug mode).
+///
+/// Returns:
+///
+/// the allocated bytes amount
+pub fn get_heap_allocated_bytes() -> u32 {
+    unsafe { *(HEAP_ALLOCATED_BYTES_ADDRESS as *const u32) }
+}
+
+/// The kernel heap allocator, used as the global allocator of the kernel
+/// (so `Box`, `Vec`, `String` or `BTreeMap` can be used from the `alloc` crate).
+pub struct KernelHeap;
+
+unsafe impl GlobalAlloc for KernelHeap {
+
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+
+        match allocate(layout, 0) {
+            Some(address) => address as *mut u8,
+            None => 0 as *mut u8,
+        }
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        deallocate(pointer as u32, layout, 0);
+    }
+}
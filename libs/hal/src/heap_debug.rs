@@ -0,0 +1,342 @@
+//! Kernel heap debug mode (`heap_debug` feature)
+//!
+//! Every allocated block starts with an allocation record (requested size, call site
+//! and links to the other allocated blocks) followed by a red zone, the allocated memory
+//! is followed by a second red zone. The red zones are filled with a known pattern
+//! and checked when the block is freed: an overrun (or an underrun) halts the system.
+//! The freed blocks are filled with a poison pattern, so a use-after-free reads
+//! recognizable values (and jumps into int3 instructions if a freed function pointer is called).
+//! The allocation records are linked together, so the outstanding allocations can be listed.
+
+use video::{
+    print,
+    printi32hex,
+    clear_screen,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   the address of the most recent allocation record (0 if there is no allocation),
+   the amount of outstanding allocations */
+const FIRST_ALLOCATION_ADDRESS: u32 = 0xC00130D8;
+const ALLOCATIONS_AMOUNT_ADDRESS: u32 = 0xC00130DC;
+
+/* the red zones and the unused bytes at the end of the allocated memory contain 0xCC
+   (int3 instruction), the freed blocks contain 0x6B */
+const RED_ZONE_PATTERN: u8 = 0xCC;
+const POISON_PATTERN: u8 = 0x6B;
+
+const RED_ZONE_SIZE: u32 = 16;
+
+/* the allocation record and the first red zone are located right before the allocated memory */
+const RECORD_SIZE: u32 = 16;
+const DEBUG_HEADER_SIZE: u32 = RECORD_SIZE + RED_ZONE_SIZE;
+
+/// The record stored before every allocated block.
+#[repr(C)]
+struct AllocationRecord {
+    size: u32,
+    call_site: u32,
+    previous: u32,
+    next: u32,
+}
+
+/// An outstanding allocation of the kernel heap.
+#[derive(Copy, Clone)]
+pub struct HeapAllocation {
+    pub address: u32,
+    pub size: u32,
+    pub call_site: u32,
+}
+
+/// Returns the allocation record of the given allocated memory.
+///
+/// Args:
+///
+/// `address` - the allocated memory address
+///
+/// Returns:
+///
+/// the allocation record, located before the first red zone
+fn get_record(address: u32) -> &'static mut AllocationRecord {
+    unsafe { &mut *((address - DEBUG_HEADER_SIZE) as *mut AllocationRecord) }
+}
+
+/// Returns the address of the most recent allocation record.
+///
+/// Returns:
+///
+/// the allocated memory address of the most recent allocation, 0 if there is no allocation
+fn get_first_allocation() -> u32 {
+    unsafe { *(FIRST_ALLOCATION_ADDRESS as *const u32) }
+}
+
+/// Returns the size of the second red zone and of the unused bytes preceding it.
+///
+/// Args:
+///
+/// `size` - the requested size
+///
+/// Returns:
+///
+/// the amount of bytes following the allocated memory that are checked on free
+fn get_tail_size(size: u32) -> u32 {
+
+    /* the blocks sizes are multiples of 8 bytes */
+    const BLOCK_GRANULARITY: u32 = 8;
+
+    ((size + BLOCK_GRANULARITY - 1) & !(BLOCK_GRANULARITY - 1)) - size + RED_ZONE_SIZE
+}
+
+/// Returns the block size and the offset of the allocated memory into the block in debug mode.
+///
+/// Args:
+///
+/// `size` - the block size without the debug data
+/// `alignment` - the block alignment
+///
+/// Returns:
+///
+/// the block size including the allocation record and the red zones,
+/// and the offset of the allocated memory (a multiple of the alignment)
+pub fn get_debug_block_layout(size: u32, alignment: u32) -> (u32, u32) {
+
+    let offset = (DEBUG_HEADER_SIZE + alignment - 1) & !(alignment - 1);
+    (offset + size + RED_ZONE_SIZE, offset)
+}
+
+/// Fills a memory area with the given pattern.
+///
+/// Args:
+///
+/// `address` - the area address
+/// `length` - the area length in bytes
+/// `pattern` - the filling byte
+fn fill(address: u32, length: u32, pattern: u8) {
+
+    for byte in address..address + length {
+        unsafe { *(byte as *mut u8) = pattern; }
+    }
+}
+
+/// Checks a memory area only contains the given pattern.
+///
+/// Args:
+///
+/// `address` - the area address
+/// `length` - the area length in bytes
+/// `pattern` - the expected byte
+///
+/// Returns:
+///
+/// the address of the first byte that does not match, None if the area is intact
+fn find_mismatch(address: u32, length: u32, pattern: u8) -> Option<u32> {
+    (address..address + length).find(|byte| unsafe { *(*byte as *const u8) } != pattern)
+}
+
+/// Displays a heap corruption and halts the system.
+///
+/// Args:
+///
+/// `message` - the error message
+/// `address` - the allocated memory address
+/// `faulty_address` - the address of the corrupted byte, or the address of the record
+/// `call_site` - the recorded allocation call site, 0 if the record is invalid or if it is unknown
+/// `free_call_site` - the address of the code freeing the block, 0 if it is unknown
+fn report_corruption(
+    message: &str,
+    address: u32,
+    faulty_address: u32,
+    call_site: u32,
+    free_call_site: u32,
+) -> ! {
+
+    clear_screen();
+    print(0, message);
+    print(160, "Block:");
+    printi32hex(180, address);
+    print(240, "Corrupted byte:");
+    printi32hex(260, faulty_address);
+    print(320, "Allocated from:");
+    printi32hex(340, call_site);
+    print(400, "Freed from:");
+    printi32hex(420, free_call_site);
+
+    loop {
+        unsafe { llvm_asm!("hlt" :::: "intel"); }
+    }
+}
+
+/// Writes the allocation record and the red zones of a new allocation,
+/// links the record to the outstanding allocations.
+///
+/// Args:
+///
+/// `address` - the allocated memory address
+/// `size` - the requested size
+/// `call_site` - the address of the code requesting the allocation, 0 if it is unknown
+pub fn track_allocation(address: u32, size: u32, call_site: u32) {
+
+    fill(address - RED_ZONE_SIZE, RED_ZONE_SIZE, RED_ZONE_PATTERN);
+    fill(address + size, get_tail_size(size), RED_ZONE_PATTERN);
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let next = get_first_allocation();
+
+    let record = get_record(address);
+    record.size = size;
+    record.call_site = call_site;
+    record.previous = 0;
+    record.next = next;
+
+    if next != 0 {
+        get_record(next).previous = address;
+    }
+
+    unsafe {
+        *(FIRST_ALLOCATION_ADDRESS as *mut u32) = address;
+        *(ALLOCATIONS_AMOUNT_ADDRESS as *mut u32) += 1;
+    }
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Checks the allocation record and the red zones of a freed allocation (halts the system
+/// if they are corrupted), removes the record from the outstanding allocations.
+///
+/// Args:
+///
+/// `address` - the allocated memory address
+/// `size` - the size of the freed layout
+/// `call_site` - the address of the code freeing the block, 0 if it is unknown
+pub fn untrack_allocation(address: u32, size: u32, call_site: u32) {
+
+    let record_address = address - DEBUG_HEADER_SIZE;
+
+    /* a block freed twice has a poisoned record (or a free block header) */
+    if get_record(address).size != size {
+        report_corruption(
+            "Error: kernel heap block freed twice or with a wrong size",
+            address,
+            record_address,
+            0,
+            call_site,
+        );
+    }
+
+    let allocation_call_site = get_record(address).call_site;
+
+    let red_zones = [
+        (address - RED_ZONE_SIZE, RED_ZONE_SIZE),
+        (address + size, get_tail_size(size)),
+    ];
+
+    for &(start, length) in red_zones.iter() {
+
+        if let Some(faulty_address) = find_mismatch(start, length, RED_ZONE_PATTERN) {
+            report_corruption(
+                "Error: kernel heap red zone overwritten",
+                address,
+                faulty_address,
+                allocation_call_site,
+                call_site,
+            );
+        }
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let (previous, next) = {
+        let record = get_record(address);
+        (record.previous, record.next)
+    };
+
+    if previous == 0 {
+        unsafe { *(FIRST_ALLOCATION_ADDRESS as *mut u32) = next; }
+    } else {
+        get_record(previous).next = next;
+    }
+
+    if next != 0 {
+        get_record(next).previous = previous;
+    }
+
+    unsafe {
+        *(ALLOCATIONS_AMOUNT_ADDRESS as *mut u32) -= 1;
+        restore_interrupts(flags);
+    }
+}
+
+/// Fills a freed block with the poison pattern.
+///
+/// Args:
+///
+/// `address` - the block address (allocation record included)
+/// `size` - the block size
+pub fn poison_block(address: u32, size: u32) {
+    fill(address, size, POISON_PATTERN);
+}
+
+/// Initializes the outstanding allocations list, called by the heap initialization.
+pub fn initialize_heap_debug() {
+
+    unsafe {
+        *(FIRST_ALLOCATION_ADDRESS as *mut u32) = 0;
+        *(ALLOCATIONS_AMOUNT_ADDRESS as *mut u32) = 0;
+    }
+}
+
+/// Returns the amount of outstanding allocations of the kernel heap.
+///
+/// Returns:
+///
+/// the amount of allocated blocks not freed yet
+pub fn get_heap_allocations_amount() -> u32 {
+    unsafe { *(ALLOCATIONS_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns an outstanding allocation of the kernel heap.
+///
+/// Args:
+///
+/// `index` - the allocation index, from the most recent allocation (0)
+/// to the oldest one (`get_heap_allocations_amount() - 1`)
+///
+/// Returns:
+///
+/// the allocation, None if the index is out of range
+pub fn get_heap_allocation(index: u32) -> Option<HeapAllocation> {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let mut address = get_first_allocation();
+
+    for _ in 0..index {
+
+        if address == 0 {
+            break;
+        }
+
+        address = get_record(address).next;
+    }
+
+    let allocation = if address == 0 {
+        None
+    } else {
+        let record = get_record(address);
+        Some(HeapAllocation {
+            address: address,
+            size: record.size,
+            call_site: record.call_site,
+        })
+    };
+
+    unsafe { restore_interrupts(flags); }
+
+    allocation
+}
@@ -0,0 +1,221 @@
+//! Common interrupt dispatch path and per-vector interrupt statistics
+//!
+//! Every IDT descriptor points to one small entry routine (one per vector),
+//! that pushes a fake error code (if the CPU does not push one for this vector)
+//! and the vector number, then jumps to a common routine saving every register
+//! and calling `dispatch_interrupt`. The dispatcher counts the interrupt
+//! and calls the handler registered for the vector.
+
+use core::mem;
+
+use video::{
+    print,
+    printi32,
+    clear_screen,
+};
+
+/* kernel global variables (check README.md):
+   amount of occurrences of every vector (256 u32),
+   address of the handler of every vector (256 u32) */
+const INTERRUPTS_AMOUNTS_ADDRESS: u32 = 0xC0014000;
+const INTERRUPTS_HANDLERS_ADDRESS: u32 = 0xC0014400;
+
+pub const INTERRUPTS_VECTORS_AMOUNT: usize = 256;
+
+/* every entry routine is aligned on 16 bytes,
+   so the entry routine of a vector is at `interrupt_entries + vector * 16`;
+   the CPU pushes an error code for the exceptions 8, 10 to 14, 17, 21, 29 and 30,
+   a fake one (0) is pushed for the other vectors in order to keep the same frame layout;
+   the push instructions are written as bytes (0x6A: push imm8, 0x68: push imm32) */
+global_asm!(r#"
+    .intel_syntax noprefix
+    .section .text
+    .global interrupt_entries
+    .align 16
+interrupt_entries:
+    .set vector, 0
+    .rept 256
+    .align 16
+    .if (vector == 8) || ((vector >= 10) && (vector <= 14)) || (vector == 17) || (vector == 21) || (vector == 29) || (vector == 30)
+    .else
+    .byte 0x6A, 0x00
+    .endif
+    .byte 0x68
+    .long vector
+    jmp interrupt_common
+    .set vector, vector + 1
+    .endr
+
+interrupt_common:
+    pushad
+    cld
+    push esp
+    call dispatch_interrupt
+    add esp, 4
+    popad
+    add esp, 8
+    iretd
+    .att_syntax
+"#);
+
+extern {
+    fn interrupt_entries();
+}
+
+/// The stack content when an interrupt handler is called:
+/// the general registers saved by the common routine (pushad order),
+/// the vector and the error code pushed by the entry routine,
+/// and the registers pushed by the CPU.
+#[repr(C)]
+pub struct InterruptFrame {
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+    pub vector: u32,
+    pub error_code: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+}
+
+/// An interrupt handler, called with the interrupted context.
+pub type InterruptHandler = fn(&mut InterruptFrame);
+
+/// Returns the address of the entry routine of the given vector (to write into the IDT).
+///
+/// Args:
+///
+/// `vector` - the IDT index
+///
+/// Returns:
+///
+/// the entry routine address
+pub fn get_interrupt_entry(vector: usize) -> u32 {
+
+    const INTERRUPT_ENTRY_SIZE: u32 = 16;
+    (interrupt_entries as *const ()) as u32 + (vector as u32) * INTERRUPT_ENTRY_SIZE
+}
+
+/// Sets the handler called when the given vector is raised.
+///
+/// Args:
+///
+/// `vector` - the IDT index
+/// `handler` - the function to call
+pub fn set_interrupt_handler(vector: usize, handler: InterruptHandler) {
+
+    let address = INTERRUPTS_HANDLERS_ADDRESS + (vector as u32) * 4;
+    unsafe { *(address as *mut u32) = (handler as *const ()) as u32; }
+}
+
+/// Sets the given handler for every vector and resets every statistic.
+///
+/// Args:
+///
+/// `handler` - the default handler
+pub fn initialize_interrupt_handlers(handler: InterruptHandler) {
+
+    for vector in 0..INTERRUPTS_VECTORS_AMOUNT {
+        set_interrupt_handler(vector, handler);
+    }
+
+    reset_interrupts_statistics();
+}
+
+/// Called by the common entry routine for every interrupt,
+/// counts the interrupt and calls the handler of the vector.
+///
+/// Args:
+///
+/// `frame` - the interrupted context
+#[no_mangle]
+pub extern "C" fn dispatch_interrupt(frame: &mut InterruptFrame) {
+
+    let vector = (frame.vector as usize) & (INTERRUPTS_VECTORS_AMOUNT - 1);
+
+    unsafe {
+        *((INTERRUPTS_AMOUNTS_ADDRESS + (vector as u32) * 4) as *mut u32) += 1;
+
+        let address = *((INTERRUPTS_HANDLERS_ADDRESS + (vector as u32) * 4) as *const u32);
+        let handler: InterruptHandler = mem::transmute(address as usize);
+        handler(frame);
+    }
+}
+
+/// Returns the amount of times the given vector has been raised.
+///
+/// Args:
+///
+/// `vector` - the IDT index
+///
+/// Returns:
+///
+/// the occurrences amount
+pub fn get_interrupts_amount(vector: usize) -> u32 {
+    unsafe { *((INTERRUPTS_AMOUNTS_ADDRESS + (vector as u32) * 4) as *const u32) }
+}
+
+/// Resets the occurrences amount of every vector.
+pub fn reset_interrupts_statistics() {
+
+    for vector in 0..INTERRUPTS_VECTORS_AMOUNT {
+        unsafe { *((INTERRUPTS_AMOUNTS_ADDRESS + (vector as u32) * 4) as *mut u32) = 0; }
+    }
+}
+
+/// Returns the name of the given CPU exception.
+///
+/// Args:
+///
+/// `vector` - the IDT index (from 0 to 31)
+///
+/// Returns:
+///
+/// the exception name, "Reserved" for the reserved indices
+pub fn get_exception_name(vector: usize) -> &'static str {
+
+    match vector {
+        0 => "Division by zero",
+        1 => "Debug",
+        2 => "Non maskable interrupt",
+        3 => "Breakpoint",
+        4 => "Overflow",
+        5 => "Bound range exceeded",
+        6 => "Invalid opcode",
+        7 => "Device not available",
+        8 => "Double fault",
+        10 => "Invalid TSS",
+        11 => "Segment not present",
+        12 => "Stack segment fault",
+        13 => "General protection fault",
+        14 => "Page fault",
+        16 => "x87 floating point",
+        17 => "Alignment check",
+        18 => "Machine check",
+        19 => "SIMD floating point",
+        20 => "Virtualization",
+        _ => "Reserved",
+    }
+}
+
+/// Default handler of every vector without specific handler,
+/// displays the vector and halts the system.
+///
+/// Args:
+///
+/// `frame` - the interrupted context
+pub fn handle_unexpected_interrupt(frame: &mut InterruptFrame) {
+
+    clear_screen();
+    print(0, "Error: unexpected interrupt");
+    printi32(80, frame.vector);
+
+    loop {
+        unsafe { llvm_asm!("hlt" :::: "intel"); }
+    }
+}
@@ -0,0 +1,89 @@
+//! I/O ports access routines
+
+/// Writes one byte into the given I/O port.
+///
+/// Args:
+///
+/// `port` - the I/O port number
+/// `value` - the byte to write
+pub unsafe fn outb(port: u16, value: u8) {
+    llvm_asm!("out dx, al" :: "{dx}" (port), "{al}" (value) :: "intel");
+}
+
+/// Reads one byte from the given I/O port.
+///
+/// Args:
+///
+/// `port` - the I/O port number
+///
+/// Returns:
+///
+/// the read byte
+pub unsafe fn inb(port: u16) -> u8 {
+
+    let mut value: u8 = 0;
+    llvm_asm!("in al, dx" : "={al}" (value) : "{dx}" (port) :: "intel");
+    value
+}
+
+/// Writes one word (two bytes) into the given I/O port.
+///
+/// Args:
+///
+/// `port` - the I/O port number
+/// `value` - the word to write
+pub unsafe fn outw(port: u16, value: u16) {
+    llvm_asm!("out dx, ax" :: "{dx}" (port), "{ax}" (value) :: "intel");
+}
+
+/// Reads one word (two bytes) from the given I/O port.
+///
+/// Args:
+///
+/// `port` - the I/O port number
+///
+/// Returns:
+///
+/// the read word
+pub unsafe fn inw(port: u16) -> u16 {
+
+    let mut value: u16 = 0;
+    llvm_asm!("in ax, dx" : "={ax}" (value) : "{dx}" (port) :: "intel");
+    value
+}
+
+/// Writes one double word (four bytes) into the given I/O port.
+///
+/// Args:
+///
+/// `port` - the I/O port number
+/// `value` - the double word to write
+pub unsafe fn outl(port: u16, value: u32) {
+    llvm_asm!("out dx, eax" :: "{dx}" (port), "{eax}" (value) :: "intel");
+}
+
+/// Reads one double word (four bytes) from the given I/O port.
+///
+/// Args:
+///
+/// `port` - the I/O port number
+///
+/// Returns:
+///
+/// the read double word
+pub unsafe fn inl(port: u16) -> u32 {
+
+    let mut value: u32 = 0;
+    llvm_asm!("in eax, dx" : "={eax}" (value) : "{dx}" (port) :: "intel");
+    value
+}
+
+/// Waits for a very short amount of time (1 to 4 microseconds),
+/// required by old devices (like the PIC) between two commands.
+///
+/// The port 0x80 is used by the BIOS for POST codes during the boot,
+/// it is unused after the boot, so writing into it has no effect
+/// but takes one full I/O bus cycle.
+pub unsafe fn io_wait() {
+    outb(0x80, 0);
+}
@@ -1,10 +1,317 @@
 //! SmallOS Hardware Abstraction Layer library
 #![allow(unused_assignments, dead_code)]
-#![feature(llvm_asm)]
+#![feature(llvm_asm, global_asm)]
+#![cfg_attr(feature = "heap_debug", feature(core_intrinsics))]
 #![no_std]
 
 extern crate video;
 
+mod io;
+mod cpu;
+mod pic;
+mod apic;
+mod deferred;
+mod interrupts;
+mod pit;
+mod time;
+mod timer;
+mod rtc;
+mod tsc;
+mod speaker;
+mod memory_map;
+mod frames;
+#[cfg(feature = "memory_test")]
+mod memory_test;
+mod paging;
+mod heap;
+#[cfg(feature = "heap_debug")]
+mod heap_debug;
+mod slab;
+mod stack;
+mod gdt;
+mod cow;
+mod page_fault;
+mod ata;
+mod swap;
+mod memory_statistics;
+
+pub use io::{
+    inb,
+    outb,
+    inw,
+    outw,
+    inl,
+    outl,
+    io_wait,
+};
+
+pub use cpu::{
+    cpuid,
+    read_msr,
+    write_msr,
+    is_supported_cpu,
+    get_cpu_features,
+    get_cpu_info,
+    CpuInfo,
+    CpuFeatures,
+    CPU_FEATURES,
+};
+
+pub use pic::{
+    initialize_pic,
+    disable_pic,
+    get_irq_vector,
+    mask_irq,
+    unmask_irq,
+    get_pic_imr,
+    get_pic_isr,
+    get_pic_irr,
+    send_eoi,
+    is_spurious_irq,
+    get_spurious_irqs_amount,
+    get_irq_name,
+};
+
+pub use apic::{
+    initialize_apic,
+    has_local_apic,
+    is_apic_enabled,
+    get_local_apic_base,
+    get_io_apic_base,
+    get_local_apic_id,
+    get_redirection_entries_amount,
+    get_redirection_entry,
+    set_redirection_entry,
+    send_local_apic_eoi,
+    RedirectionEntry,
+    DeliveryMode,
+};
+
+pub use deferred::{
+    initialize_deferred_work,
+    defer_work,
+    has_deferred_work,
+    run_deferred_work,
+    get_dropped_deferred_work_amount,
+    DeferredWork,
+};
+
+pub use interrupts::{
+    set_interrupt_handler,
+    get_interrupts_amount,
+    reset_interrupts_statistics,
+    get_exception_name,
+    InterruptFrame,
+    InterruptHandler,
+    INTERRUPTS_VECTORS_AMOUNT,
+};
+
+use interrupts::{
+    get_interrupt_entry,
+    initialize_interrupt_handlers,
+    handle_unexpected_interrupt,
+};
+
+pub use pit::{
+    initialize_pit,
+    get_ticks_amount,
+    set_timer_frequency,
+    get_timer_frequency,
+    get_tick_duration,
+    get_monotonic_time,
+    PIT_BASE_FREQUENCY,
+};
+
+pub use time::{
+    Instant,
+    sleep,
+    sleep_ms,
+    sleep_us,
+};
+
+pub use timer::{
+    Timer,
+    TimerCallback,
+};
+
+pub use rtc::{
+    initialize_rtc,
+    read_date_time,
+    enable_rtc_periodic_interrupt,
+    disable_rtc_periodic_interrupt,
+    get_rtc_periodic_interrupts_amount,
+    enable_rtc_alarm,
+    disable_rtc_alarm,
+    DateTime,
+    RtcCallback,
+};
+
+pub use tsc::{
+    initialize_tsc,
+    is_tsc_invariant,
+    get_tsc_frequency,
+    get_tsc_time,
+    read_tsc,
+    cycles_to_nanoseconds,
+    CycleCounter,
+};
+
+pub use speaker::{
+    initialize_speaker,
+    beep,
+    play_notes,
+    stop_speaker,
+    Note,
+};
+
+pub use memory_map::{
+    get_memory_map,
+    get_ram_amount,
+    MemoryMap,
+    MemoryArea,
+    MemoryAreaType,
+    MEMORY_AREAS_MAX_AMOUNT,
+};
+
+pub use frames::{
+    initialize_frame_allocator,
+    reserve_frames,
+    alloc_frame,
+    alloc_contiguous,
+    free_frame,
+    free_contiguous,
+    is_frame_used,
+    get_usable_frames_amount,
+    get_free_frames_amount,
+    get_allocated_frames_amount,
+    get_frames_limit,
+    FRAME_BYTES_SIZE,
+};
+
+#[cfg(feature = "memory_test")]
+pub use memory_test::{
+    test_memory,
+    get_faulty_ranges_amount,
+    get_faulty_range,
+    FAULTY_RANGES_MAX_AMOUNT,
+};
+
+pub use paging::{
+    load_pagination,
+    map,
+    map_physical,
+    unmap,
+    translate,
+    translate_physical,
+    identity_map,
+    get_page_flags,
+    get_page_table_entry,
+    set_page_table_entry,
+    set_page_flags,
+    enable_write_protection,
+    flush_tlb_entry,
+    flush_tlb,
+    is_pae_enabled,
+    is_no_execute_enabled,
+    map_large_page,
+    are_large_pages_enabled,
+    get_large_page_bytes_size,
+    get_kernel_sections,
+    get_page_tables_amount,
+    get_mapped_pages_amount,
+    PageFlags,
+    PageDirectoryEntry,
+    PageTableEntry,
+    KernelSections,
+    KERNEL_VIRTUAL_BASE,
+};
+
+pub use heap::{
+    initialize_heap,
+    heap_alloc,
+    heap_free,
+    get_heap_size,
+    get_heap_allocated_bytes,
+    KernelHeap,
+    HEAP_START,
+};
+
+#[cfg(feature = "heap_debug")]
+pub use heap_debug::{
+    get_heap_allocations_amount,
+    get_heap_allocation,
+    HeapAllocation,
+};
+
+pub use slab::{
+    initialize_slab_allocator,
+    get_size_cache,
+    slab_alloc,
+    slab_free,
+    get_caches_statistics,
+    get_slab_pages_amount,
+    ObjectCache,
+    ObjectConstructor,
+    CacheStatistics,
+    CACHES_MAX_AMOUNT,
+    OBJECT_MAXIMUM_SIZE,
+};
+
+pub use stack::{
+    initialize_kernel_stacks,
+    switch_to_kernel_stack,
+    is_kernel_stack_guard_page,
+    get_kernel_stacks_bytes_size,
+};
+
+pub use cow::{
+    initialize_copy_on_write,
+    share_page,
+    share_pages,
+    free_page,
+    get_frame_references,
+};
+
+pub use page_fault::{
+    initialize_page_fault_handler,
+    get_page_fault_address,
+};
+
+pub use ata::{
+    initialize_ata,
+    get_ata_sectors_amount,
+    read_sectors,
+    write_sectors,
+    get_ata_error,
+    SECTOR_BYTES_SIZE,
+};
+
+pub use swap::{
+    initialize_swap,
+    create_pageable_area,
+    swap_out_page,
+    handle_page_in,
+    get_swap_slots_amount,
+    get_free_swap_slots_amount,
+    get_swapped_out_pages_amount,
+    get_swapped_in_pages_amount,
+    PAGEABLE_AREAS_MAX_AMOUNT,
+};
+
+pub use memory_statistics::{
+    get_memory_statistics,
+    get_owned_frames_amount,
+    MemoryStatistics,
+    MemoryOwner,
+    MEMORY_OWNERS,
+};
+
+pub use gdt::{
+    initialize_gdt,
+    KERNEL_CODE_SELECTOR,
+    KERNEL_DATA_SELECTOR,
+};
+
 use video::{
     print,
     clear_screen,
@@ -12,7 +319,7 @@ use video::{
 
 use core::mem;
 
-const IDT_START_ADDRESS: u32 = 0x11000;
+const IDT_START_ADDRESS: u32 = 0xC0011000;
 
 /* stores the required values to load the IDT with LIDT
    bits 0 - 15: IDT size
@@ -54,59 +361,7 @@ struct IDTDescriptor {
     base_high: u16,
 }
 
-/* one page directory entry for memory paging;
- * bit 0: present flag, 1 if the page is into memory, 0 if the page is on a hard drive (swap),
- * bit 1: writable, 1 if the page is writable, 0 if the page is read only,
- * bit 2: 1 if the page is used by the kernel, 0 if the page is used from the userland
- * bit 3: caching, 0 for write-back caching, 1 for write-through-cache,
- * bit 4: 0 to disable cache on this page, 1 to enable cache on this page,
- * bit 5: set by the processor (0 if page has not been accessed, 1 if page has been accessed),
- * bit 6: reserved
- * bit 7: page size, 0 for 4KBytes pages, 1 for 4MBytes pages
- * bit 8: ignored
- * bits 9-11: no meaning, can be used for any custom information
- * bits 12-31: page table physical base address
- * */
-#[repr(packed)]
-struct PageDirectoryEntry {
-
-    /* we use u8 instead of multiple booleans as one boolean
-       is one byte long which is too much */
-    properties: u8,
-
-    /* use 24 bits to store the address,
-       even if the address is never more than 20 bits long */
-    base_address_low: u16,
-    base_address_high: u8,
-}
-
-/* one page table entry for memoring paging
- * bit 0: present flag, 1 if the page is into memory, 0 if the page is on a hard drive (swap),
- * bit 1: writable, 1 if the page is writable, 0 if the page is read only,
- * bit 2: 1 if the page is used by the kernel, 0 if the page is used from the userland
- * bits 3-4: reserved
- * bit 5: set by the processor (0 if page has not been accessed, 1 if page has been accessed),
- * bit 6: set by the processor, written, 1 if the page has been written, if not 0
- * bits 7-8: reserved
- * bits 9-11: no meaning, can be used for any custom information
- * bits 12-31: page physical base address
- * */
-#[repr(packed)]
-struct PageTableEntry {
-
-    /* we use u8 instead of multiple booleans as one boolean
-       is one byte long which is too much */
-    properties: u8,
-
-    /* use 24 bits to store the address,
-       even if the address is never more than 20 bits long */
-    base_address_low: u16,
-    base_address_high: u8,
-}
-
 /// General function for any kind of exception/error.
-///
-/// IMPORTANT: must be private in order to return in-memory address when call "halt as *const ()"
 unsafe fn halt() {
     llvm_asm!("hlt");
 }
@@ -121,9 +376,53 @@ pub unsafe fn enable_interrupts() {
     llvm_asm!("sti" :::: "intel");
 }
 
+/// Disables interrupts and returns the previous EFLAGS value,
+/// in order to restore the interrupts state with `restore_interrupts`.
+///
+/// Returns:
+///
+/// the EFLAGS value before interrupts were disabled
+pub unsafe fn save_and_disable_interrupts() -> u32 {
+
+    let mut flags: u32 = 0;
+    llvm_asm!("
+        pushfd
+        pop $0
+        cli
+        " : "=r" (flags) ::: "intel"
+    );
+    flags
+}
+
+/// Enables interrupts again if they were enabled when `save_and_disable_interrupts` was called.
+///
+/// Args:
+///
+/// `flags` - the EFLAGS value returned by `save_and_disable_interrupts`
+pub unsafe fn restore_interrupts(flags: u32) {
+
+    /* bit 9 of EFLAGS is the interrupts flag */
+    const INTERRUPT_FLAG: u32 = 1 << 9;
+    if flags & INTERRUPT_FLAG != 0 {
+        enable_interrupts();
+    }
+}
+
+/// Enables interrupts and halts the CPU until the next interrupt.
+/// The interrupts are only enabled after the instruction following sti,
+/// so no interrupt can occur between sti and hlt: an interrupt raised after
+/// a check done with interrupts disabled always wakes the CPU up.
+pub unsafe fn wait_for_interrupt() {
+    llvm_asm!("
+        sti
+        hlt
+        " :::: "intel"
+    );
+}
+
 /// Handler for the interrupt of a division by zero
 /// (triggered when a division by 0 occured)
-fn handle_division_by_zero() {
+fn handle_division_by_zero(_frame: &mut InterruptFrame) {
 
     clear_screen();
     print(0, "Error: a division by zero occured");
@@ -132,7 +431,7 @@ fn handle_division_by_zero() {
 
 /// Handler for the debug breakpoint interrupt
 /// (triggered when the special instruction 0xCC is met, so int 0x3)
-fn handle_debug_breakpoint() {
+fn handle_debug_breakpoint(_frame: &mut InterruptFrame) {
 
     clear_screen();
     print(0, "Error: debug breakpoint");
@@ -141,7 +440,7 @@ fn handle_debug_breakpoint() {
 
 /// Handler for the overflow interrupt
 /// (triggered when an overflow occured, result of a division bigger than the destination register for instance)
-fn handle_overflow() {
+fn handle_overflow(_frame: &mut InterruptFrame) {
 
     clear_screen();
     print(0, "Error: overflow");
@@ -150,7 +449,7 @@ fn handle_overflow() {
 
 /// Handler of the array index out of range
 /// (triggered when an index tries to access an array location out of the array range)
-fn handle_array_index_out_range() {
+fn handle_array_index_out_range(_frame: &mut InterruptFrame) {
 
     clear_screen();
     print(0, "Error: array index out of range");
@@ -159,21 +458,13 @@ fn handle_array_index_out_range() {
 
 /// Handler of the invalid code instruction
 /// (triggered when the CPU has to execute an instruction that it cannot recognize)
-fn handle_invalid_code_instruction() {
+fn handle_invalid_code_instruction(_frame: &mut InterruptFrame) {
 
     clear_screen();
     print(0, "Error: invalid code instruction");
     unsafe { halt(); };
 }
 
-/// Handler for any unhandled interrupt
-fn handle_unhandled_interrupt() {
-
-    clear_screen();
-    print(0, "Error: unhandled interrupt detected causing double fault");
-    unsafe { halt(); };
-}
-
 /// Loads one IDT descriptor at the given index into the IDT. An IRQ at this index would call the IR at the given address.
 ///
 /// Args:
@@ -201,592 +492,81 @@ fn create_idt_descriptor(
     }
 }
 
-/// Loads the Interrupts Descriptor Table. The function is unsafe as it directly write into memory addresses (we want the IDT to have a specific position, at 0x11000). Loads 256 descriptors.
-pub fn load_idt() {
-
-    const IDT_REGISTER_ADDRESS: u32 = 0x11800;
-    const IDT_DESCRIPTORS_AMOUNT: usize = 256;
-
-    for index in 0..IDT_DESCRIPTORS_AMOUNT {
-        create_idt_descriptor(index, (halt as *const ()) as u32);
-    }
-
-    /* TODO: #121 for now, all the IRQ would trigger the same IR, that simply halts the system;
-       the IR to call should be specific to every IRQ */
-
-    create_idt_descriptor(0, (handle_division_by_zero as *const()) as u32);
-    create_idt_descriptor(3, (handle_debug_breakpoint as *const()) as u32);
-    create_idt_descriptor(4, (handle_overflow as *const()) as u32);
-    create_idt_descriptor(5, (handle_array_index_out_range as *const()) as u32);
-    create_idt_descriptor(6, (handle_invalid_code_instruction as *const()) as u32);
-    create_idt_descriptor(8, (handle_unhandled_interrupt as *const()) as u32);
-
-    unsafe {
-        *(IDT_REGISTER_ADDRESS as *mut IDTRegister) = IDTRegister {
-            limit: (mem::size_of::<IDTDescriptor>() * IDT_DESCRIPTORS_AMOUNT) as u16,
-            base: IDT_START_ADDRESS as u32,
-        };
-
-        llvm_asm!("lidt ($0)" :: "r" (IDT_REGISTER_ADDRESS));
-    }
-}
-
-/// Indicates if the CPU vendor is Intel (smallOS only works with Intel CPU)
+/// Loads one task gate descriptor at the given index into the IDT. An interrupt at this index
+/// switches to the task of the given TSS (the current state is saved into the current TSS).
 ///
-/// Returns:
-///
-/// bool
-pub fn is_intel_cpu() -> bool {
-
-    let mut vendor_name_first_part: u32 = 0;
-
-    /* if eax=0, cpuid stores the vendor name
-       into ebx, ecx and edx; we just check
-       the ebx value as it should be 0x756E6547
-       for Intel */
-    unsafe {
-        llvm_asm!("
-            mov eax, 0
-            cpuid
-            " : "={ebx}"(vendor_name_first_part) ::: "intel"
-        );
-    }
-
-    const INTEL_VENDOR: u32 = 0x756E6547;
-    if vendor_name_first_part == INTEL_VENDOR {
-        return true;
-    }
-
-    return false;
-}
-
-/// Initializes the 8259A PIC (Programmable Interrupt Controller).
-/// Sends the ICW (Initialization Control Word) to the PIC
-/// in order to set it up.
-pub fn initialize_pic() {
-
-    /* send the first ICW with the following properties:
-     * bit 0: set to 1 to considere sending an ICW 4,
-     * bit 1: 0 if cascaded PIC (slave PIC linked with master PIC), 1 if single PIC
-     * bit 2: ignored, 0
-     * bit 3: level triggered or edge triggered interrupts
-     *        - level (1): interrupt keep PIC Interrupt Request line enabled (with current) until the
-     *        interrupt is considered by the CPU (making the line unusuable for others interrupts),
-     *        - edge (0): interrupt is a single current pulse on a line, the line is immediately
-     *        available for other interrupts (too low current pulse might not be detected though)
-     * bit 4: 1 to initialize the PIC, 0 to not initialize the PIC
-     * bits 5 - 7: ignored, 0
-     *
-     * we enable the PIC, with edge triggered mode (we dont need to handle interrupts priority for
-     * now, so there is no problem if one interrupt keeps an interrupt line for a long time,
-     * so we could use level mode; the problem is that the Bochs emulator does not support
-     * PIC level triggered mode), x86 architecture has two PICs, so we enable cascading;
-     *
-     * the primary PIC command port address is 0x20,
-     * the slave PIC command port address is 0xA0
-     * (PIC command port address is used for ICW1) */
-    const PIC_FIRST_ICW: u8 = 0b00010001;
-    unsafe {
-
-        /* master and slave PIC initialization */
-        llvm_asm!("
-            mov al, $0
-            out 0x20, al
-            out 0xA0, al
-            " :: "r" (PIC_FIRST_ICW) :: "intel"
-        );
-    }
-
-    /* send the second ICW (first PIC data port call) with the following properties:
-     * it contains the base index of the interrupt requests.
-     * For now, the IVT is loaded at the address 0x00000,
-     * it contains 32 default interrupt requests, so 32 indices.
-     * so we start to plug the Interrupt Request lines from the PIC
-     * to the IVT from index 32 (0x20), so:
-     * IRQ0 uses interrupt number 0x20,
-     * IRQ1 uses interrupt number 0x21... etc...
-     * IRQ7 uses interrupt number 0x27
-     * the first height indices are set on the master PIC,
-     * the following height indices are set on the slave PIC;
-     *
-     * these indices must be sent to PIC data port address
-     * (0x21 for the primary and 0xA1 for the secondary)
-     *
-     * FIXME: it seems impossible to change the IRQ base index
-     * for both of the master and slave PICs
-     * (they always stay at 0x20 and 0x28 respectively) */
-    const MASTER_PIC_IRQ_BASE_INDEX: u8 = 0x20;
-    const SLAVE_PIC_IRQ_BASE_INDEX: u8 = 0x28;
-    unsafe {
-        /* set the master PIC IRQs base index */
-        llvm_asm!("
-            mov al, $0
-            out 0x21, al
-            " :: "r" (MASTER_PIC_IRQ_BASE_INDEX) :: "intel"
-        );
-
-        /* set the secondary PIC IRQs base index */
-        llvm_asm!("
-            mov al, $0
-            out 0xA1, al
-            " :: "r" (SLAVE_PIC_IRQ_BASE_INDEX) :: "intel"
-        );
-    }
-
-    /* send the third ICW (second PIC data port call) with the following properties:
-     *
-     * - on the master: indicates which Interrupt Routine IR line
-     *   to use to communicate with the secondary PIC,
-     *   (x86 architecture uses the IR line 2 to connect the master PIC with the secondary PIC)
-     *   bit 0: use IR0,
-     *   bit 1: use IR1,
-     *   bit 2: use IR2,
-     *   ...
-     *   bit 7: use IR7,
-     *
-     * - on the slave: indicates which Interrupt Routine IR line
-     *   to use to communicate with the master PIC,
-     *   (x86 architecture uses the IR line 2 to connect the secondary PIC with the master PIC)
-     *   bits 0-7: IR number (ex: 011b for the third one)
-     *
-     *   IMPORTANT: on the master, the IR line is chosen by setting one specific bit to 1,
-     *   on the slave, the IR line is chosen by setting the IR number on bits 0 to 7 */
-    const MASTER_TO_SECOND_PIC_SELECTOR: u8 = 0b00000100; // third IR line (IR0) so third bit
-    const SECOND_TO_MASTER_PIC_SELECTOR: u8 = 2;
-    unsafe {
-        /* connect the master PIC to the slave PIC */
-        llvm_asm!("
-            mov al, $0
-            out 0x21, al
-            " :: "r" (MASTER_TO_SECOND_PIC_SELECTOR) :: "intel"
-        );
-
-        /* connect the slave PIC to the master PIC */
-        llvm_asm!("
-            mov al, $0
-            out 0xA1, al
-            " :: "r" (SECOND_TO_MASTER_PIC_SELECTOR) :: "intel"
-        );
-    }
-
-    /* send the fourth ICW (third PIC data port call) with the following properties:
-     * bit 0: PIC mode (1 if 80x86 mode, 0 if 8085 mode),
-     * bit 1: 1 to automatically ends an interrupt after pulse (special mode), 0 for normal mode,
-     * bit 2: specify master PIC if PIC buffering is enabled (1 if master, 0 if slave),
-     * bit 3: enable PIC buffering (1 to enable, 0 to disable),
-     * bit 4: enable fully nested mode (special mode when a large amount of PIC is available),
-     * bits 5-7: unused
-     *
-     * we start each PIC (master and slave) in 80x86 mode, without any specific mode,
-     * without fully nested mode, without buffering (we keep things simple for now) */
-    const PIC_FOURTH_ICW: u8 = 0b00000001;
-    unsafe {
-        llvm_asm!("
-            mov al, $0
-            out 0x21, al
-            out 0xA1, al
-            " :: "r" (PIC_FOURTH_ICW) :: "intel"
-        );
-    }
-
-}
-
-/// Increments the ticks amount, called everytime the PIC receives an IRQ from the PIT.
+/// Args:
 ///
-/// Unsafe as it manipulates mutable static.
-unsafe fn increment_ticks() {
-
-    /* ax is the only modified register during the interrupt handler execution */
-    llvm_asm!("push ax" :::: "intel");
-
-    /* increment the ticks amount */
-    *(0x11806 as *mut u32) += 1;
+/// `index` - the index of the IDT descriptor
+/// `tss_selector` - the GDT selector of the TSS of the task
+fn create_task_gate_descriptor(
+    index: usize,
+    tss_selector: u16,
+) {
 
-    /* signal the PIC that the interrupts is finished,
-       this is an interrupt handler, so EFLAGS, CS and EIP
-       have to be popped from the stack before returning
-       to the main code, so we use iretd */
-    llvm_asm!("
-        mov al, 0x20
-        out 0x20, al
-        pop ax
-        iretd
-        " :::: "intel"
+    const IDT_DESCRIPTOR_SIZE: u32 = 8;
+    let descriptor_address = IDT_START_ADDRESS + (
+        IDT_DESCRIPTOR_SIZE * (index as u32)
     );
-}
-
-/// Returns the RAM amount found by the BIOS.
-///
-/// Returns:
-///
-/// RAM amount in bytes
-pub unsafe fn get_ram_amount() -> u32 {
-
-    /* multiplied by 1000 as stage2 stores value in Kbytes */
-    *(0x1180A as *mut u32) * 1000
-}
-
-/// Returns the current ticks amount.
-///
-/// Returns:
-///
-/// current ticks amount
-pub unsafe fn get_ticks_amount() -> u32 {
-    *(0x11806 as *mut u32)
-}
-
-/// Initializes the Programmable Interrupt Timer, starts one of the three counters,
-/// sets the counter reading mode and sets the PIT runner mode
-pub fn initialize_pit() {
-
-    create_idt_descriptor(32, (increment_ticks as *const ()) as u32);
-
-    /* ICW to send to the PIT for initialization:
-       bit 0:
-           - 0: simple mode, binary counting (x86 PCs usually only use binary mode)
-           - 1: BCD mode (Binary Coded Decimal), more complex, no guarantee to work on every architectures,
-       bits 1-3: PIT mode
-           - 000: mode 0 (Interrupt on Terminal Count): starts to count down from a given counter value;
-                  when the counter is equal to 0, the OUT line is set to 1, and remains at 1 until
-                  the counter is manually reset or if a new control word is sent to the PIT
-                  (this mode is useful for unique countdown)
-
-                    +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
-                    |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
-             CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
-
-                                         +-----------------+
-                                         |                 |
-             OUT +-----------------------+                 +--------+
-
-                 ^                       ^                 ^
-                 |                       |                 |
-                 |                       |                 |
-                 +                       +                 +
-
-                ICW                  Usuable as           ICW
-             Counter = 4             an interrupt    or new counter
-
-           - 001: mode 1 (Hardware Triggered One-shot): the OUT line is set to 1 by default,
-                  when a GATE pulse is sent, the counter starts and the OUT line is set to 0;
-                  any GATE pulse resets the counter; OUT is set to 1 only when the countdown
-                  is finished;
-
-                    +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
-                    |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
-             CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
-
-                       +-+         +-+                              +-+
-                       | |         | |                              | |
-            GATE +-----+ +---------+ +------------------------------+ +---+
-
-
-             OUT +-----+                                   +--------+
-                       |                                   |        |
-                       +-----------------------------------+        +-----+
-
-                       ^           ^                       ^
-                       |           |                       |
-                       |           |                       |
-                       +           +                       +
-                      C=4         C=4                 Usuable as
-                                                      an interrupt
-
-           - 010: mode 3 (Rate Generator): the countdown goes down from its initial value
-             to 1 and repeats, until GATE is set to 0; everytime the counter reaches 1,
-             OUT is set to 0 and immediately set back to 1 (usuable as an interrupt)
-
-                    +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
-                    |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
-             CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
-
-                          +--------------------------------------------+
-                          |                                            |
-            GATE +--------+                                            +------------------
-
-                          +-----------------+ +---------------+ +---------------+
-                          |                 | |               | |               |
-             OUT +--------+                 +-+               +-+               +---------
-
-                  Count = 3
-
-           - 011: mode 3 (Square Wave Generator): exactly the same as the mode 2,
-             except that OUT is set to 1 and to 0 half time of the counter value;
-
-                   +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
-                   |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
-            CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
-
-                            +-----------+           +-----------+           +-----------+
-                            |           |           |           |           |           |
-            OUT +-----------+           +-----------+           +-----------+           +
-
-                  Count = 4
-
-          - 100: mode 4 (Software Triggered Strobe)
-          - 101: mode 5 (Hardware Triggered Strobe)
-          (software and hardware triggered strobes are similar and they depend on the GATE value)
-
-        bits 4-5: read/load mode of the 2 bytes counter
-          - 00: lock the counter for reading,
-          - 01: read the high byte only,
-          - 10: read the low byte only,
-          - 11: read the low byte then the high byte
-
-        bits 6-7: counter selection (PIT has three counters available)
-          - 00: counter 0
-          - 01: counter 1
-          - 10: counter 2
-
-        we start the counter 0, we read and load the low byte first
-        then the low byte when accessing the counter; we use the rate generator,
-        we use simple binary count
-
-        this ICW is sent to the port 0x43, which is the PIT control word port */
-    const PIT_ICW: u8 = 0b00110100;
-    unsafe {
-        llvm_asm!("
-            mov al, $0
-            out 0x43, al
-            " :: "r" (PIT_ICW) :: "intel"
-        );
-    }
-
-    /* we want a frequency of 100 Hz, throw an interrupt every 10 ms,
-       so we divide the default frequency 1193180 by 100 */
-    const COUNT: u16 = 11932;
-    unsafe {
-        llvm_asm!("
-            mov al, $0
-            out 0x40, al
-            " :: "r" ((COUNT & 0xff) as u8) :: "intel"
-        );
-        llvm_asm!("
-            mov al, $0
-            out 0x40, al
-            " :: "r" (((COUNT >> 8) & 0xff) as u8) :: "intel"
-        );
-    }
 
+    /* present (bit 7), privilege level 0 (bits 5 - 6),
+       task gate type (bits 0 - 3 set to 0101), the offset is not used */
     unsafe {
-        /* initialize timer at value 0 */
-        *(0x11806 as *mut u16) = 0;
-    }
-}
-
-/// Represents one memory area information item. Stage2 used BIOS interrupt in order to set those values in memory. It considered the base address 64 bits long, the length 64 bits long and the type 32 bits long. As smallOS only uses 16MBytes of RAM, we reduce the base address to 32 bits (it will never exceed this value), same for the length, and simply use a boolean for the type (we only want to know if the area is usuable or not).
-#[derive(Copy, Clone)]
-pub struct MemoryArea {
-    base_address: u32,
-    length: u32,
-    usuable: bool,
-}
-
-impl MemoryArea {
-
-    /// Constructor of a memory area.
-    ///
-    /// Returns:
-    ///
-    /// a new memory area with default values
-    pub fn new() -> MemoryArea {
-        MemoryArea {
-            base_address: 0,
-            length: 0,
-            usuable: false,
-        }
-    }
-
-    /// Getter of the memory area base address.
-    ///
-    /// Returns:
-    ///
-    /// the base address
-    pub fn get_base_address(&self) -> u32 {
-        self.base_address
-    }
-
-    /// Getter of the memory area length.
-    ///
-    /// Returns:
-    ///
-    /// the length
-    pub fn get_length(&self) -> u32 {
-        self.length
-    }
-
-    /// Indicates if the memory area is usuable or not.
-    ///
-    /// Returns:
-    ///
-    /// true if the memory area is usuable
-    pub fn is_usuable(&self) -> bool {
-        self.usuable
-    }
-
-    /// Sets the base address.
-    ///
-    /// Args:
-    ///
-    /// `base_address` - the memory area base address
-    fn set_base_address(&mut self, base_address: u32) {
-        self.base_address = base_address;
-    }
-
-    /// Sets the length.
-    ///
-    /// Args:
-    ///
-    /// `length` - the memory area length
-    fn set_length(&mut self, length: u32) {
-        self.length = length;
-    }
-
-    /// Indicates if the memory area is usuable.
-    ///
-    /// Args:
-    ///
-    /// `usuable` - set to true if the memory area is usuable
-    fn set_is_usuable(&mut self, usuable: bool) {
-        self.usuable = usuable;
+        *((descriptor_address) as *mut IDTDescriptor) = IDTDescriptor {
+            base_low: 0,
+            selector: tss_selector,
+            unused: 0,
+            flags: 0b10000101,
+            base_high: 0,
+        };
     }
 }
 
-/// Returns entries of the memory map. It returns an array of the ten first detected memory areas. These areas have been loaded by Stage2. As smallOS has 16Mbytes of RAM, there is almost no risk to have more than ten entries.
-///
-/// # Returns:
-///
-/// array of memory areas, the ten first memory areas
-pub fn get_memory_map() -> [MemoryArea; 10] {
-
-    const MEMORY_AREAS_MAX_AMOUNT: usize = 10;
-    let mut areas = [
-        MemoryArea::new();
-        MEMORY_AREAS_MAX_AMOUNT
-    ];
-
-    const MEMORY_MAP_BASE_ADDRESS: usize = 0x1180C;
-    let mut offset: usize = MEMORY_MAP_BASE_ADDRESS;
-
-    for area in areas.iter_mut() {
-
-        area.set_base_address( unsafe { *((offset) as *mut u32) } );
-        offset += 8;
+/// Loads the Interrupts Descriptor Table. The function is unsafe as it directly write into memory addresses (we want the IDT to have a specific position, at 0x11000). Loads 256 descriptors, all of them go through the common interrupt dispatch path.
+pub fn load_idt() {
 
-        area.set_length( unsafe { *((offset) as *mut u32) } );
-        offset += 8;
+    const IDT_REGISTER_ADDRESS: u32 = 0xC0011800;
+    const IDT_DESCRIPTORS_AMOUNT: usize = 256;
 
-        let area_type = unsafe { *((offset) as *mut u32) };
-        area.set_is_usuable(area_type == 1);
-        offset += 8;
+    /* every IDT descriptor points to the entry routine of its index,
+       the entry routines call the handler registered for the index */
+    for index in 0..IDT_DESCRIPTORS_AMOUNT {
+        create_idt_descriptor(index, get_interrupt_entry(index));
     }
 
-    areas
-}
+    initialize_interrupt_handlers(handle_unexpected_interrupt);
 
-/// Loads the pages directory.
-///
-/// TODO: should load the pages tables, only load the kernel pages for now,
-/// in order to ensure identity mapping and prevent fault when switching
-pub fn load_pagination() {
-
-    const PAGES_DIRECTORY_ADDRESS: u32 = 0x110000;
-    const PAGES_TABLES_ADDRESS: u32 = 0x111000;
-
-    /* directory entries properties configuration:
-     * bit 0: the page is into the RAM
-     * bit 1: the page is writable,
-     * bit 2: the page is used by the kernel,
-     * bit 3: the cache is disabled on this page,
-     * bit 4: the cache is disabled on this page,
-     * bit 5: the page has not been accessed yet,
-     * bit 6: reserved
-     * bit 7: the page is 4KBytes long
-     *
-     * (check PageDirectoryEntry for details of the properties) */
-    const PAGE_DIRECTORY_ENTRY_PROPERTIES: u8 = 0b00000111;
+    set_interrupt_handler(0, handle_division_by_zero);
+    set_interrupt_handler(3, handle_debug_breakpoint);
+    set_interrupt_handler(4, handle_overflow);
+    set_interrupt_handler(5, handle_array_index_out_range);
+    set_interrupt_handler(6, handle_invalid_code_instruction);
 
     unsafe {
-        *(PAGES_DIRECTORY_ADDRESS as *mut PageDirectoryEntry) = PageDirectoryEntry {
-
-            properties: PAGE_DIRECTORY_ENTRY_PROPERTIES,
-
-            /* fit the 32 bits physical address on 20 bits
-             * (mandatory for the pages directory entries),
-             * physical address of the first page table: 0x111000,
-             * so 0b100010001000000000000 (we remove the 12 low bits),
-             * base address starts at bit 12 into the 32 bits of the entry,
-             * 8 have been used already for the properties, so we shift the 4 remaining bits */
-            base_address_low: (0x111 << 4) as u16,
-            base_address_high: 0,
-        };
-    }
-
-    const IDENTITY_MAPPING_AREA_START: u32 = 0x0;
-    const IDENTITY_MAPPING_AREA_END: u32 = 0x110000;
-
-    let mut physical_address = IDENTITY_MAPPING_AREA_START as u32;
-    let mut page_table_entry_address = PAGES_TABLES_ADDRESS;
-    let mut base_address_low = 0;
-
-    /* directory entries properties configuration:
-     * bit 0: the page is into the RAM
-     * bit 1: the page is writable,
-     * bit 2: the page is used by the kernel,
-     * bits 3-4: reserved,
-     * bit 5: set by the processor if the page has been accessed or not,
-     * bit 6: set by the processor if the page has been written or not,
-     * bits 7-8: reserved
-     *
-     * (check PageTableEntry for details of the properties) */
-    const PAGE_TABLE_ENTRY_PROPERTIES: u8 = 0b00000111;
-
-    while physical_address != IDENTITY_MAPPING_AREA_END {
-
-        unsafe {
-            *(page_table_entry_address as *mut PageTableEntry) = PageTableEntry {
-
-                properties: PAGE_TABLE_ENTRY_PROPERTIES,
-
-                /* fit the 32 bits physical address on 20 bits
-                 * (mandatory for the pages directory entries),
-                 * base address starts at bit 12 into the 32 bits of the entry,
-                 * 8 have been used already for the properties, so we shift the 4 remaining bits */
-                base_address_low: (base_address_low << 4) as u16,
-                base_address_high: 0 as u8,
-            };
+        *(IDT_REGISTER_ADDRESS as *mut IDTRegister) = IDTRegister {
+            limit: (mem::size_of::<IDTDescriptor>() * IDT_DESCRIPTORS_AMOUNT) as u16,
+            base: IDT_START_ADDRESS as u32,
         };
 
-        const PAGE_FRAME_BYTES_SIZE: u32 = 4096;
-        physical_address += PAGE_FRAME_BYTES_SIZE;
-
-        page_table_entry_address += 4;
-        base_address_low += 1;
+        llvm_asm!("lidt ($0)" :: "r" (IDT_REGISTER_ADDRESS));
     }
+}
 
-    /* set the pages directory started address (CR3)
-       and enable pagination (bit 31 of CR0) */
-
-    unsafe {
-        llvm_asm!("
-            mov eax, $0
-            mov cr3, eax
-            " :: "r" (PAGES_DIRECTORY_ADDRESS) :: "intel"
-        );
+/* the keyboard controller is connected to the IR line 1 of the master PIC */
+const KEYBOARD_IRQ: u8 = 1;
 
-        llvm_asm!("
-            mov eax, cr0
-            or eax, 0x80000000
-            mov cr0, eax
-            " :::: "intel"
-        );
-    };
-}
+/* kernel global variables (check README.md):
+   the last received scan code,
+   the address of the function called for every scan code (0 if none) */
+const LAST_SCAN_CODE_ADDRESS: u32 = 0xC001301C;
+const SCAN_CODE_HANDLER_ADDRESS: u32 = 0xC0013020;
 
 /// Interrupt routine for any keyboard action.
-fn handle_keyboard_interrupt() {
-
-    unsafe { llvm_asm!("push ax" :::: "intel"); };
+///
+/// Args:
+///
+/// `_frame` - the interrupted context
+fn handle_keyboard_interrupt(_frame: &mut InterruptFrame) {
 
     let mut status_register: u8 = 0;
 
@@ -832,28 +612,76 @@ fn handle_keyboard_interrupt() {
 
     if (status_register & OUTPUT_BUFFER_FULL) != OUTPUT_BUFFER_FULL {
 
-        unsafe {
-            llvm_asm!("
-                pop ax
-                iretd
-                " :::: "intel"
-            );
-        };
+        send_eoi(KEYBOARD_IRQ);
+        return;
     }
 
-    /* TODO: get the keyboard action */
-
+    /* the scan code must be read from the keyboard controller output buffer
+       (port 0x60) in order to receive the next keyboard interrupts;
+       the scan code handling is deferred and runs with interrupts enabled */
+    let mut scan_code: u8 = 0;
     unsafe {
         llvm_asm!("
-            pop ax
-            iretd
-            " :::: "intel"
+            in al, 0x60
+            " : "={al}"(scan_code) ::: "intel"
         );
     };
+
+    defer_work(handle_scan_code, scan_code as u32);
+
+    send_eoi(KEYBOARD_IRQ);
+}
+
+/// Handles one scan code received from the keyboard (deferred work of the keyboard interrupt).
+///
+/// Args:
+///
+/// `scan_code` - the received scan code
+fn handle_scan_code(scan_code: u32) {
+
+    /* TODO: get the keyboard action, only keeps the last scan code for now */
+    unsafe { *(LAST_SCAN_CODE_ADDRESS as *mut u8) = scan_code as u8; }
+
+    let handler_address = unsafe { *(SCAN_CODE_HANDLER_ADDRESS as *const u32) };
+    if handler_address != 0 {
+        let handler: fn(u8) = unsafe { mem::transmute(handler_address as usize) };
+        handler(scan_code as u8);
+    }
+}
+
+/// Sets the function called for every scan code received from the keyboard.
+/// The function is called from the deferred work of the keyboard interrupt
+/// (so with interrupts enabled).
+///
+/// Args:
+///
+/// `handler` - the function to call with the scan code
+pub fn set_scan_code_handler(handler: fn(u8)) {
+    unsafe { *(SCAN_CODE_HANDLER_ADDRESS as *mut u32) = (handler as *const ()) as u32; }
+}
+
+/// Returns the last scan code received from the keyboard.
+///
+/// Returns:
+///
+/// the last scan code, 0 if no key has been pressed yet
+pub fn get_last_scan_code() -> u8 {
+    unsafe { *(LAST_SCAN_CODE_ADDRESS as *const u8) }
 }
 
-/// Initializes the keyboard, add one IDT entry in order to handle keyboard interrupts.
+/// Initializes the keyboard, add one IDT entry in order to handle keyboard interrupts
+/// and unmask the keyboard IRQ line.
 pub fn initialize_keyboard() {
 
-    create_idt_descriptor(33, (handle_keyboard_interrupt as *const ()) as u32);
+    unsafe {
+        *(LAST_SCAN_CODE_ADDRESS as *mut u8) = 0;
+        *(SCAN_CODE_HANDLER_ADDRESS as *mut u32) = 0;
+    }
+
+    set_interrupt_handler(
+        get_irq_vector(KEYBOARD_IRQ) as usize,
+        handle_keyboard_interrupt,
+    );
+
+    unmask_irq(KEYBOARD_IRQ);
 }
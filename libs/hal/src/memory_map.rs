@@ -0,0 +1,347 @@
+//! Memory map (from the BIOS E820 entries loaded by Stage2)
+//!
+//! The BIOS entries might overlap, be unsorted or be split into several adjacent entries
+//! of the same type. The memory map returned to the kernel is sanitized: sorted by base address,
+//! without overlap (the most restrictive type is kept for overlapping parts), and adjacent
+//! areas of the same type are merged.
+
+/* kernel global variables (check README.md):
+   the amount of entries loaded by Stage2,
+   the entries loaded by Stage2 */
+const MEMORY_MAP_ENTRIES_AMOUNT_ADDRESS: u32 = 0xC001180A;
+const MEMORY_MAP_ENTRIES_ADDRESS: u32 = 0xC001180C;
+
+/* Stage2 loads 128 entries at most */
+pub const MEMORY_AREAS_MAX_AMOUNT: usize = 128;
+
+/// The type of one memory area, as returned by the BIOS.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MemoryAreaType {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Bad,
+}
+
+impl MemoryAreaType {
+
+    /// Returns the type of the given BIOS type code, unknown codes are considered as reserved.
+    ///
+    /// Args:
+    ///
+    /// `code` - the E820 type code
+    ///
+    /// Returns:
+    ///
+    /// the memory area type
+    fn from_code(code: u32) -> MemoryAreaType {
+
+        match code {
+            1 => MemoryAreaType::Usable,
+            3 => MemoryAreaType::AcpiReclaimable,
+            4 => MemoryAreaType::AcpiNvs,
+            5 => MemoryAreaType::Bad,
+            _ => MemoryAreaType::Reserved,
+        }
+    }
+
+    /// Returns the priority of the type when two areas overlap (the highest one is kept).
+    ///
+    /// Returns:
+    ///
+    /// the type priority
+    fn get_priority(&self) -> u8 {
+
+        match *self {
+            MemoryAreaType::Usable => 0,
+            MemoryAreaType::AcpiReclaimable => 1,
+            MemoryAreaType::AcpiNvs => 2,
+            MemoryAreaType::Reserved => 3,
+            MemoryAreaType::Bad => 4,
+        }
+    }
+
+    /// Returns the name of the type.
+    ///
+    /// Returns:
+    ///
+    /// the type name
+    pub fn get_name(&self) -> &'static str {
+
+        match *self {
+            MemoryAreaType::Usable => "Usuable",
+            MemoryAreaType::Reserved => "Reserved",
+            MemoryAreaType::AcpiReclaimable => "ACPI reclaimable",
+            MemoryAreaType::AcpiNvs => "ACPI NVS",
+            MemoryAreaType::Bad => "Bad",
+        }
+    }
+}
+
+/// Represents one memory area information item.
+#[derive(Copy, Clone)]
+pub struct MemoryArea {
+    base_address: u64,
+    length: u64,
+    area_type: MemoryAreaType,
+}
+
+/// One entry loaded by Stage2: the base address 64 bits long, the length 64 bits long,
+/// the type 32 bits long and the ACPI 3.0 extended attributes 32 bits long.
+#[repr(C, packed)]
+struct MemoryMapEntry {
+    base_address: u64,
+    length: u64,
+    area_type: u32,
+    attributes: u32,
+}
+
+impl MemoryArea {
+
+    /// Constructor of a memory area.
+    ///
+    /// Returns:
+    ///
+    /// a new memory area with default values
+    pub fn new() -> MemoryArea {
+        MemoryArea {
+            base_address: 0,
+            length: 0,
+            area_type: MemoryAreaType::Reserved,
+        }
+    }
+
+    /// Getter of the memory area base address.
+    ///
+    /// Returns:
+    ///
+    /// the base address
+    pub fn get_base_address(&self) -> u64 {
+        self.base_address
+    }
+
+    /// Getter of the memory area length.
+    ///
+    /// Returns:
+    ///
+    /// the length
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns the address following the memory area.
+    ///
+    /// Returns:
+    ///
+    /// the end address (excluded)
+    pub fn get_end_address(&self) -> u64 {
+        self.base_address + self.length
+    }
+
+    /// Getter of the memory area type.
+    ///
+    /// Returns:
+    ///
+    /// the type
+    pub fn get_type(&self) -> MemoryAreaType {
+        self.area_type
+    }
+
+    /// Indicates if the memory area is usuable or not.
+    ///
+    /// Returns:
+    ///
+    /// true if the memory area is usuable
+    pub fn is_usuable(&self) -> bool {
+        self.area_type == MemoryAreaType::Usable
+    }
+}
+
+/// The sanitized memory map.
+pub struct MemoryMap {
+    areas: [MemoryArea; MEMORY_AREAS_MAX_AMOUNT],
+    amount: usize,
+}
+
+impl MemoryMap {
+
+    /// Returns the memory areas, sorted by base address.
+    ///
+    /// Returns:
+    ///
+    /// the memory areas
+    pub fn get_areas(&self) -> &[MemoryArea] {
+        &self.areas[0..self.amount]
+    }
+
+    /// Returns the total amount of usable memory.
+    ///
+    /// Returns:
+    ///
+    /// the usable memory amount in bytes
+    pub fn get_usable_amount(&self) -> u64 {
+
+        self.get_areas()
+            .iter()
+            .filter(|area| area.is_usuable())
+            .fold(0, |total, area| total + area.get_length())
+    }
+
+    /// Appends an area at the end of the map, merges it with the last area
+    /// if they are adjacent and have the same type.
+    ///
+    /// Args:
+    ///
+    /// `base_address` - the area base address (after the last area end)
+    /// `end_address` - the area end address (excluded)
+    /// `area_type` - the area type
+    fn push(&mut self, base_address: u64, end_address: u64, area_type: MemoryAreaType) {
+
+        if self.amount != 0 {
+
+            let last = &mut self.areas[self.amount - 1];
+
+            if last.get_end_address() == base_address && last.area_type == area_type {
+                last.length = end_address - last.base_address;
+                return;
+            }
+        }
+
+        /* the areas after the maximum amount are ignored */
+        if self.amount == MEMORY_AREAS_MAX_AMOUNT {
+            return;
+        }
+
+        self.areas[self.amount] = MemoryArea {
+            base_address: base_address,
+            length: end_address - base_address,
+            area_type: area_type,
+        };
+
+        self.amount += 1;
+    }
+}
+
+/// Returns the entries loaded by Stage2, empty entries and entries marked
+/// as invalid by their extended attributes are ignored.
+///
+/// Args:
+///
+/// `entries` - the array to fill
+///
+/// Returns:
+///
+/// the amount of entries
+fn read_entries(entries: &mut [MemoryArea; MEMORY_AREAS_MAX_AMOUNT]) -> usize {
+
+    let mut entries_amount = unsafe { *(MEMORY_MAP_ENTRIES_AMOUNT_ADDRESS as *const u16) } as usize;
+    if entries_amount > MEMORY_AREAS_MAX_AMOUNT {
+        entries_amount = MEMORY_AREAS_MAX_AMOUNT;
+    }
+
+    /* bit 0 of the extended attributes: the entry must be ignored if not set */
+    const ENTRY_VALID_ATTRIBUTE: u32 = 1;
+
+    let mut amount: usize = 0;
+
+    for index in 0..entries_amount {
+
+        let address = MEMORY_MAP_ENTRIES_ADDRESS + (index as u32) * 24;
+        let entry = unsafe { &*(address as *const MemoryMapEntry) };
+
+        let base_address = entry.base_address;
+        let length = entry.length;
+
+        if length == 0 || entry.attributes & ENTRY_VALID_ATTRIBUTE == 0 {
+            continue;
+        }
+
+        entries[amount] = MemoryArea {
+            base_address: base_address,
+
+            /* the area cannot exceed the 64 bits addresses space */
+            length: if base_address.checked_add(length).is_none() {
+                0u64.wrapping_sub(base_address)
+            } else {
+                length
+            },
+
+            area_type: MemoryAreaType::from_code(entry.area_type),
+        };
+
+        amount += 1;
+    }
+
+    amount
+}
+
+/// Returns the sanitized memory map, built from the entries loaded by Stage2.
+///
+/// The map is cut at every start and end address of the entries: every resulting part
+/// has the most restrictive type of the entries containing it (parts without entry are ignored).
+///
+/// Returns:
+///
+/// the memory map
+pub fn get_memory_map() -> MemoryMap {
+
+    let mut entries = [MemoryArea::new(); MEMORY_AREAS_MAX_AMOUNT];
+    let entries_amount = read_entries(&mut entries);
+    let entries = &entries[0..entries_amount];
+
+    /* every start and end address, sorted (insertion sort, there are only a few entries) */
+    let mut boundaries = [0u64; MEMORY_AREAS_MAX_AMOUNT * 2];
+    let mut boundaries_amount: usize = 0;
+
+    for entry in entries.iter() {
+
+        for &boundary in [entry.get_base_address(), entry.get_end_address()].iter() {
+
+            let mut position = boundaries_amount;
+            while position > 0 && boundaries[position - 1] > boundary {
+                boundaries[position] = boundaries[position - 1];
+                position -= 1;
+            }
+
+            boundaries[position] = boundary;
+            boundaries_amount += 1;
+        }
+    }
+
+    let mut map = MemoryMap {
+        areas: [MemoryArea::new(); MEMORY_AREAS_MAX_AMOUNT],
+        amount: 0,
+    };
+
+    for index in 1..boundaries_amount {
+
+        let start = boundaries[index - 1];
+        let end = boundaries[index];
+
+        if start == end {
+            continue;
+        }
+
+        let area_type = entries
+            .iter()
+            .filter(|entry| entry.get_base_address() <= start && entry.get_end_address() >= end)
+            .map(|entry| entry.get_type())
+            .max_by_key(|area_type| area_type.get_priority());
+
+        if let Some(area_type) = area_type {
+            map.push(start, end, area_type);
+        }
+    }
+
+    map
+}
+
+/// Returns the total amount of usable RAM, computed from the memory map.
+///
+/// Returns:
+///
+/// usable RAM amount in bytes
+pub fn get_ram_amount() -> u64 {
+    get_memory_map().get_usable_amount()
+}
@@ -0,0 +1,162 @@
+//! Physical memory statistics
+//!
+//! Gathers the page frames allocator statistics, the kernel heap usage, the amount
+//! of paging structures, and the amount of page frames owned by every part of the system
+//! (kernel image, IDT and kernel global variables, page tables, heap, slabs, stacks, userland).
+//! The owners are computed from the mappings, so the frames shared by several mappings
+//! (copy-on-write) are counted once per mapping.
+
+use frames::{
+    get_usable_frames_amount,
+    get_free_frames_amount,
+    get_allocated_frames_amount,
+    FRAME_BYTES_SIZE,
+};
+
+use paging::{
+    get_kernel_sections,
+    get_page_tables_amount,
+    get_mapped_pages_amount,
+    KERNEL_VIRTUAL_BASE,
+};
+
+use heap::{
+    get_heap_size,
+    get_heap_allocated_bytes,
+};
+
+use slab::get_slab_pages_amount;
+
+use stack::get_kernel_stacks_bytes_size;
+
+/* the reserved physical areas containing the IDT and the kernel global variables
+   (start address, end address excluded), check the frames allocator reserved areas:
+   - IVT, BIOS data, boot sector, stage2, root directory, FAT, IDT and kernel global variables,
+   - kernel timers and frames bitmap */
+const KERNEL_GLOBALS_AREAS: [(u32, u32); 2] = [
+    (0x0, 0x15000),
+    (0x20000, 0x60000),
+];
+
+/// The owner of page frames.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MemoryOwner {
+    KernelImage,
+    KernelGlobals,
+    PageTables,
+    Heap,
+    Slabs,
+    Stacks,
+    User,
+    Other,
+}
+
+/* every owner, in display order */
+pub const MEMORY_OWNERS: [MemoryOwner; 8] = [
+    MemoryOwner::KernelImage,
+    MemoryOwner::KernelGlobals,
+    MemoryOwner::PageTables,
+    MemoryOwner::Heap,
+    MemoryOwner::Slabs,
+    MemoryOwner::Stacks,
+    MemoryOwner::User,
+    MemoryOwner::Other,
+];
+
+impl MemoryOwner {
+
+    /// Returns the name of the owner.
+    ///
+    /// Returns:
+    ///
+    /// the owner name
+    pub fn get_name(&self) -> &'static str {
+
+        match *self {
+            MemoryOwner::KernelImage => "Kernel image",
+            MemoryOwner::KernelGlobals => "IDT and globals",
+            MemoryOwner::PageTables => "Page tables",
+            MemoryOwner::Heap => "Kernel heap",
+            MemoryOwner::Slabs => "Slabs",
+            MemoryOwner::Stacks => "Kernel stacks",
+            MemoryOwner::User => "Userland",
+            MemoryOwner::Other => "Other",
+        }
+    }
+}
+
+/// The physical memory statistics at a given time.
+#[derive(Copy, Clone)]
+pub struct MemoryStatistics {
+    pub usable_frames: u32,
+    pub used_frames: u32,
+    pub free_frames: u32,
+    pub heap_size: u32,
+    pub heap_allocated_bytes: u32,
+    pub page_tables_amount: u32,
+}
+
+/// Returns the current physical memory statistics.
+///
+/// Returns:
+///
+/// the frames amounts (reserved frames excluded), the heap usage and the amount of paging structures
+pub fn get_memory_statistics() -> MemoryStatistics {
+
+    MemoryStatistics {
+        usable_frames: get_usable_frames_amount(),
+        used_frames: get_allocated_frames_amount(),
+        free_frames: get_free_frames_amount(),
+        heap_size: get_heap_size(),
+        heap_allocated_bytes: get_heap_allocated_bytes(),
+        page_tables_amount: get_page_tables_amount(),
+    }
+}
+
+/// Returns the amount of page frames owned by the given owner.
+///
+/// Args:
+///
+/// `owner` - the frames owner
+///
+/// Returns:
+///
+/// the frames amount; the kernel image and the kernel globals are reserved frames,
+/// the other owners share the allocated frames (`Other` is what remains of the allocated frames:
+/// copy-on-write references table, pageable areas of the kernel...)
+pub fn get_owned_frames_amount(owner: MemoryOwner) -> u32 {
+
+    match owner {
+        MemoryOwner::KernelImage => {
+            let sections = get_kernel_sections();
+            (sections.data.1 - sections.text.0) / FRAME_BYTES_SIZE
+        },
+        MemoryOwner::KernelGlobals => {
+            KERNEL_GLOBALS_AREAS
+                .iter()
+                .map(|&(start, end)| (end - start) / FRAME_BYTES_SIZE)
+                .fold(0, |total, amount| total + amount)
+        },
+        MemoryOwner::PageTables => get_page_tables_amount(),
+        MemoryOwner::Heap => get_heap_size() / FRAME_BYTES_SIZE,
+        MemoryOwner::Slabs => get_slab_pages_amount(),
+        MemoryOwner::Stacks => get_kernel_stacks_bytes_size() / FRAME_BYTES_SIZE,
+        MemoryOwner::User => get_mapped_pages_amount(0, KERNEL_VIRTUAL_BASE),
+        MemoryOwner::Other => {
+
+            const ALLOCATED_FRAMES_OWNERS: [MemoryOwner; 5] = [
+                MemoryOwner::PageTables,
+                MemoryOwner::Heap,
+                MemoryOwner::Slabs,
+                MemoryOwner::Stacks,
+                MemoryOwner::User,
+            ];
+
+            ALLOCATED_FRAMES_OWNERS
+                .iter()
+                .fold(get_allocated_frames_amount(), |remaining, owner| {
+                    remaining.saturating_sub(get_owned_frames_amount(*owner))
+                })
+        },
+    }
+}
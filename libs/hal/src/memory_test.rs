@@ -0,0 +1,415 @@
+//! Boot memory test (`memory_test` feature)
+//!
+//! Tests the usable memory areas of the memory map before the page frames allocator
+//! takes them (the reserved areas of the allocator are in use, so they are not tested).
+//! Every page frame is mapped at the test page without cache, so the RAM itself is tested:
+//!  - walking ones: every bit of every word is set alone, then read back (stuck or shorted data bits),
+//!  - address in address: every word contains its own physical address, then its complement;
+//!    the whole memory is written before it is checked (address lines faults, aliased areas),
+//!  - moving inversions: the memory is filled with a pattern, every word is checked and inverted
+//!    from the lowest to the highest address, then checked and restored from the highest
+//!    to the lowest address (faults depending on the neighbour cells).
+//!
+//! The page frames that fail one test are gathered into faulty ranges,
+//! the page frames allocator reserves them when it is initialized.
+
+use core::ptr::{
+    read_volatile,
+    write_volatile,
+};
+
+use memory_map::{
+    get_memory_map,
+    MemoryArea,
+    MemoryMap,
+};
+
+use frames::{
+    FRAME_BYTES_SIZE,
+    RESERVED_AREAS,
+};
+
+use paging::{
+    initialize_paging_flags,
+    map,
+    unmap,
+    PageFlags,
+};
+
+/* kernel global variables (check README.md):
+   the amount of faulty ranges,
+   the faulty ranges (start and end physical addresses) */
+const FAULTY_RANGES_AMOUNT_ADDRESS: u32 = 0xC00130E0;
+const FAULTY_RANGES_ADDRESS: u32 = 0xC0013780;
+
+pub const FAULTY_RANGES_MAX_AMOUNT: u32 = 16;
+
+/* virtual page where every tested page frame is mapped
+   (into the kernel page table, after the kernel mapping end, before the PAE build page) */
+const MEMORY_TEST_PAGE_ADDRESS: u32 = 0xC03FE000;
+
+/* amount of frames of the 32 bits physical addresses space (the only tested frames) */
+const FRAMES_AMOUNT: u64 = 1048576;
+
+const WORDS_PER_FRAME: u32 = FRAME_BYTES_SIZE / 4;
+
+/* the moving inversions patterns (every pattern is followed by its complement) */
+const MOVING_INVERSIONS_PATTERNS: [u32; 2] = [0x00000000, 0x55555555];
+
+/// Returns the page frames entirely into the given area (below 4 GBytes).
+///
+/// Args:
+///
+/// `area` - the memory area
+///
+/// Returns:
+///
+/// the first frame index and the index following the last frame, None if there is no frame
+fn get_area_frames(area: &MemoryArea) -> Option<(u32, u32)> {
+
+    let first_frame = (area.get_base_address() + FRAME_BYTES_SIZE as u64 - 1) /
+        FRAME_BYTES_SIZE as u64;
+    let mut last_frame = area.get_end_address() / FRAME_BYTES_SIZE as u64;
+
+    if last_frame > FRAMES_AMOUNT {
+        last_frame = FRAMES_AMOUNT;
+    }
+
+    if first_frame >= last_frame {
+        return None;
+    }
+
+    Some((first_frame as u32, last_frame as u32))
+}
+
+/// Indicates if the given frame is into a reserved area of the page frames allocator.
+///
+/// Args:
+///
+/// `frame` - the frame index
+///
+/// Returns:
+///
+/// true if the frame is in use (it must not be tested)
+fn is_reserved_frame(frame: u32) -> bool {
+
+    let address = frame * FRAME_BYTES_SIZE;
+    RESERVED_AREAS.iter().any(|&(start, end)| address >= start && address < end)
+}
+
+/// Maps every tested page frame at the test page one after the other,
+/// calls the given function for every frame. Stops if a frame cannot be mapped
+/// (the remaining frames are not tested).
+///
+/// Args:
+///
+/// `memory_map` - the memory map
+/// `descending` - true to browse the frames from the highest to the lowest address
+/// `function` - the function called with the physical address of the mapped frame
+fn for_each_frame<F: FnMut(u32)>(memory_map: &MemoryMap, descending: bool, mut function: F) {
+
+    let areas = memory_map.get_areas();
+
+    for position in 0..areas.len() {
+
+        let area = if descending {
+            &areas[areas.len() - 1 - position]
+        } else {
+            &areas[position]
+        };
+
+        if !area.is_usuable() {
+            continue;
+        }
+
+        let (first_frame, last_frame) = match get_area_frames(area) {
+            Some(frames) => frames,
+            None => continue,
+        };
+
+        for index in 0..last_frame - first_frame {
+
+            let frame = if descending {
+                last_frame - 1 - index
+            } else {
+                first_frame + index
+            };
+
+            if is_reserved_frame(frame) {
+                continue;
+            }
+
+            let address = frame * FRAME_BYTES_SIZE;
+
+            /* the test page table is the Stage3 kernel page table, so no page table
+               is allocated (the page frames allocator is not initialized yet) */
+            let mapped = map(
+                MEMORY_TEST_PAGE_ADDRESS,
+                address,
+                PageFlags::WRITABLE | PageFlags::CACHE_DISABLED,
+            );
+
+            if !mapped {
+                return;
+            }
+
+            function(address);
+        }
+    }
+}
+
+/// Reads one word of the frame mapped at the test page.
+///
+/// Args:
+///
+/// `index` - the word index into the frame
+///
+/// Returns:
+///
+/// the word value
+fn read_word(index: u32) -> u32 {
+    unsafe { read_volatile((MEMORY_TEST_PAGE_ADDRESS + index * 4) as *const u32) }
+}
+
+/// Writes one word of the frame mapped at the test page.
+///
+/// Args:
+///
+/// `index` - the word index into the frame
+/// `value` - the word value
+fn write_word(index: u32, value: u32) {
+    unsafe { write_volatile((MEMORY_TEST_PAGE_ADDRESS + index * 4) as *mut u32, value); }
+}
+
+/// Returns the faulty range at the given index.
+///
+/// Args:
+///
+/// `index` - the range index
+///
+/// Returns:
+///
+/// the range start and end (excluded) physical addresses
+fn read_faulty_range(index: u32) -> (u32, u32) {
+
+    let address = FAULTY_RANGES_ADDRESS + index * 8;
+    unsafe { (*(address as *const u32), *((address + 4) as *const u32)) }
+}
+
+/// Updates the faulty range at the given index.
+///
+/// Args:
+///
+/// `index` - the range index
+/// `start` - the range start physical address
+/// `end` - the range end physical address (excluded)
+fn write_faulty_range(index: u32, start: u32, end: u32) {
+
+    let address = FAULTY_RANGES_ADDRESS + index * 8;
+
+    unsafe {
+        *(address as *mut u32) = start;
+        *((address + 4) as *mut u32) = end;
+    }
+}
+
+/// Adds a faulty page frame to the faulty ranges, merges it with an adjacent range.
+/// When every range is used, the last range is extended up to the frame
+/// (more memory is excluded rather than using a faulty frame).
+///
+/// Args:
+///
+/// `address` - the faulty frame physical address
+fn add_faulty_frame(address: u32) {
+
+    let end = address + FRAME_BYTES_SIZE;
+    let amount = get_faulty_ranges_amount();
+
+    for index in 0..amount {
+
+        let (range_start, range_end) = read_faulty_range(index);
+
+        if address >= range_start && end <= range_end {
+            return;
+        }
+
+        if address == range_end {
+            write_faulty_range(index, range_start, end);
+            return;
+        }
+
+        if end == range_start {
+            write_faulty_range(index, address, range_end);
+            return;
+        }
+    }
+
+    if amount == FAULTY_RANGES_MAX_AMOUNT {
+
+        let (range_start, range_end) = read_faulty_range(amount - 1);
+        write_faulty_range(
+            amount - 1,
+            if address < range_start { address } else { range_start },
+            if end > range_end { end } else { range_end },
+        );
+
+        return;
+    }
+
+    write_faulty_range(amount, address, end);
+    unsafe { *(FAULTY_RANGES_AMOUNT_ADDRESS as *mut u32) = amount + 1; }
+}
+
+/// Walking ones test: every bit of every word is set alone, then read back.
+///
+/// Args:
+///
+/// `memory_map` - the memory map
+fn test_walking_ones(memory_map: &MemoryMap) {
+
+    const BITS_PER_WORD: u32 = 32;
+
+    for_each_frame(memory_map, false, |address| {
+
+        for index in 0..WORDS_PER_FRAME {
+
+            for bit in 0..BITS_PER_WORD {
+
+                write_word(index, 1 << bit);
+
+                if read_word(index) != 1 << bit {
+                    add_faulty_frame(address);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Address in address test: every word contains its own physical address (or its complement),
+/// the whole memory is written before it is checked.
+///
+/// Args:
+///
+/// `memory_map` - the memory map
+fn test_address_in_address(memory_map: &MemoryMap) {
+
+    for &complement in [0, 0xFFFFFFFF].iter() {
+
+        for_each_frame(memory_map, false, |address| {
+            for index in 0..WORDS_PER_FRAME {
+                write_word(index, (address + index * 4) ^ complement);
+            }
+        });
+
+        for_each_frame(memory_map, false, |address| {
+
+            let faulty = (0..WORDS_PER_FRAME)
+                .any(|index| read_word(index) != (address + index * 4) ^ complement);
+
+            if faulty {
+                add_faulty_frame(address);
+            }
+        });
+    }
+}
+
+/// Moving inversions test: the memory is filled with a pattern, every word is checked
+/// and inverted from the lowest address, then checked and restored from the highest address.
+///
+/// Args:
+///
+/// `memory_map` - the memory map
+fn test_moving_inversions(memory_map: &MemoryMap) {
+
+    for &pattern in MOVING_INVERSIONS_PATTERNS.iter() {
+
+        for_each_frame(memory_map, false, |_| {
+            for index in 0..WORDS_PER_FRAME {
+                write_word(index, pattern);
+            }
+        });
+
+        for_each_frame(memory_map, false, |address| {
+
+            let mut faulty = false;
+
+            for index in 0..WORDS_PER_FRAME {
+                faulty |= read_word(index) != pattern;
+                write_word(index, !pattern);
+            }
+
+            if faulty {
+                add_faulty_frame(address);
+            }
+        });
+
+        for_each_frame(memory_map, true, |address| {
+
+            let mut faulty = false;
+
+            for index in (0..WORDS_PER_FRAME).rev() {
+                faulty |= read_word(index) != !pattern;
+                write_word(index, pattern);
+            }
+
+            if faulty {
+                add_faulty_frame(address);
+            }
+        });
+    }
+}
+
+/// Tests the usable memory (walking ones, address in address, moving inversions)
+/// and records the faulty ranges. Must be called before the page frames allocator
+/// initialization and before `load_pagination` (the test page is into the kernel page table).
+/// The content of the tested memory is lost.
+///
+/// Returns:
+///
+/// the amount of faulty ranges
+pub fn test_memory() -> u32 {
+
+    unsafe { *(FAULTY_RANGES_AMOUNT_ADDRESS as *mut u32) = 0; }
+
+    /* the paging mode flags are read by `map` and `unmap`,
+       they are only set by `load_pagination` otherwise */
+    initialize_paging_flags();
+
+    let memory_map = get_memory_map();
+
+    test_walking_ones(&memory_map);
+    test_address_in_address(&memory_map);
+    test_moving_inversions(&memory_map);
+
+    unmap(MEMORY_TEST_PAGE_ADDRESS);
+
+    get_faulty_ranges_amount()
+}
+
+/// Returns the amount of faulty ranges found by the memory test.
+///
+/// Returns:
+///
+/// the faulty ranges amount
+pub fn get_faulty_ranges_amount() -> u32 {
+    unsafe { *(FAULTY_RANGES_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns one faulty range found by the memory test.
+///
+/// Args:
+///
+/// `index` - the range index
+///
+/// Returns:
+///
+/// the range start and end (excluded) physical addresses, None if the index is out of range
+pub fn get_faulty_range(index: u32) -> Option<(u32, u32)> {
+
+    if index >= get_faulty_ranges_amount() {
+        return None;
+    }
+
+    Some(read_faulty_range(index))
+}
@@ -0,0 +1,91 @@
+//! Page fault handler (vector 14)
+//!
+//! The CPU raises a page fault when a virtual address is not mapped, or when a page is accessed
+//! without the required rights (writing into a read only page for instance).
+//! The faulting address is stored into CR2 and the error code describes the access.
+//! The faults caused by the paging features (copy-on-write, demand paging and swapping)
+//! are resolved, the other faults are fatal.
+
+use video::{
+    print,
+    printi32hex,
+    clear_screen,
+};
+
+use cow::handle_copy_on_write;
+
+use swap::handle_page_in;
+
+use interrupts::{
+    set_interrupt_handler,
+    InterruptFrame,
+};
+
+const PAGE_FAULT_VECTOR: usize = 14;
+
+/* page fault error code:
+   bit 0: 0 if the page is not present, 1 if the access right is not respected,
+   bit 1: 0 for a read access, 1 for a write access,
+   bit 2: 1 if the access comes from the userland,
+   bit 4: 1 if the access is an instruction fetch (execute-disable page) */
+const PROTECTION_VIOLATION: u32 = 1 << 0;
+const WRITE_ACCESS: u32 = 1 << 1;
+const INSTRUCTION_FETCH: u32 = 1 << 4;
+
+/// Returns the virtual address that caused the last page fault.
+///
+/// Returns:
+///
+/// the CR2 value
+pub fn get_page_fault_address() -> u32 {
+
+    let mut address: u32 = 0;
+    unsafe { llvm_asm!("mov $0, cr2" : "=r" (address) ::: "intel", "volatile"); }
+    address
+}
+
+/// Handles the page faults, resolves the copy-on-write faults and maps the demand
+/// and swapped pages, displays the fault and halts the system otherwise.
+///
+/// Args:
+///
+/// `frame` - the interrupted context
+fn handle_page_fault(frame: &mut InterruptFrame) {
+
+    let address = get_page_fault_address();
+    let error_code = frame.error_code;
+
+    if error_code & (PROTECTION_VIOLATION | WRITE_ACCESS) == PROTECTION_VIOLATION | WRITE_ACCESS &&
+        handle_copy_on_write(address) {
+        return;
+    }
+
+    if error_code & PROTECTION_VIOLATION == 0 && handle_page_in(address) {
+        return;
+    }
+
+    clear_screen();
+
+    if error_code & (PROTECTION_VIOLATION | INSTRUCTION_FETCH) ==
+        PROTECTION_VIOLATION | INSTRUCTION_FETCH {
+        print(0, "Error: page fault (execution of a not executable page)");
+    } else {
+        print(0, "Error: page fault");
+    }
+
+    print(160, "Address:");
+    printi32hex(170, address);
+    print(240, "EIP:");
+    printi32hex(250, frame.eip);
+    print(320, "Error code:");
+    printi32hex(332, error_code);
+
+    loop {
+        unsafe { llvm_asm!("hlt" :::: "intel"); }
+    }
+}
+
+/// Installs the page fault handler.
+pub fn initialize_page_fault_handler() {
+    set_interrupt_handler(PAGE_FAULT_VECTOR, handle_page_fault);
+}
@@ -0,0 +1,1525 @@
+//! Virtual memory manager (two levels 32 bits paging or three levels PAE paging)
+//!
+//! Paging is enabled by Stage3: the kernel is linked at 0xC0000000 (higher half),
+//! the physical addresses from 0x0 are mapped from 0xC0000000.
+//!
+//! Stage3 builds two levels tables (entries of 4 bytes). When the CPU supports PAE
+//! (Physical Address Extension), `load_pagination` switches to three levels tables:
+//! a pages directory pointers table of 4 entries, 4 pages directories and the page tables,
+//! with entries of 8 bytes, so page frames above 4 GBytes can be mapped. When the CPU
+//! also supports the execute-disable bit (NX), the pages mapped with `PageFlags::NO_EXECUTE`
+//! cannot be executed. The two levels tables are kept otherwise.
+//!
+//! Large contiguous areas are mapped with large pages (4 MBytes pages with two levels paging
+//! if the CPU supports PSE, 2 MBytes pages with PAE paging): the directory entry maps
+//! the whole area directly, without page table.
+//!
+//! The kernel image is mapped with 4 KBytes pages, with the properties of its sections
+//! (the boundaries are defined by the linker script): the code is read only and executable,
+//! the read only data is not executable, the writable data is not executable (W^X).
+//!
+//! The last entry of the pages directory points to the directory itself (recursive mapping),
+//! so once paging is enabled, every page table is accessible at `0xFFC00000 + index * 4096`
+//! and the directory itself is accessible at `0xFFFFF000`. With PAE, the last 4 entries
+//! of the last directory point to the 4 directories: every page table is accessible
+//! at `0xFF800000 + index * 4096` and the 4 directories are contiguous from `0xFFFFC000`.
+//! Page tables are allocated on demand from the page frames allocator when a page is mapped.
+
+use core::ops::{
+    BitOr,
+    BitAnd,
+    Not,
+};
+
+use core::cmp;
+
+use frames::{
+    alloc_frame,
+    free_frame,
+    get_free_frames_amount,
+    get_frames_limit,
+    FRAME_BYTES_SIZE,
+};
+
+use cpu::{
+    read_msr,
+    write_msr,
+    get_cpu_features,
+    CpuFeatures,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   1 if PAE paging is enabled, 0 for two levels paging,
+   1 if the execute-disable bit is enabled,
+   1 if the large pages are enabled */
+const PAE_ENABLED_ADDRESS: u32 = 0xC00130D4;
+const NO_EXECUTE_ENABLED_ADDRESS: u32 = 0xC00130D5;
+const LARGE_PAGES_ENABLED_ADDRESS: u32 = 0xC00130D6;
+
+/* the kernel is mapped from 0xC0000000, so the directory entry 768
+   contains the page table of the kernel (the directory built by Stage3 is at 0x110000,
+   the first page table is at 0x111000) */
+pub const KERNEL_VIRTUAL_BASE: u32 = 0xC0000000;
+
+/* the first 0x113000 bytes are the low memory, the kernel image,
+   the Stage3 pages directory and the Stage3 first page table
+   (`kernel/linker.ld` checks the kernel image ends before 0x110000) */
+const KERNEL_MAPPING_END: u32 = 0x113000;
+
+/* the kernel sections boundaries (virtual addresses aligned on 4096 bytes),
+   defined by kernel/linker.ld */
+extern {
+    static kernel_text_start: u8;
+    static kernel_text_end: u8;
+    static kernel_rodata_start: u8;
+    static kernel_rodata_end: u8;
+    static kernel_data_start: u8;
+    static kernel_data_end: u8;
+}
+
+/* two levels paging: virtual addresses of the page tables and of the directory
+   (recursive mapping), 1024 entries of 4 bytes per table */
+const RECURSIVE_ENTRY_INDEX: u32 = 1023;
+const PAGE_TABLES_VIRTUAL_ADDRESS: u32 = 0xFFC00000;
+const PAGES_DIRECTORY_VIRTUAL_ADDRESS: u32 = 0xFFFFF000;
+const ENTRIES_PER_TABLE: u32 = 1024;
+
+/* PAE paging: virtual addresses of the page tables and of the 4 directories
+   (recursive mapping), 512 entries of 8 bytes per table; the 4 directories are contiguous,
+   so they are used as one directory of 2048 entries (bits 21-31 of the virtual address),
+   the entries 2044 to 2047 are the recursive entries */
+const PAE_RECURSIVE_ENTRIES_FIRST_INDEX: u32 = 2044;
+const PAE_PAGE_TABLES_VIRTUAL_ADDRESS: u32 = 0xFF800000;
+const PAE_PAGES_DIRECTORIES_VIRTUAL_ADDRESS: u32 = 0xFFFFC000;
+const PAE_ENTRIES_PER_TABLE: u32 = 512;
+const PAE_DIRECTORIES_AMOUNT: u32 = 4;
+
+/* bits 12-51 of an entry: physical address of the page (or page table),
+   only the bits 12-31 are used by two levels paging */
+const ENTRY_ADDRESS_MASK: u64 = 0x000FFFFFFFFFF000;
+
+/* the 32 bits physical addresses space end */
+const PHYSICAL_ADDRESSES_32_BITS_END: u64 = 0x100000000;
+
+/* one directory entry maps 4 MBytes (two levels paging) or 2 MBytes (PAE paging) */
+const LARGE_PAGE_BYTES_SIZE: u32 = 0x400000;
+const PAE_LARGE_PAGE_BYTES_SIZE: u32 = 0x200000;
+
+/// Properties of one pages directory entry or one page table entry.
+///
+/// bit 0: present flag, 1 if the page is into memory, 0 if the page is on a hard drive (swap),
+/// bit 1: writable, 1 if the page is writable, 0 if the page is read only,
+/// bit 2: 1 if the page can be used from the userland, 0 if the page is only used by the kernel,
+/// bit 3: caching, 0 for write-back caching, 1 for write-through caching,
+/// bit 4: 1 to disable cache on this page, 0 to enable cache on this page,
+/// bit 5: set by the processor (0 if page has not been accessed, 1 if page has been accessed),
+/// bit 6: set by the processor (page table entries only), 1 if the page has been written,
+/// bit 7: page size (directory entries only), 0 for 4KBytes pages, 1 for 4MBytes pages,
+/// bit 8: global page (page table entries only, not flushed when CR3 is loaded),
+/// bits 9-11: no meaning, can be used for any custom information,
+/// bit 63: execute-disable (PAE only), 1 if the page cannot be executed
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+
+    pub const PRESENT: PageFlags = PageFlags(1 << 0);
+    pub const WRITABLE: PageFlags = PageFlags(1 << 1);
+    pub const USER: PageFlags = PageFlags(1 << 2);
+    pub const WRITE_THROUGH: PageFlags = PageFlags(1 << 3);
+    pub const CACHE_DISABLED: PageFlags = PageFlags(1 << 4);
+    pub const ACCESSED: PageFlags = PageFlags(1 << 5);
+    pub const DIRTY: PageFlags = PageFlags(1 << 6);
+    pub const PAGE_SIZE: PageFlags = PageFlags(1 << 7);
+    pub const GLOBAL: PageFlags = PageFlags(1 << 8);
+    pub const CUSTOM_0: PageFlags = PageFlags(1 << 9);
+    pub const CUSTOM_1: PageFlags = PageFlags(1 << 10);
+    pub const CUSTOM_2: PageFlags = PageFlags(1 << 11);
+
+    /* ignored (removed from the written entries) if the execute-disable bit is not enabled */
+    pub const NO_EXECUTE: PageFlags = PageFlags(1 << 63);
+
+    /* custom bit 9: the page is shared and read only, it is copied on the first write */
+    pub const COPY_ON_WRITE: PageFlags = PageFlags::CUSTOM_0;
+
+    /* custom bit 10 (not present pages only): the page is into the swap area,
+       the address bits contain the swap slot index */
+    pub const SWAPPED: PageFlags = PageFlags::CUSTOM_1;
+
+    /* custom bit 11 (not present pages only): the page is allocated on the first access */
+    pub const ON_DEMAND: PageFlags = PageFlags::CUSTOM_2;
+
+    /// Returns flags without any property set.
+    ///
+    /// Returns:
+    ///
+    /// the empty flags
+    pub fn empty() -> PageFlags {
+        PageFlags(0)
+    }
+
+    /// Returns the flags of the given entry bits (the address bits are ignored).
+    ///
+    /// Args:
+    ///
+    /// `bits` - the entry bits
+    ///
+    /// Returns:
+    ///
+    /// the flags
+    pub fn from_bits(bits: u64) -> PageFlags {
+        PageFlags(bits & !ENTRY_ADDRESS_MASK)
+    }
+
+    /// Returns the flags bits.
+    ///
+    /// Returns:
+    ///
+    /// the bits
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Indicates if every given flag is set.
+    ///
+    /// Args:
+    ///
+    /// `flags` - the flags to check
+    ///
+    /// Returns:
+    ///
+    /// true if every flag is set
+    pub fn contains(&self, flags: PageFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl BitOr for PageFlags {
+
+    type Output = PageFlags;
+
+    fn bitor(self, flags: PageFlags) -> PageFlags {
+        PageFlags(self.0 | flags.0)
+    }
+}
+
+impl BitAnd for PageFlags {
+
+    type Output = PageFlags;
+
+    fn bitand(self, flags: PageFlags) -> PageFlags {
+        PageFlags(self.0 & flags.0)
+    }
+}
+
+impl Not for PageFlags {
+
+    type Output = PageFlags;
+
+    fn not(self) -> PageFlags {
+        PageFlags(!self.0 & !ENTRY_ADDRESS_MASK)
+    }
+}
+
+/// One pages directory entry: the page table physical address and its properties.
+/// The entry is 4 bytes long with two levels paging and 8 bytes long with PAE paging.
+#[derive(Copy, Clone)]
+pub struct PageDirectoryEntry(u64);
+
+/// One page table entry: the page physical address and its properties.
+/// The entry is 4 bytes long with two levels paging and 8 bytes long with PAE paging.
+///
+/// The custom bits 9-11 are used by the kernel:
+/// bit 9: copy-on-write marker (`PageFlags::COPY_ON_WRITE`), the page is read only
+/// and its page frame is shared, the page is copied when it is written for the first time,
+/// bit 10: swapped page marker (`PageFlags::SWAPPED`), the page is not present
+/// and the bits 12-31 contain the index of its slot into the swap area,
+/// bit 11: demand page marker (`PageFlags::ON_DEMAND`), the page is not present
+/// and a zeroed page frame is mapped when the page is accessed for the first time.
+#[derive(Copy, Clone)]
+pub struct PageTableEntry(u64);
+
+impl PageDirectoryEntry {
+
+    /// Returns the page table physical address.
+    ///
+    /// Returns:
+    ///
+    /// the page table address
+    pub fn get_address(&self) -> u64 {
+        self.0 & ENTRY_ADDRESS_MASK
+    }
+
+    /// Returns the entry properties.
+    ///
+    /// Returns:
+    ///
+    /// the properties
+    pub fn get_flags(&self) -> PageFlags {
+        PageFlags::from_bits(self.0)
+    }
+
+    /// Indicates if the entry points to a page table or maps a large page.
+    ///
+    /// Returns:
+    ///
+    /// true if the present flag is set
+    pub fn is_present(&self) -> bool {
+        self.get_flags().contains(PageFlags::PRESENT)
+    }
+
+    /// Indicates if the entry maps a large page (instead of pointing to a page table).
+    ///
+    /// Returns:
+    ///
+    /// true if the present flag and the page size flag are set
+    pub fn is_large_page(&self) -> bool {
+        self.get_flags().contains(PageFlags::PRESENT | PageFlags::PAGE_SIZE)
+    }
+
+    /// Sets the page table address and the entry properties.
+    ///
+    /// Args:
+    ///
+    /// `address` - the page table physical address (aligned on 4096 bytes)
+    /// `flags` - the entry properties
+    pub fn set(&mut self, address: u64, flags: PageFlags) {
+        self.0 = (address & ENTRY_ADDRESS_MASK) | flags.bits();
+    }
+
+    /// Clears the entry.
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+impl PageTableEntry {
+
+    /// Returns the page physical address.
+    ///
+    /// Returns:
+    ///
+    /// the page address
+    pub fn get_address(&self) -> u64 {
+        self.0 & ENTRY_ADDRESS_MASK
+    }
+
+    /// Returns the entry properties.
+    ///
+    /// Returns:
+    ///
+    /// the properties
+    pub fn get_flags(&self) -> PageFlags {
+        PageFlags::from_bits(self.0)
+    }
+
+    /// Indicates if the page is mapped.
+    ///
+    /// Returns:
+    ///
+    /// true if the present flag is set
+    pub fn is_present(&self) -> bool {
+        self.get_flags().contains(PageFlags::PRESENT)
+    }
+
+    /// Sets the page address and the entry properties.
+    ///
+    /// Args:
+    ///
+    /// `address` - the page physical address (aligned on 4096 bytes)
+    /// `flags` - the entry properties
+    pub fn set(&mut self, address: u64, flags: PageFlags) {
+        self.0 = (address & ENTRY_ADDRESS_MASK) | flags.bits();
+    }
+
+    /// Clears the entry.
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+/// The virtual boundaries of the kernel image sections (start address, end address excluded).
+#[derive(Copy, Clone)]
+pub struct KernelSections {
+    pub text: (u32, u32),
+    pub rodata: (u32, u32),
+    pub data: (u32, u32),
+}
+
+/// Returns the boundaries of the kernel image sections, from the linker script symbols.
+///
+/// Returns:
+///
+/// the code, read only data and writable data (data and bss) boundaries
+pub fn get_kernel_sections() -> KernelSections {
+
+    unsafe {
+        KernelSections {
+            text: (
+                &kernel_text_start as *const u8 as u32,
+                &kernel_text_end as *const u8 as u32,
+            ),
+            rodata: (
+                &kernel_rodata_start as *const u8 as u32,
+                &kernel_rodata_end as *const u8 as u32,
+            ),
+            data: (
+                &kernel_data_start as *const u8 as u32,
+                &kernel_data_end as *const u8 as u32,
+            ),
+        }
+    }
+}
+
+/// Indicates if PAE paging is enabled.
+///
+/// Returns:
+///
+/// true if PAE paging is enabled, false for two levels paging
+pub fn is_pae_enabled() -> bool {
+    unsafe { *(PAE_ENABLED_ADDRESS as *const u8) == 1 }
+}
+
+/// Indicates if the execute-disable bit is enabled (PAE paging only).
+///
+/// Returns:
+///
+/// true if the pages mapped with `PageFlags::NO_EXECUTE` cannot be executed
+pub fn is_no_execute_enabled() -> bool {
+    unsafe { *(NO_EXECUTE_ENABLED_ADDRESS as *const u8) == 1 }
+}
+
+/// Indicates if the large pages are enabled.
+///
+/// Returns:
+///
+/// true if `map_large_page` can be used
+pub fn are_large_pages_enabled() -> bool {
+    unsafe { *(LARGE_PAGES_ENABLED_ADDRESS as *const u8) == 1 }
+}
+
+/// Returns the size of the large pages.
+///
+/// Returns:
+///
+/// 2 MBytes with PAE paging, 4 MBytes otherwise
+pub fn get_large_page_bytes_size() -> u32 {
+
+    if is_pae_enabled() {
+        PAE_LARGE_PAGE_BYTES_SIZE
+    } else {
+        LARGE_PAGE_BYTES_SIZE
+    }
+}
+
+/// Returns the size of one directory or page table entry.
+///
+/// Returns:
+///
+/// 8 bytes with PAE paging, 4 bytes otherwise
+fn get_entry_bytes_size() -> u32 {
+
+    if is_pae_enabled() {
+        8
+    } else {
+        4
+    }
+}
+
+/// Reads one directory or page table entry.
+///
+/// Args:
+///
+/// `address` - the virtual address of the entry (through the recursive mapping)
+///
+/// Returns:
+///
+/// the entry bits
+fn read_entry(address: u32) -> u64 {
+
+    if is_pae_enabled() {
+        unsafe { *(address as *const u64) }
+    } else {
+        unsafe { *(address as *const u32) as u64 }
+    }
+}
+
+/// Writes one directory or page table entry, the execute-disable bit is removed
+/// if it is not enabled (it is a reserved bit otherwise).
+///
+/// Args:
+///
+/// `address` - the virtual address of the entry (through the recursive mapping)
+/// `bits` - the entry bits
+fn write_entry(address: u32, bits: u64) {
+
+    let bits = if is_no_execute_enabled() {
+        bits
+    } else {
+        bits & !PageFlags::NO_EXECUTE.bits()
+    };
+
+    if is_pae_enabled() {
+        unsafe { *(address as *mut u64) = bits; }
+    } else {
+        unsafe { *(address as *mut u32) = bits as u32; }
+    }
+}
+
+/// Returns the directory entry index and the page table entry index of the given virtual address.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address
+///
+/// Returns:
+///
+/// the directory index (bits 22-31, or bits 21-31 with PAE)
+/// and the table index (bits 12-21, or bits 12-20 with PAE)
+fn get_indices(virtual_address: u32) -> (u32, u32) {
+
+    if is_pae_enabled() {
+        (virtual_address >> 21, (virtual_address >> 12) & (PAE_ENTRIES_PER_TABLE - 1))
+    } else {
+        (virtual_address >> 22, (virtual_address >> 12) & (ENTRIES_PER_TABLE - 1))
+    }
+}
+
+/// Indicates if the given directory entry is a recursive mapping entry.
+///
+/// Args:
+///
+/// `directory_index` - the directory entry index
+///
+/// Returns:
+///
+/// true if the entry maps the page tables area (last 4 MBytes, or last 8 MBytes with PAE)
+fn is_recursive_entry(directory_index: u32) -> bool {
+
+    if is_pae_enabled() {
+        directory_index >= PAE_RECURSIVE_ENTRIES_FIRST_INDEX
+    } else {
+        directory_index == RECURSIVE_ENTRY_INDEX
+    }
+}
+
+/// Returns the virtual address of the given directory entry (through the recursive mapping).
+///
+/// Args:
+///
+/// `directory_index` - the directory entry index
+///
+/// Returns:
+///
+/// the entry virtual address
+fn get_directory_entry_address(directory_index: u32) -> u32 {
+
+    let directory_address = if is_pae_enabled() {
+        PAE_PAGES_DIRECTORIES_VIRTUAL_ADDRESS
+    } else {
+        PAGES_DIRECTORY_VIRTUAL_ADDRESS
+    };
+
+    directory_address + directory_index * get_entry_bytes_size()
+}
+
+/// Returns the virtual address of the page table of the given directory entry
+/// (through the recursive mapping).
+///
+/// Args:
+///
+/// `directory_index` - the directory entry index
+///
+/// Returns:
+///
+/// the page table virtual address
+fn get_page_table_address(directory_index: u32) -> u32 {
+
+    let tables_address = if is_pae_enabled() {
+        PAE_PAGE_TABLES_VIRTUAL_ADDRESS
+    } else {
+        PAGE_TABLES_VIRTUAL_ADDRESS
+    };
+
+    tables_address + directory_index * FRAME_BYTES_SIZE
+}
+
+/// Returns the directory entry of the given index.
+///
+/// Args:
+///
+/// `directory_index` - the directory entry index
+///
+/// Returns:
+///
+/// the directory entry
+fn get_directory_entry(directory_index: u32) -> PageDirectoryEntry {
+    PageDirectoryEntry(read_entry(get_directory_entry_address(directory_index)))
+}
+
+/// Indicates if the given directory entry points to a page table.
+///
+/// Args:
+///
+/// `directory_index` - the directory entry index
+///
+/// Returns:
+///
+/// false if the entry is not present or if it maps a large page
+fn has_page_table(directory_index: u32) -> bool {
+
+    let entry = get_directory_entry(directory_index);
+    entry.is_present() && !entry.is_large_page()
+}
+
+/// Returns the virtual address of the page table entry of the given indices.
+///
+/// Args:
+///
+/// `directory_index` - the directory entry index
+/// `table_index` - the page table entry index
+///
+/// Returns:
+///
+/// the entry virtual address
+fn get_table_entry_address(directory_index: u32, table_index: u32) -> u32 {
+    get_page_table_address(directory_index) + table_index * get_entry_bytes_size()
+}
+
+/// Invalidates the TLB entry of the given virtual address,
+/// must be called everytime a present page table entry is modified.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the modified page
+pub fn flush_tlb_entry(virtual_address: u32) {
+
+    unsafe {
+        llvm_asm!("invlpg [$0]"
+            :: "r" (virtual_address)
+            : "memory"
+            : "intel", "volatile"
+        );
+    }
+}
+
+/// Maps the given virtual page to the given physical page frame,
+/// the page table is allocated if required. An existing mapping is replaced.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page (aligned on 4096 bytes)
+/// `physical_address` - the physical address of the page frame (aligned on 4096 bytes)
+/// `flags` - the page properties (the present flag is always set)
+///
+/// Returns:
+///
+/// false if the page table cannot be allocated (no free page frame)
+/// or if the page is into the page tables area (last 4 MBytes)
+pub fn map(virtual_address: u32, physical_address: u32, flags: PageFlags) -> bool {
+    map_physical(virtual_address, physical_address as u64, flags)
+}
+
+/// Maps the given virtual page to the given physical page frame, that might be above
+/// 4 GBytes with PAE paging (the page frames allocator only manages the first 4 GBytes).
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page (aligned on 4096 bytes)
+/// `physical_address` - the physical address of the page frame (aligned on 4096 bytes)
+/// `flags` - the page properties (the present flag is always set)
+///
+/// Returns:
+///
+/// false if the page table cannot be allocated, if the page is into the page tables area
+/// or if the page frame is above 4 GBytes without PAE paging
+pub fn map_physical(virtual_address: u32, physical_address: u64, flags: PageFlags) -> bool {
+
+    if !is_pae_enabled() && physical_address >= PHYSICAL_ADDRESSES_32_BITS_END {
+        return false;
+    }
+
+    write_page_table_entry(virtual_address, physical_address, flags | PageFlags::PRESENT)
+}
+
+/// Writes the page table entry of the given virtual page, the page table is allocated if required.
+/// The entry might be not present: the address bits and the properties of a not present entry
+/// are only used by the kernel (to find a swapped page for instance).
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page
+/// `address` - the address bits of the entry (aligned on 4096 bytes)
+/// `flags` - the entry properties
+///
+/// Returns:
+///
+/// false if the page table cannot be allocated (no free page frame),
+/// if the page is into the page tables area (last 4 MBytes) or into a large page
+pub fn set_page_table_entry(virtual_address: u32, address: u32, flags: PageFlags) -> bool {
+    write_page_table_entry(virtual_address, address as u64, flags)
+}
+
+/// Writes the page table entry of the given virtual page, the page table is allocated if required.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page
+/// `address` - the address bits of the entry (aligned on 4096 bytes)
+/// `flags` - the entry properties
+///
+/// Returns:
+///
+/// false if the page table cannot be allocated (no free page frame),
+/// if the page is into the page tables area or into a large page
+fn write_page_table_entry(virtual_address: u32, address: u64, flags: PageFlags) -> bool {
+
+    let (directory_index, table_index) = get_indices(virtual_address);
+
+    if is_recursive_entry(directory_index) ||
+        get_directory_entry(directory_index).is_large_page() {
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    if !get_directory_entry(directory_index).is_present() {
+
+        let table_address = match alloc_frame() {
+            Some(address) => address,
+            None => {
+                unsafe { restore_interrupts(interrupts); }
+                return false;
+            }
+        };
+
+        /* the directory entry allows everything,
+           the access rights are checked into the page tables entries */
+        let mut directory_entry = PageDirectoryEntry(0);
+        directory_entry.set(
+            table_address as u64,
+            PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER,
+        );
+        write_entry(get_directory_entry_address(directory_index), directory_entry.0);
+
+        /* the new table is accessible through the recursive mapping
+           only once the directory entry is set */
+        let table_virtual_address = get_page_table_address(directory_index);
+        flush_tlb_entry(table_virtual_address);
+
+        for offset in 0..FRAME_BYTES_SIZE / 4 {
+            unsafe { *((table_virtual_address + offset * 4) as *mut u32) = 0; }
+        }
+    }
+
+    let mut entry = PageTableEntry(0);
+    entry.set(address, flags);
+    write_entry(get_table_entry_address(directory_index, table_index), entry.0);
+
+    flush_tlb_entry(virtual_address);
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/// Unmaps the given virtual page (the page table is kept, even if empty).
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page
+///
+/// Returns:
+///
+/// the physical address of the page frame that was mapped, None if the page was not mapped,
+/// if the page frame is above 4 GBytes (not managed by the page frames allocator)
+/// or if the page is into a large page (the large page is kept)
+pub fn unmap(virtual_address: u32) -> Option<u32> {
+
+    let (directory_index, table_index) = get_indices(virtual_address);
+
+    if is_recursive_entry(directory_index) || !has_page_table(directory_index) {
+        return None;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let entry_address = get_table_entry_address(directory_index, table_index);
+    let entry = PageTableEntry(read_entry(entry_address));
+
+    let physical_address = if entry.is_present() &&
+        entry.get_address() < PHYSICAL_ADDRESSES_32_BITS_END {
+        Some(entry.get_address() as u32)
+    } else {
+        None
+    };
+
+    write_entry(entry_address, 0);
+    flush_tlb_entry(virtual_address);
+
+    unsafe { restore_interrupts(interrupts); }
+
+    physical_address
+}
+
+/// Translates the given virtual address into a physical address.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address
+///
+/// Returns:
+///
+/// the physical address, None if the page is not mapped or if it is above 4 GBytes
+pub fn translate(virtual_address: u32) -> Option<u32> {
+
+    match translate_physical(virtual_address) {
+        Some(address) if address < PHYSICAL_ADDRESSES_32_BITS_END => Some(address as u32),
+        _ => None,
+    }
+}
+
+/// Translates the given virtual address into a physical address, that might be above 4 GBytes.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address
+///
+/// Returns:
+///
+/// the physical address, None if the page is not mapped
+pub fn translate_physical(virtual_address: u32) -> Option<u64> {
+
+    let (directory_index, table_index) = get_indices(virtual_address);
+
+    let directory_entry = get_directory_entry(directory_index);
+    if !directory_entry.is_present() {
+        return None;
+    }
+
+    /* the address bits of a large page entry below the large page size are reserved
+       (or select the caching type) */
+    if directory_entry.is_large_page() {
+        let large_page_mask = (get_large_page_bytes_size() - 1) as u64;
+        return Some(
+            (directory_entry.get_address() & !large_page_mask) |
+                (virtual_address as u64 & large_page_mask)
+        );
+    }
+
+    let entry = PageTableEntry(read_entry(get_table_entry_address(directory_index, table_index)));
+    if !entry.is_present() {
+        return None;
+    }
+
+    Some(entry.get_address() | (virtual_address & (FRAME_BYTES_SIZE - 1)) as u64)
+}
+
+/// Returns the properties of the given virtual page.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address
+///
+/// Returns:
+///
+/// the page properties, None if the page is not mapped or if it is into a large page
+pub fn get_page_flags(virtual_address: u32) -> Option<PageFlags> {
+
+    match get_page_table_entry(virtual_address) {
+        Some(entry) if entry.is_present() => Some(entry.get_flags()),
+        _ => None,
+    }
+}
+
+/// Returns the page table entry of the given virtual page, even if the page is not present.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page
+///
+/// Returns:
+///
+/// the page table entry, None if the page table does not exist (or if the page is into a large page)
+pub fn get_page_table_entry(virtual_address: u32) -> Option<PageTableEntry> {
+
+    let (directory_index, table_index) = get_indices(virtual_address);
+
+    if is_recursive_entry(directory_index) || !has_page_table(directory_index) {
+        return None;
+    }
+
+    Some(PageTableEntry(read_entry(get_table_entry_address(directory_index, table_index))))
+}
+
+/// Replaces the properties of a mapped page, the page frame is unchanged.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the page
+/// `flags` - the new page properties (the present flag is always set)
+///
+/// Returns:
+///
+/// false if the page is not mapped or if it is into a large page
+pub fn set_page_flags(virtual_address: u32, flags: PageFlags) -> bool {
+
+    let (directory_index, table_index) = get_indices(virtual_address);
+
+    if is_recursive_entry(directory_index) || !has_page_table(directory_index) {
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let entry_address = get_table_entry_address(directory_index, table_index);
+    let mut entry = PageTableEntry(read_entry(entry_address));
+    let present = entry.is_present();
+
+    if present {
+        let address = entry.get_address();
+        entry.set(address, flags | PageFlags::PRESENT);
+        write_entry(entry_address, entry.0);
+        flush_tlb_entry(virtual_address);
+    }
+
+    unsafe { restore_interrupts(interrupts); }
+
+    present
+}
+
+/// Enables the write protection (bit 16 of CR0): the read only pages
+/// are also read only for the kernel (by default, the kernel can write into any page).
+pub fn enable_write_protection() {
+
+    unsafe {
+        llvm_asm!("
+            mov eax, cr0
+            or eax, 0x10000
+            mov cr0, eax
+            " ::: "eax" : "intel", "volatile"
+        );
+    }
+}
+
+/// Identity maps the given physical area (every page containing a part of the area).
+///
+/// Args:
+///
+/// `address` - the area start address
+/// `length` - the area length in bytes
+/// `flags` - the pages properties
+///
+/// Returns:
+///
+/// false if one page table cannot be allocated
+pub fn identity_map(address: u32, length: u32, flags: PageFlags) -> bool {
+
+    let first_page = address & !(FRAME_BYTES_SIZE - 1);
+    let end = address as u64 + length as u64;
+
+    let mut page = first_page as u64;
+    while page < end {
+
+        if !map(page as u32, page as u32, flags) {
+            return false;
+        }
+
+        page += FRAME_BYTES_SIZE as u64;
+    }
+
+    true
+}
+
+/// Maps the given virtual large page to the given physical area with one directory entry
+/// (check `get_large_page_bytes_size`). An existing large page is replaced.
+///
+/// Args:
+///
+/// `virtual_address` - the virtual address of the large page (aligned on the large page size)
+/// `physical_address` - the physical address of the area (aligned on the large page size)
+/// `flags` - the page properties (the present and page size flags are always set)
+///
+/// Returns:
+///
+/// false if the large pages are not enabled, if an address is not aligned,
+/// if the directory entry points to a page table, if the page is into the page tables area
+/// or if the area is above 4 GBytes without PAE paging
+pub fn map_large_page(virtual_address: u32, physical_address: u64, flags: PageFlags) -> bool {
+
+    let large_page_bytes_size = get_large_page_bytes_size();
+
+    if !are_large_pages_enabled() ||
+        virtual_address % large_page_bytes_size != 0 ||
+        physical_address % large_page_bytes_size as u64 != 0 ||
+        (!is_pae_enabled() && physical_address >= PHYSICAL_ADDRESSES_32_BITS_END) {
+        return false;
+    }
+
+    let (directory_index, _) = get_indices(virtual_address);
+
+    if is_recursive_entry(directory_index) || has_page_table(directory_index) {
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let mut entry = PageDirectoryEntry(0);
+    entry.set(physical_address, flags | PageFlags::PRESENT | PageFlags::PAGE_SIZE);
+    write_entry(get_directory_entry_address(directory_index), entry.0);
+
+    flush_tlb_entry(virtual_address);
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/// Returns the amount of paging structures: the page tables (the large pages have no page table),
+/// the pages directory (or the 4 directories and the pointers table with PAE paging).
+///
+/// Returns:
+///
+/// the amount of page frames used by the paging structures
+pub fn get_page_tables_amount() -> u32 {
+
+    let (first_recursive_index, directories_amount) = if is_pae_enabled() {
+        (PAE_RECURSIVE_ENTRIES_FIRST_INDEX, PAE_DIRECTORIES_AMOUNT + 1)
+    } else {
+        (RECURSIVE_ENTRY_INDEX, 1)
+    };
+
+    let tables_amount = (0..first_recursive_index)
+        .filter(|index| has_page_table(*index))
+        .count() as u32;
+
+    tables_amount + directories_amount
+}
+
+/// Returns the amount of mapped pages (present 4 KBytes pages) into the given virtual area,
+/// the pages of the large pages are included.
+///
+/// Args:
+///
+/// `start` - the area start address
+/// `end` - the area end address (excluded)
+///
+/// Returns:
+///
+/// the amount of mapped pages
+pub fn get_mapped_pages_amount(start: u32, end: u32) -> u32 {
+
+    /* one directory entry covers the large page size, the empty directory entries
+       are skipped at once */
+    let directory_entry_span = get_large_page_bytes_size() as u64;
+    let end = end as u64;
+
+    let mut amount: u32 = 0;
+    let mut address = (start & !(FRAME_BYTES_SIZE - 1)) as u64;
+
+    while address < end {
+
+        let (directory_index, table_index) = get_indices(address as u32);
+        let next_directory_address = (address | (directory_entry_span - 1)) + 1;
+
+        if is_recursive_entry(directory_index) {
+            break;
+        }
+
+        let directory_entry = get_directory_entry(directory_index);
+
+        if !directory_entry.is_present() {
+            address = next_directory_address;
+            continue;
+        }
+
+        if directory_entry.is_large_page() {
+            let mapped_end = cmp::min(next_directory_address, end);
+            amount += ((mapped_end - address) / FRAME_BYTES_SIZE as u64) as u32;
+            address = next_directory_address;
+            continue;
+        }
+
+        let entry_address = get_table_entry_address(directory_index, table_index);
+        if PageTableEntry(read_entry(entry_address)).is_present() {
+            amount += 1;
+        }
+
+        address += FRAME_BYTES_SIZE as u64;
+    }
+
+    amount
+}
+
+/// Invalidates every TLB entry (loading CR3 again flushes the whole TLB).
+pub fn flush_tlb() {
+
+    unsafe {
+        llvm_asm!("
+            mov eax, cr3
+            mov cr3, eax
+            " ::: "eax", "memory" : "intel", "volatile"
+        );
+    }
+}
+
+/// Indicates if the CPU supports PAE paging.
+///
+/// Returns:
+///
+/// true if PAE is available
+fn has_pae() -> bool {
+    get_cpu_features().contains(CpuFeatures::PAE)
+}
+
+/// Indicates if the CPU supports the execute-disable bit.
+///
+/// Returns:
+///
+/// true if NX is available
+fn has_no_execute() -> bool {
+    get_cpu_features().contains(CpuFeatures::NX)
+}
+
+/// Indicates if the CPU supports 4 MBytes pages with two levels paging.
+///
+/// Returns:
+///
+/// true if PSE (Page Size Extension) is available
+fn has_page_size_extension() -> bool {
+    get_cpu_features().contains(CpuFeatures::PSE)
+}
+
+/* switches from two levels paging to PAE paging, called with the physical address
+   of the pages directory pointers table (cdecl); paging must be disabled to set CR4.PAE,
+   so the switch is executed at the physical address of the routine (the kernel is still
+   identity mapped by the bootstrap mapping), without using the stack (not identity mapped) */
+global_asm!(r#"
+    .intel_syntax noprefix
+    .section .text
+    .global switch_to_pae_paging
+switch_to_pae_paging:
+    mov ecx, [esp + 4]
+    mov eax, OFFSET switch_to_pae_paging_identity - 0xC0000000
+    jmp eax
+
+switch_to_pae_paging_identity:
+    mov eax, cr0
+    and eax, 0x7FFFFFFF
+    mov cr0, eax
+    mov eax, cr4
+    or eax, 0x20
+    mov cr4, eax
+    mov cr3, ecx
+    mov eax, cr0
+    or eax, 0x80000000
+    mov cr0, eax
+    mov eax, OFFSET switch_to_pae_paging_end
+    jmp eax
+
+switch_to_pae_paging_end:
+    ret
+    .att_syntax
+"#);
+
+extern {
+    fn switch_to_pae_paging(pointers_table_address: u32);
+}
+
+/* virtual page used to fill the PAE tables before they are used
+   (into the kernel page table, after the kernel mapping end) */
+const PAE_BUILD_PAGE_ADDRESS: u32 = 0xC03FF000;
+
+/// Maps the given page frame at the build page and clears it if required (two levels paging).
+///
+/// Args:
+///
+/// `frame` - the page frame physical address
+/// `clear` - true to fill the page frame with zeros
+fn map_build_page(frame: u32, clear: bool) {
+
+    map(PAE_BUILD_PAGE_ADDRESS, frame, PageFlags::WRITABLE);
+
+    if clear {
+        for offset in 0..FRAME_BYTES_SIZE / 4 {
+            unsafe { *((PAE_BUILD_PAGE_ADDRESS + offset * 4) as *mut u32) = 0; }
+        }
+    }
+}
+
+/// Writes one 8 bytes entry into the page frame mapped at the build page.
+///
+/// Args:
+///
+/// `index` - the entry index
+/// `bits` - the entry bits
+fn write_build_page_entry(index: u32, bits: u64) {
+    unsafe { *((PAE_BUILD_PAGE_ADDRESS + index * 8) as *mut u64) = bits; }
+}
+
+/// Builds the PAE tables from the current two levels tables and switches to PAE paging,
+/// enables the execute-disable bit if the CPU supports it. The bootstrap identity mapping
+/// must still exist (the switch is executed at a physical address).
+///
+/// Returns:
+///
+/// false if there are not enough free page frames (two levels paging is kept)
+fn enable_pae_paging() -> bool {
+
+    /* every page table of 1024 entries is split into two page tables of 512 entries;
+       the bootstrap identity mapping shares its page table with the kernel mapping,
+       so its PAE directory entries share the kernel PAE page tables too */
+    const IDENTITY_MAPPING_DIRECTORY_INDEX: u32 = 0;
+    const KERNEL_DIRECTORY_INDEX: u32 = KERNEL_VIRTUAL_BASE >> 22;
+
+    let is_converted_entry = |index: u32| {
+        index != IDENTITY_MAPPING_DIRECTORY_INDEX &&
+            index != RECURSIVE_ENTRY_INDEX &&
+            get_directory_entry(index).is_present()
+    };
+
+    /* the required frames are counted first, so the allocations below cannot fail
+       (the pointers table, the 4 directories and two page tables per converted entry) */
+    let tables_amount = (0..ENTRIES_PER_TABLE)
+        .filter(|index| is_converted_entry(*index))
+        .count() as u32;
+
+    if get_free_frames_amount() < 1 + PAE_DIRECTORIES_AMOUNT + tables_amount * 2 {
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let pointers_table = alloc_frame().unwrap_or(0);
+    let mut directories: [u32; PAE_DIRECTORIES_AMOUNT as usize] = [0; 4];
+
+    for directory in directories.iter_mut() {
+        *directory = alloc_frame().unwrap_or(0);
+        map_build_page(*directory, true);
+    }
+
+    let table_flags = PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER;
+
+    for index in (0..ENTRIES_PER_TABLE).filter(|index| is_converted_entry(*index)) {
+
+        for half in 0..2 {
+
+            /* the 4 bytes entries have the same format as the lower bits of the 8 bytes entries */
+            let table = alloc_frame().unwrap_or(0);
+            map_build_page(table, false);
+
+            for table_index in 0..PAE_ENTRIES_PER_TABLE {
+
+                let source_index = half * PAE_ENTRIES_PER_TABLE + table_index;
+                let source = get_page_table_address(index) + source_index * 4;
+
+                write_build_page_entry(table_index, unsafe { *(source as *const u32) } as u64);
+            }
+
+            let pae_index = index * 2 + half;
+            let directory_entry = table as u64 | table_flags.bits();
+
+            map_build_page(directories[(pae_index / PAE_ENTRIES_PER_TABLE) as usize], false);
+            write_build_page_entry(pae_index % PAE_ENTRIES_PER_TABLE, directory_entry);
+
+            if index == KERNEL_DIRECTORY_INDEX {
+                map_build_page(directories[0], false);
+                write_build_page_entry(half, directory_entry);
+            }
+        }
+    }
+
+    /* the last 4 entries of the last directory point to the 4 directories */
+    let last_directory = directories[(PAE_DIRECTORIES_AMOUNT - 1) as usize];
+    map_build_page(last_directory, false);
+
+    for (index, directory) in directories.iter().enumerate() {
+        write_build_page_entry(
+            PAE_ENTRIES_PER_TABLE - PAE_DIRECTORIES_AMOUNT + index as u32,
+            *directory as u64 | (PageFlags::PRESENT | PageFlags::WRITABLE).bits(),
+        );
+    }
+
+    /* the pointers table entries only have the present bit and the caching bits */
+    map_build_page(pointers_table, true);
+
+    for (index, directory) in directories.iter().enumerate() {
+        write_build_page_entry(index as u32, *directory as u64 | PageFlags::PRESENT.bits());
+    }
+
+    /* EFER (Extended Feature Enable Register), bit 11: execute-disable bit enabled */
+    const EFER_REGISTER: u32 = 0xC0000080;
+    const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+
+    let no_execute = has_no_execute();
+
+    unsafe {
+        if no_execute {
+            write_msr(EFER_REGISTER, read_msr(EFER_REGISTER) | EFER_NO_EXECUTE_ENABLE);
+        }
+
+        switch_to_pae_paging(pointers_table);
+
+        *(PAE_ENABLED_ADDRESS as *mut u8) = 1;
+        *(NO_EXECUTE_ENABLED_ADDRESS as *mut u8) = no_execute as u8;
+    }
+
+    /* the two levels tables of Stage3 are reserved frames, they are not freed */
+    unmap(PAE_BUILD_PAGE_ADDRESS);
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/* the physical memory is mapped from 0xC0000000 with large pages, 252 MBytes at most
+   (the last large page before the heap contains the kernel stacks) */
+const PHYSICAL_MEMORY_WINDOW_MAXIMUM_END: u32 = 0x0FC00000;
+
+/// Enables the large pages if the CPU supports them: the 2 MBytes pages are always
+/// supported with PAE paging, the 4 MBytes pages require PSE (bit 4 of CR4).
+///
+/// Returns:
+///
+/// true if the large pages are enabled
+fn enable_large_pages() -> bool {
+
+    if has_page_size_extension() {
+
+        unsafe {
+            llvm_asm!("
+                mov eax, cr4
+                or eax, 0x10
+                mov cr4, eax
+                " ::: "eax" : "intel", "volatile"
+            );
+        }
+
+    } else if !is_pae_enabled() {
+        return false;
+    }
+
+    unsafe { *(LARGE_PAGES_ENABLED_ADDRESS as *mut u8) = 1; }
+
+    true
+}
+
+/// Returns the end of the physical memory window.
+///
+/// Returns:
+///
+/// the physical address following the last byte of usable memory (252 MBytes at most)
+fn get_physical_memory_window_end() -> u32 {
+
+    cmp::min(
+        get_frames_limit() as u64 * FRAME_BYTES_SIZE as u64,
+        PHYSICAL_MEMORY_WINDOW_MAXIMUM_END as u64,
+    ) as u32
+}
+
+/// Maps the physical memory window: the usable physical memory is mapped from 0xC0000000
+/// with large pages (the first large pages contain the low memory, the VGA memory
+/// and the kernel image), the kernel page tables are replaced and freed.
+/// Every large page but the first one is not executable (the first one contains the kernel code,
+/// its properties are set by `protect_kernel_sections`).
+/// The bootstrap identity mapping must be removed before (it shares the kernel page tables).
+fn map_physical_memory_window() {
+
+    let large_page_bytes_size = get_large_page_bytes_size();
+    let window_end = get_physical_memory_window_end();
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    let mut address: u32 = 0;
+    while address < window_end {
+
+        let (directory_index, _) = get_indices(KERNEL_VIRTUAL_BASE + address);
+        let previous_entry = get_directory_entry(directory_index);
+
+        /* the frames of the window (heap, slabs, stacks, pages tables...) are only
+           accessed as data, the kernel code is executed from the first large page */
+        let flags = if address == 0 {
+            PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::PAGE_SIZE
+        } else {
+            PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::PAGE_SIZE | PageFlags::NO_EXECUTE
+        };
+
+        let mut entry = PageDirectoryEntry(0);
+        entry.set(address as u64, flags);
+        write_entry(get_directory_entry_address(directory_index), entry.0);
+
+        /* the Stage3 page table is a reserved frame, it is not freed */
+        if previous_entry.is_present() && !previous_entry.is_large_page() {
+            free_frame(previous_entry.get_address() as u32);
+        }
+
+        address += large_page_bytes_size;
+    }
+
+    unsafe { restore_interrupts(interrupts); }
+}
+
+/// Returns the properties of one page of the kernel mapping, from the kernel sections.
+///
+/// Args:
+///
+/// `sections` - the kernel sections boundaries
+/// `virtual_address` - the virtual address of the page
+///
+/// Returns:
+///
+/// read only for the code, read only and not executable for the read only data,
+/// writable and not executable for the writable data and any other page
+fn get_kernel_page_flags(sections: &KernelSections, virtual_address: u32) -> PageFlags {
+
+    let is_into = |section: (u32, u32)| {
+        virtual_address >= section.0 && virtual_address < section.1
+    };
+
+    if is_into(sections.text) {
+        PageFlags::empty()
+    } else if is_into(sections.rodata) {
+        PageFlags::NO_EXECUTE
+    } else {
+        PageFlags::WRITABLE | PageFlags::NO_EXECUTE
+    }
+}
+
+/// Applies the kernel sections properties to the kernel mapping (W^X),
+/// then enables the write protection, so the kernel faults when it writes into its code.
+/// With large pages, the first large page of the physical memory window is replaced
+/// by a page table (filled through the physical memory window).
+///
+/// Returns:
+///
+/// false if the page table cannot be allocated
+fn protect_kernel_sections() -> bool {
+
+    let sections = get_kernel_sections();
+    let (directory_index, _) = get_indices(KERNEL_VIRTUAL_BASE);
+
+    if !get_directory_entry(directory_index).is_large_page() {
+
+        let mut address = 0;
+        while address < KERNEL_MAPPING_END {
+
+            let virtual_address = KERNEL_VIRTUAL_BASE + address;
+            set_page_flags(virtual_address, get_kernel_page_flags(&sections, virtual_address));
+
+            address += FRAME_BYTES_SIZE;
+        }
+
+        enable_write_protection();
+        return true;
+    }
+
+    let table = match alloc_frame() {
+        Some(frame) => frame,
+        None => return false,
+    };
+
+    if table >= get_physical_memory_window_end() {
+        free_frame(table);
+        return false;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    /* the page table maps the same physical area as the large page */
+    let entries_amount = get_large_page_bytes_size() / FRAME_BYTES_SIZE;
+    let table_virtual_address = KERNEL_VIRTUAL_BASE + table;
+
+    for index in 0..entries_amount {
+
+        let address = index * FRAME_BYTES_SIZE;
+        let virtual_address = KERNEL_VIRTUAL_BASE + address;
+
+        let mut entry = PageTableEntry(0);
+        entry.set(
+            address as u64,
+            get_kernel_page_flags(&sections, virtual_address) | PageFlags::PRESENT,
+        );
+        write_entry(table_virtual_address + index * get_entry_bytes_size(), entry.0);
+    }
+
+    let mut directory_entry = PageDirectoryEntry(0);
+    directory_entry.set(
+        table as u64,
+        PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER,
+    );
+    write_entry(get_directory_entry_address(directory_index), directory_entry.0);
+
+    flush_tlb();
+    enable_write_protection();
+
+    unsafe { restore_interrupts(interrupts); }
+
+    true
+}
+
+/// Finalizes the kernel pages directory built by Stage3, must be called after
+/// the page frames allocator initialization (page tables are allocated on demand)
+/// and before any other mapping.
+///
+/// Resets the paging mode flags to the Stage3 paging mode (two levels paging,
+/// no execute-disable bit, no large pages), so the pages can be mapped before `load_pagination`
+/// (called by `load_pagination` itself).
+pub fn initialize_paging_flags() {
+
+    unsafe {
+        *(PAE_ENABLED_ADDRESS as *mut u8) = 0;
+        *(NO_EXECUTE_ENABLED_ADDRESS as *mut u8) = 0;
+        *(LARGE_PAGES_ENABLED_ADDRESS as *mut u8) = 0;
+    }
+}
+
+/// Stage3 maps the first 4 MBytes twice: at 0x0 (bootstrap identity mapping,
+/// used until the jump to the kernel) and at 0xC0000000 (kernel). Only the first
+/// 0x113000 bytes (low memory, kernel, pages directory and first page table) stay mapped
+/// at 0xC0000000. The kernel switches to PAE paging if the CPU supports it,
+/// then the bootstrap identity mapping is removed, so the lower addresses space
+/// is free and NULL pointers dereferences fault. When the large pages are supported,
+/// the kernel mapping is replaced by the physical memory window. Finally, the kernel
+/// sections get their own properties (W^X) and the write protection is enabled.
+///
+/// Returns:
+///
+/// false if the kernel sections cannot be protected (no free page frame)
+pub fn load_pagination() -> bool {
+
+    initialize_paging_flags();
+
+    const KERNEL_PAGE_TABLE_END: u32 = 0x400000;
+
+    let mut address = KERNEL_MAPPING_END;
+    while address < KERNEL_PAGE_TABLE_END {
+        unmap(KERNEL_VIRTUAL_BASE + address);
+        address += FRAME_BYTES_SIZE;
+    }
+
+    if has_pae() {
+        enable_pae_paging();
+    }
+
+    /* the bootstrap identity mapping shares its page tables with the kernel mapping,
+       only the directory entries are removed (one entry, or two entries with PAE) */
+    let (first_index, _) = get_indices(0);
+    let (last_index, _) = get_indices(KERNEL_PAGE_TABLE_END - 1);
+
+    for index in first_index..last_index + 1 {
+        write_entry(get_directory_entry_address(index), 0);
+    }
+
+    if enable_large_pages() {
+        map_physical_memory_window();
+    }
+
+    flush_tlb();
+
+    protect_kernel_sections()
+}
@@ -0,0 +1,484 @@
+//! 8259A PIC (Programmable Interrupt Controller) management
+
+use io::{
+    inb,
+    outb,
+    io_wait,
+};
+
+use apic::{
+    is_apic_enabled,
+    mask_io_apic_irq,
+    unmask_io_apic_irq,
+    send_local_apic_eoi,
+};
+
+use interrupts::{
+    set_interrupt_handler,
+    InterruptFrame,
+};
+
+/* the primary PIC command port address is 0x20,
+   the slave PIC command port address is 0xA0,
+   the data port addresses are 0x21 and 0xA1 respectively */
+const MASTER_PIC_COMMAND_PORT: u16 = 0x20;
+const MASTER_PIC_DATA_PORT: u16 = 0x21;
+const SLAVE_PIC_COMMAND_PORT: u16 = 0xA0;
+const SLAVE_PIC_DATA_PORT: u16 = 0xA1;
+
+/* the IRQ line of the master PIC connected to the slave PIC */
+const CASCADE_IRQ: u8 = 2;
+
+/* every PIC handles 8 IRQ lines */
+const IRQS_PER_PIC: u8 = 8;
+
+/* the last IRQ line of every PIC is the one used by the PIC
+   when a spurious interrupt occurs */
+const MASTER_SPURIOUS_IRQ: u8 = 7;
+const SLAVE_SPURIOUS_IRQ: u8 = 15;
+
+/* kernel global variables (check README.md):
+   the vector of the IRQ0 (the IRQ8 vector is 8 indices after),
+   the amount of spurious IRQ7 and the amount of spurious IRQ15 */
+const PIC_VECTOR_BASE_ADDRESS: u32 = 0xC0013000;
+const MASTER_SPURIOUS_IRQS_AMOUNT_ADDRESS: u32 = 0xC0013004;
+const SLAVE_SPURIOUS_IRQS_AMOUNT_ADDRESS: u32 = 0xC0013008;
+
+/* the End Of Interrupt command (OCW2), sent to the command port
+   to indicate to the PIC that the interrupt has been handled */
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/* the OCW3 commands, sent to the command port, to select
+   the register returned by the next read from the command port:
+   bit 0: 0 for IRR (Interrupt Request Register), 1 for ISR (In-Service Register),
+   bit 1: 1 to consider bit 0,
+   bit 3: 1 to indicate an OCW3 */
+const READ_IRR_COMMAND: u8 = 0b00001010;
+const READ_ISR_COMMAND: u8 = 0b00001011;
+
+/// Initializes the 8259A PIC (Programmable Interrupt Controller).
+/// Sends the ICW (Initialization Control Word) to the PIC
+/// in order to set it up. Every IRQ line is masked at the end
+/// of the initialization (except the cascade line IRQ2),
+/// every driver has to unmask the line it uses.
+///
+/// Args:
+///
+/// `vector_base` - the IDT index of IRQ0, must be a multiple of 8
+/// (IRQ8 to IRQ15 use the eight following indices)
+pub fn initialize_pic(vector_base: u8) {
+
+    /* send the first ICW with the following properties:
+     * bit 0: set to 1 to considere sending an ICW 4,
+     * bit 1: 0 if cascaded PIC (slave PIC linked with master PIC), 1 if single PIC
+     * bit 2: ignored, 0
+     * bit 3: level triggered or edge triggered interrupts
+     *        - level (1): interrupt keep PIC Interrupt Request line enabled (with current) until the
+     *        interrupt is considered by the CPU (making the line unusuable for others interrupts),
+     *        - edge (0): interrupt is a single current pulse on a line, the line is immediately
+     *        available for other interrupts (too low current pulse might not be detected though)
+     * bit 4: 1 to initialize the PIC, 0 to not initialize the PIC
+     * bits 5 - 7: ignored, 0
+     *
+     * we enable the PIC, with edge triggered mode (we dont need to handle interrupts priority for
+     * now, so there is no problem if one interrupt keeps an interrupt line for a long time,
+     * so we could use level mode; the problem is that the Bochs emulator does not support
+     * PIC level triggered mode), x86 architecture has two PICs, so we enable cascading;
+     *
+     * (PIC command port address is used for ICW1) */
+    const PIC_FIRST_ICW: u8 = 0b00010001;
+    unsafe {
+
+        /* master and slave PIC initialization */
+        outb(MASTER_PIC_COMMAND_PORT, PIC_FIRST_ICW);
+        io_wait();
+        outb(SLAVE_PIC_COMMAND_PORT, PIC_FIRST_ICW);
+        io_wait();
+    }
+
+    /* send the second ICW (first PIC data port call) with the following properties:
+     * it contains the base index of the interrupt requests.
+     * The first 32 indices of the IDT are reserved for the CPU exceptions,
+     * so we usually start to plug the Interrupt Request lines from the PIC
+     * to the IDT from index 32 (0x20), so:
+     * IRQ0 uses interrupt number 0x20,
+     * IRQ1 uses interrupt number 0x21... etc...
+     * IRQ7 uses interrupt number 0x27
+     * the first height indices are set on the master PIC,
+     * the following height indices are set on the slave PIC;
+     *
+     * these indices must be sent to PIC data port address
+     * (0x21 for the primary and 0xA1 for the secondary);
+     *
+     * the PIC ignores the three lowest bits of the given index
+     * (it puts the IR line number there), so the base
+     * must be a multiple of 8 */
+    let master_vector_base = vector_base & 0b11111000;
+    let slave_vector_base = master_vector_base + IRQS_PER_PIC;
+
+    unsafe {
+        *(PIC_VECTOR_BASE_ADDRESS as *mut u8) = master_vector_base;
+        *(MASTER_SPURIOUS_IRQS_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(SLAVE_SPURIOUS_IRQS_AMOUNT_ADDRESS as *mut u32) = 0;
+
+        /* set the master PIC IRQs base index */
+        outb(MASTER_PIC_DATA_PORT, master_vector_base);
+        io_wait();
+
+        /* set the secondary PIC IRQs base index */
+        outb(SLAVE_PIC_DATA_PORT, slave_vector_base);
+        io_wait();
+    }
+
+    /* send the third ICW (second PIC data port call) with the following properties:
+     *
+     * - on the master: indicates which Interrupt Routine IR line
+     *   to use to communicate with the secondary PIC,
+     *   (x86 architecture uses the IR line 2 to connect the master PIC with the secondary PIC)
+     *   bit 0: use IR0,
+     *   bit 1: use IR1,
+     *   bit 2: use IR2,
+     *   ...
+     *   bit 7: use IR7,
+     *
+     * - on the slave: indicates which Interrupt Routine IR line
+     *   to use to communicate with the master PIC,
+     *   (x86 architecture uses the IR line 2 to connect the secondary PIC with the master PIC)
+     *   bits 0-7: IR number (ex: 011b for the third one)
+     *
+     *   IMPORTANT: on the master, the IR line is chosen by setting one specific bit to 1,
+     *   on the slave, the IR line is chosen by setting the IR number on bits 0 to 7 */
+    const MASTER_TO_SECOND_PIC_SELECTOR: u8 = 1 << CASCADE_IRQ;
+    const SECOND_TO_MASTER_PIC_SELECTOR: u8 = CASCADE_IRQ;
+    unsafe {
+        /* connect the master PIC to the slave PIC */
+        outb(MASTER_PIC_DATA_PORT, MASTER_TO_SECOND_PIC_SELECTOR);
+        io_wait();
+
+        /* connect the slave PIC to the master PIC */
+        outb(SLAVE_PIC_DATA_PORT, SECOND_TO_MASTER_PIC_SELECTOR);
+        io_wait();
+    }
+
+    /* send the fourth ICW (third PIC data port call) with the following properties:
+     * bit 0: PIC mode (1 if 80x86 mode, 0 if 8085 mode),
+     * bit 1: 1 to automatically ends an interrupt after pulse (special mode), 0 for normal mode,
+     * bit 2: specify master PIC if PIC buffering is enabled (1 if master, 0 if slave),
+     * bit 3: enable PIC buffering (1 to enable, 0 to disable),
+     * bit 4: enable fully nested mode (special mode when a large amount of PIC is available),
+     * bits 5-7: unused
+     *
+     * we start each PIC (master and slave) in 80x86 mode, without any specific mode,
+     * without fully nested mode, without buffering (we keep things simple for now) */
+    const PIC_FOURTH_ICW: u8 = 0b00000001;
+    unsafe {
+        outb(MASTER_PIC_DATA_PORT, PIC_FOURTH_ICW);
+        io_wait();
+        outb(SLAVE_PIC_DATA_PORT, PIC_FOURTH_ICW);
+        io_wait();
+    }
+
+    /* the PIC is now ready, any write into the data ports sets the IMR
+       (Interrupt Mask Register, OCW1): every bit set to 1 masks the matching IRQ line;
+       we mask every line except the one used for cascading,
+       drivers unmask the lines they use when they are initialized */
+    unsafe {
+        outb(MASTER_PIC_DATA_PORT, !(1 << CASCADE_IRQ));
+        outb(SLAVE_PIC_DATA_PORT, 0xFF);
+    }
+
+    set_interrupt_handler(
+        get_irq_vector(MASTER_SPURIOUS_IRQ) as usize,
+        handle_master_pic_last_irq,
+    );
+    set_interrupt_handler(
+        get_irq_vector(SLAVE_SPURIOUS_IRQ) as usize,
+        handle_slave_pic_last_irq,
+    );
+}
+
+/// Returns the IDT index used by the given IRQ line.
+///
+/// Args:
+///
+/// `irq` - the IRQ line (from 0 to 15)
+///
+/// Returns:
+///
+/// the IDT index of the IRQ line
+pub fn get_irq_vector(irq: u8) -> u8 {
+    unsafe { *(PIC_VECTOR_BASE_ADDRESS as *const u8) + irq }
+}
+
+/// Returns the name of the device connected to the given IRQ line on x86 PCs.
+///
+/// Args:
+///
+/// `irq` - the IRQ line (from 0 to 15)
+///
+/// Returns:
+///
+/// the device name
+pub fn get_irq_name(irq: u8) -> &'static str {
+
+    match irq {
+        0 => "Timer",
+        1 => "Keyboard",
+        2 => "Cascade",
+        3 => "Serial 2",
+        4 => "Serial 1",
+        5 => "Parallel port 2",
+        6 => "Floppy drive",
+        7 => "Parallel port 1",
+        8 => "CMOS real time clock",
+        9 => "CGA",
+        12 => "PS/2 mouse",
+        13 => "FPU",
+        14 => "Primary hard disk",
+        15 => "Secondary hard disk",
+        _ => "Reserved",
+    }
+}
+
+/// Returns the data port and the bit of the given IRQ line.
+///
+/// Args:
+///
+/// `irq` - the IRQ line (from 0 to 15)
+///
+/// Returns:
+///
+/// the data port of the PIC handling the line and the line bit into this PIC registers
+fn get_irq_port_and_bit(irq: u8) -> (u16, u8) {
+
+    if irq < IRQS_PER_PIC {
+        return (MASTER_PIC_DATA_PORT, 1 << irq);
+    }
+
+    (SLAVE_PIC_DATA_PORT, 1 << (irq - IRQS_PER_PIC))
+}
+
+/// Masks every IRQ line of both PICs, used when the IRQs are handled by the APIC.
+pub fn disable_pic() {
+
+    unsafe {
+        outb(MASTER_PIC_DATA_PORT, 0xFF);
+        outb(SLAVE_PIC_DATA_PORT, 0xFF);
+    }
+}
+
+/// Masks the given IRQ line, the PIC ignores any request from this line.
+/// The line is masked into the I/O APIC instead if the APIC is used.
+///
+/// Args:
+///
+/// `irq` - the IRQ line (from 0 to 15)
+pub fn mask_irq(irq: u8) {
+
+    if is_apic_enabled() {
+        mask_io_apic_irq(irq);
+        return;
+    }
+
+    let (port, bit) = get_irq_port_and_bit(irq);
+
+    unsafe {
+        let mask = inb(port);
+        outb(port, mask | bit);
+    }
+}
+
+/// Unmasks the given IRQ line, the PIC forwards the requests from this line to the CPU.
+/// The cascade line is unmasked as well if the line belongs to the slave PIC.
+/// The line is unmasked into the I/O APIC instead if the APIC is used.
+///
+/// Args:
+///
+/// `irq` - the IRQ line (from 0 to 15)
+pub fn unmask_irq(irq: u8) {
+
+    if is_apic_enabled() {
+        unmask_io_apic_irq(irq);
+        return;
+    }
+
+    let (port, bit) = get_irq_port_and_bit(irq);
+
+    unsafe {
+        let mask = inb(port);
+        outb(port, mask & !bit);
+
+        if irq >= IRQS_PER_PIC {
+            let master_mask = inb(MASTER_PIC_DATA_PORT);
+            outb(MASTER_PIC_DATA_PORT, master_mask & !(1 << CASCADE_IRQ));
+        }
+    }
+}
+
+/// Reads one register of both PICs using the given OCW3 command.
+///
+/// Args:
+///
+/// `command` - the OCW3 command selecting the register to read
+///
+/// Returns:
+///
+/// the slave register in the high byte, the master register in the low byte
+fn read_pic_registers(command: u8) -> u16 {
+
+    unsafe {
+        outb(MASTER_PIC_COMMAND_PORT, command);
+        outb(SLAVE_PIC_COMMAND_PORT, command);
+
+        ((inb(SLAVE_PIC_COMMAND_PORT) as u16) << 8) |
+            inb(MASTER_PIC_COMMAND_PORT) as u16
+    }
+}
+
+/// Returns the IMR (Interrupt Mask Register) of both PICs,
+/// every bit set to 1 is a masked IRQ line.
+///
+/// Returns:
+///
+/// the IRQ8-15 mask in the high byte, the IRQ0-7 mask in the low byte
+pub fn get_pic_imr() -> u16 {
+
+    unsafe {
+        ((inb(SLAVE_PIC_DATA_PORT) as u16) << 8) |
+            inb(MASTER_PIC_DATA_PORT) as u16
+    }
+}
+
+/// Returns the ISR (In-Service Register) of both PICs,
+/// every bit set to 1 is an IRQ line sent to the CPU and not acknowledged yet (no EOI).
+///
+/// Returns:
+///
+/// the IRQ8-15 lines in the high byte, the IRQ0-7 lines in the low byte
+pub fn get_pic_isr() -> u16 {
+    read_pic_registers(READ_ISR_COMMAND)
+}
+
+/// Returns the IRR (Interrupt Request Register) of both PICs,
+/// every bit set to 1 is an IRQ line raised and not sent to the CPU yet.
+///
+/// Returns:
+///
+/// the IRQ8-15 lines in the high byte, the IRQ0-7 lines in the low byte
+pub fn get_pic_irr() -> u16 {
+    read_pic_registers(READ_IRR_COMMAND)
+}
+
+/// Signals the end of the given IRQ handling to the PIC(s). An IRQ from the slave PIC
+/// requires an EOI on both PICs, as the master PIC considers it comes from the cascade line.
+/// The EOI is sent to the local APIC instead if the APIC is used.
+///
+/// Args:
+///
+/// `irq` - the handled IRQ line (from 0 to 15)
+pub fn send_eoi(irq: u8) {
+
+    if is_apic_enabled() {
+        send_local_apic_eoi();
+        return;
+    }
+
+    unsafe {
+        if irq >= IRQS_PER_PIC {
+            outb(SLAVE_PIC_COMMAND_PORT, END_OF_INTERRUPT);
+        }
+
+        outb(MASTER_PIC_COMMAND_PORT, END_OF_INTERRUPT);
+    }
+}
+
+/// Indicates if the given IRQ is a spurious IRQ. A spurious IRQ is raised by the PIC
+/// when an interrupt request disappears before being acknowledged by the CPU; the PIC
+/// then sends its lowest priority line (IRQ7 or IRQ15) without setting it into the ISR.
+/// No EOI must be sent for a spurious IRQ, except to the master PIC for a spurious IRQ15
+/// (the master PIC does not know the slave IRQ was spurious). The amount of spurious IRQs
+/// is incremented everytime one is detected. The I/O APIC does not raise spurious IRQs
+/// on these lines (the local APIC uses its own spurious interrupt vector).
+///
+/// Args:
+///
+/// `irq` - the received IRQ line (from 0 to 15)
+///
+/// Returns:
+///
+/// true if the IRQ is spurious and must be ignored
+pub fn is_spurious_irq(irq: u8) -> bool {
+
+    if is_apic_enabled() {
+        return false;
+    }
+
+    if irq != MASTER_SPURIOUS_IRQ && irq != SLAVE_SPURIOUS_IRQ {
+        return false;
+    }
+
+    if get_pic_isr() & (1 << irq) != 0 {
+        return false;
+    }
+
+    unsafe {
+        if irq == MASTER_SPURIOUS_IRQ {
+            *(MASTER_SPURIOUS_IRQS_AMOUNT_ADDRESS as *mut u32) += 1;
+        } else {
+            *(SLAVE_SPURIOUS_IRQS_AMOUNT_ADDRESS as *mut u32) += 1;
+            outb(MASTER_PIC_COMMAND_PORT, END_OF_INTERRUPT);
+        }
+    }
+
+    true
+}
+
+/// Returns the amount of spurious IRQs detected on the given line since the PIC initialization.
+///
+/// Args:
+///
+/// `irq` - the spurious IRQ line (7 or 15)
+///
+/// Returns:
+///
+/// the amount of spurious IRQs
+pub fn get_spurious_irqs_amount(irq: u8) -> u32 {
+
+    unsafe {
+        if irq == MASTER_SPURIOUS_IRQ {
+            return *(MASTER_SPURIOUS_IRQS_AMOUNT_ADDRESS as *const u32);
+        }
+
+        if irq == SLAVE_SPURIOUS_IRQ {
+            return *(SLAVE_SPURIOUS_IRQS_AMOUNT_ADDRESS as *const u32);
+        }
+    }
+
+    0
+}
+
+/// Interrupt routine of the IRQ7 (last master PIC line),
+/// ignores the interrupt if it is spurious.
+///
+/// Args:
+///
+/// `_frame` - the interrupted context
+fn handle_master_pic_last_irq(_frame: &mut InterruptFrame) {
+
+    if !is_spurious_irq(MASTER_SPURIOUS_IRQ) {
+        send_eoi(MASTER_SPURIOUS_IRQ);
+    }
+}
+
+/// Interrupt routine of the IRQ15 (last slave PIC line),
+/// ignores the interrupt if it is spurious.
+///
+/// Args:
+///
+/// `_frame` - the interrupted context
+fn handle_slave_pic_last_irq(_frame: &mut InterruptFrame) {
+
+    if !is_spurious_irq(SLAVE_SPURIOUS_IRQ) {
+        send_eoi(SLAVE_SPURIOUS_IRQ);
+    }
+}
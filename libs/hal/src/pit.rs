@@ -0,0 +1,361 @@
+//! PIT (Programmable Interval Timer) management
+
+use io::{
+    inb,
+    outb,
+};
+
+use pic::{
+    get_irq_vector,
+    unmask_irq,
+    send_eoi,
+};
+
+use interrupts::{
+    set_interrupt_handler,
+    InterruptFrame,
+};
+
+use timer::{
+    initialize_timers,
+    expire_timers,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* the PIT is connected to the IR line 0 of the master PIC */
+const PIT_IRQ: u8 = 0;
+
+/* the PIT counter 0 data port and the PIT control word port */
+const PIT_COUNTER_0_PORT: u16 = 0x40;
+const PIT_CONTROL_WORD_PORT: u16 = 0x43;
+
+/* the PIT oscillator frequency (in Hz), every counter is decremented at this rate */
+pub const PIT_BASE_FREQUENCY: u32 = 1193182;
+
+/* the frequency used when the PIT is initialized (one interrupt every 10 ms) */
+const PIT_DEFAULT_FREQUENCY: u32 = 100;
+
+/* kernel global variables (check README.md):
+   the ticks amount,
+   the counter 0 reload value,
+   the duration of one tick (in nanoseconds),
+   the elapsed time since the PIT initialization at the last tick (in nanoseconds),
+   the effective frequency of the ticks (in Hz),
+   the last time returned by `get_monotonic_time` (in nanoseconds) */
+const TICKS_AMOUNT_ADDRESS: u32 = 0xC0011806;
+const PIT_RELOAD_VALUE_ADDRESS: u32 = 0xC0013024;
+const TICK_DURATION_ADDRESS: u32 = 0xC0013028;
+const TICKS_TIME_ADDRESS: u32 = 0xC0013030;
+const TIMER_FREQUENCY_ADDRESS: u32 = 0xC0013038;
+const LAST_MONOTONIC_TIME_ADDRESS: u32 = 0xC0013040;
+
+const NANOSECONDS_PER_SECOND: u64 = 1000000000;
+
+/// Increments the ticks amount and the elapsed time, called everytime the PIC receives an IRQ from the PIT.
+/// Expires the kernel timers of the new tick.
+///
+/// Args:
+///
+/// `_frame` - the interrupted context
+fn increment_ticks(_frame: &mut InterruptFrame) {
+
+    let tick = unsafe {
+        /* increment the ticks amount */
+        *(TICKS_AMOUNT_ADDRESS as *mut u32) += 1;
+
+        *(TICKS_TIME_ADDRESS as *mut u64) += *(TICK_DURATION_ADDRESS as *const u32) as u64;
+
+        *(TICKS_AMOUNT_ADDRESS as *const u32)
+    };
+
+    expire_timers(tick);
+
+    /* signal the PIC that the interrupts is finished */
+    send_eoi(PIT_IRQ);
+}
+
+/// Returns the current ticks amount.
+///
+/// Returns:
+///
+/// current ticks amount
+pub unsafe fn get_ticks_amount() -> u32 {
+    *(TICKS_AMOUNT_ADDRESS as *mut u32)
+}
+
+/// Initializes the Programmable Interrupt Timer, starts one of the three counters,
+/// sets the counter reading mode and sets the PIT runner mode (at 100 Hz)
+pub fn initialize_pit() {
+
+    set_interrupt_handler(
+        get_irq_vector(PIT_IRQ) as usize,
+        increment_ticks,
+    );
+
+    unsafe {
+        /* initialize timer at value 0 */
+        *(TICKS_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(TICKS_TIME_ADDRESS as *mut u64) = 0;
+        *(LAST_MONOTONIC_TIME_ADDRESS as *mut u64) = 0;
+    }
+
+    initialize_timers();
+    set_timer_frequency(PIT_DEFAULT_FREQUENCY);
+
+    unmask_irq(PIT_IRQ);
+}
+
+/// Sets the frequency of the PIT interrupts (ticks). The effective frequency might be
+/// slightly different, as the PIT divides its base frequency (1193182 Hz) by an integer.
+///
+/// Args:
+///
+/// `frequency` - the requested frequency in Hz (from 19 Hz to 1193182 Hz, clamped into this range)
+///
+/// Returns:
+///
+/// false if the frequency is 0 (the frequency is not changed),
+/// the effective frequency is returned by `get_timer_frequency`
+pub fn set_timer_frequency(frequency: u32) -> bool {
+
+    if frequency == 0 {
+        return false;
+    }
+
+    /* the reload value cannot be lower than 1, so the frequency cannot be higher
+       than the base frequency (this also prevents the rounding addition from overflowing) */
+    let frequency = if frequency > PIT_BASE_FREQUENCY {
+        PIT_BASE_FREQUENCY
+    } else {
+        frequency
+    };
+
+    /* ICW to send to the PIT for initialization:
+       bit 0:
+           - 0: simple mode, binary counting (x86 PCs usually only use binary mode)
+           - 1: BCD mode (Binary Coded Decimal), more complex, no guarantee to work on every architectures,
+       bits 1-3: PIT mode
+           - 000: mode 0 (Interrupt on Terminal Count): starts to count down from a given counter value;
+                  when the counter is equal to 0, the OUT line is set to 1, and remains at 1 until
+                  the counter is manually reset or if a new control word is sent to the PIT
+                  (this mode is useful for unique countdown)
+
+                    +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
+                    |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
+             CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
+
+                                         +-----------------+
+                                         |                 |
+             OUT +-----------------------+                 +--------+
+
+                 ^                       ^                 ^
+                 |                       |                 |
+                 |                       |                 |
+                 +                       +                 +
+
+                ICW                  Usuable as           ICW
+             Counter = 4             an interrupt    or new counter
+
+           - 001: mode 1 (Hardware Triggered One-shot): the OUT line is set to 1 by default,
+                  when a GATE pulse is sent, the counter starts and the OUT line is set to 0;
+                  any GATE pulse resets the counter; OUT is set to 1 only when the countdown
+                  is finished;
+
+                    +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
+                    |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
+             CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
+
+                       +-+         +-+                              +-+
+                       | |         | |                              | |
+            GATE +-----+ +---------+ +------------------------------+ +---+
+
+
+             OUT +-----+                                   +--------+
+                       |                                   |        |
+                       +-----------------------------------+        +-----+
+
+                       ^           ^                       ^
+                       |           |                       |
+                       |           |                       |
+                       +           +                       +
+                      C=4         C=4                 Usuable as
+                                                      an interrupt
+
+           - 010: mode 3 (Rate Generator): the countdown goes down from its initial value
+             to 1 and repeats, until GATE is set to 0; everytime the counter reaches 1,
+             OUT is set to 0 and immediately set back to 1 (usuable as an interrupt)
+
+                    +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
+                    |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
+             CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
+
+                          +--------------------------------------------+
+                          |                                            |
+            GATE +--------+                                            +------------------
+
+                          +-----------------+ +---------------+ +---------------+
+                          |                 | |               | |               |
+             OUT +--------+                 +-+               +-+               +---------
+
+                  Count = 3
+
+           - 011: mode 3 (Square Wave Generator): exactly the same as the mode 2,
+             except that OUT is set to 1 and to 0 half time of the counter value;
+
+                   +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+
+                   |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |  |
+            CLK +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +--+  +
+
+                            +-----------+           +-----------+           +-----------+
+                            |           |           |           |           |           |
+            OUT +-----------+           +-----------+           +-----------+           +
+
+                  Count = 4
+
+          - 100: mode 4 (Software Triggered Strobe)
+          - 101: mode 5 (Hardware Triggered Strobe)
+          (software and hardware triggered strobes are similar and they depend on the GATE value)
+
+        bits 4-5: read/load mode of the 2 bytes counter
+          - 00: lock the counter for reading,
+          - 01: read the high byte only,
+          - 10: read the low byte only,
+          - 11: read the low byte then the high byte
+
+        bits 6-7: counter selection (PIT has three counters available)
+          - 00: counter 0
+          - 01: counter 1
+          - 10: counter 2
+
+        we start the counter 0, we read and load the low byte first
+        then the low byte when accessing the counter; we use the rate generator,
+        we use simple binary count
+
+        this ICW is sent to the port 0x43, which is the PIT control word port */
+    const PIT_ICW: u8 = 0b00110100;
+    unsafe { outb(PIT_CONTROL_WORD_PORT, PIT_ICW); }
+
+    /* the counter is decremented at 1193182 Hz, so the interrupts frequency
+       is the base frequency divided by the counter reload value
+       (100 Hz, so one interrupt every 10 ms, requires a reload value of 11932);
+       the reload value is 16 bits long, 0 is considered as 65536 by the PIT */
+    const MAXIMUM_RELOAD_VALUE: u32 = 65536;
+    let mut reload_value = (PIT_BASE_FREQUENCY + frequency / 2) / frequency;
+
+    if reload_value > MAXIMUM_RELOAD_VALUE {
+        reload_value = MAXIMUM_RELOAD_VALUE;
+    }
+
+    /* the rate generator mode does not support the reload value 1 */
+    if reload_value < 2 {
+        reload_value = 2;
+    }
+
+    unsafe {
+        /* the ticks are accounted with the previous frequency until the new reload value
+           is sent, interrupts are disabled so no tick can use half-updated values */
+        let flags = save_and_disable_interrupts();
+
+        outb(PIT_COUNTER_0_PORT, (reload_value & 0xff) as u8);
+        outb(PIT_COUNTER_0_PORT, ((reload_value >> 8) & 0xff) as u8);
+
+        *(PIT_RELOAD_VALUE_ADDRESS as *mut u32) = reload_value;
+        *(TICK_DURATION_ADDRESS as *mut u32) = (
+            (reload_value as u64) * NANOSECONDS_PER_SECOND / (PIT_BASE_FREQUENCY as u64)
+        ) as u32;
+        *(TIMER_FREQUENCY_ADDRESS as *mut u32) = PIT_BASE_FREQUENCY / reload_value;
+
+        restore_interrupts(flags);
+    }
+
+    true
+}
+
+/// Returns the effective frequency of the PIT interrupts (ticks).
+///
+/// Returns:
+///
+/// the frequency in Hz
+pub fn get_timer_frequency() -> u32 {
+    unsafe { *(TIMER_FREQUENCY_ADDRESS as *const u32) }
+}
+
+/// Returns the duration of one tick.
+///
+/// Returns:
+///
+/// the tick duration in nanoseconds
+pub fn get_tick_duration() -> u32 {
+    unsafe { *(TICK_DURATION_ADDRESS as *const u32) }
+}
+
+/// Reads the current value of the PIT counter 0.
+///
+/// Returns:
+///
+/// the current counter value (from the reload value down to 1)
+fn read_pit_counter() -> u32 {
+
+    /* the latch command (counter 0, bits 4-5 set to 00) copies the counter value,
+       so the low byte and the high byte read just after belong to the same value */
+    const PIT_LATCH_COUNTER_0: u8 = 0b00000000;
+
+    unsafe {
+        outb(PIT_CONTROL_WORD_PORT, PIT_LATCH_COUNTER_0);
+        let low = inb(PIT_COUNTER_0_PORT) as u32;
+        let high = inb(PIT_COUNTER_0_PORT) as u32;
+
+        /* 0 is read when the counter has just been reloaded with 65536 */
+        let value = (high << 8) | low;
+        if value == 0 {
+            return *(PIT_RELOAD_VALUE_ADDRESS as *const u32);
+        }
+
+        value
+    }
+}
+
+/// Returns the elapsed time since the PIT initialization, combining the ticks time
+/// and the current PIT counter value (resolution of less than one microsecond).
+/// The returned value never goes backward.
+///
+/// Returns:
+///
+/// the elapsed time in nanoseconds
+pub fn get_monotonic_time() -> u64 {
+
+    let mut ticks_time: u64;
+    let mut counter: u32;
+
+    /* the counter is read again if a tick occured during the reading */
+    loop {
+        ticks_time = unsafe { *(TICKS_TIME_ADDRESS as *const u64) };
+        counter = read_pit_counter();
+
+        if ticks_time == unsafe { *(TICKS_TIME_ADDRESS as *const u64) } {
+            break;
+        }
+    }
+
+    let reload_value = unsafe { *(PIT_RELOAD_VALUE_ADDRESS as *const u32) };
+    let elapsed_counts = reload_value.saturating_sub(counter) as u64;
+    let mut time = ticks_time + elapsed_counts * NANOSECONDS_PER_SECOND / (PIT_BASE_FREQUENCY as u64);
+
+    /* if the counter has been reloaded but the tick is not handled yet
+       (interrupts are disabled), the computed time is earlier than a time
+       returned before, the previous time is returned instead */
+    unsafe {
+        let last_time = *(LAST_MONOTONIC_TIME_ADDRESS as *const u64);
+        if time < last_time {
+            time = last_time;
+        }
+
+        *(LAST_MONOTONIC_TIME_ADDRESS as *mut u64) = time;
+    }
+
+    time
+}
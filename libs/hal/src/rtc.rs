@@ -0,0 +1,549 @@
+//! CMOS RTC (Real Time Clock) driver
+
+use core::mem;
+
+use io::{
+    inb,
+    outb,
+};
+
+use pic::{
+    get_irq_vector,
+    unmask_irq,
+    mask_irq,
+    send_eoi,
+};
+
+use interrupts::{
+    set_interrupt_handler,
+    InterruptFrame,
+};
+
+use deferred::defer_work;
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* the CMOS register to access is selected by writing its index into the port 0x70
+   (the bit 7 of this port disables the NMI when set, it is set while
+   the RTC is programmed), then the register is read or written through the port 0x71 */
+const CMOS_INDEX_PORT: u16 = 0x70;
+const CMOS_DATA_PORT: u16 = 0x71;
+const CMOS_DISABLE_NMI: u8 = 0b10000000;
+
+/* status register D, read only, selected after every write
+   in order to enable the NMI again */
+const RTC_STATUS_D: u8 = 0x0D;
+
+/* the RTC registers indices */
+const RTC_SECONDS: u8 = 0x00;
+const RTC_SECONDS_ALARM: u8 = 0x01;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_MINUTES_ALARM: u8 = 0x03;
+const RTC_HOURS: u8 = 0x04;
+const RTC_HOURS_ALARM: u8 = 0x05;
+const RTC_DAY_OF_MONTH: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_CENTURY: u8 = 0x32;
+
+/* status register A:
+   bits 0-3: periodic interrupt rate (frequency = 32768 >> (rate - 1)),
+   bits 4-6: oscillator frequency (must be kept),
+   bit 7: update in progress (1 while the RTC updates the time registers) */
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_UPDATE_IN_PROGRESS: u8 = 0b10000000;
+
+/* status register B:
+   bit 1: 1 for 24 hours mode, 0 for 12 hours mode (bit 7 of the hours set for PM),
+   bit 2: 1 for binary values, 0 for BCD values (Binary Coded Decimal),
+   bit 4: update ended interrupt enable,
+   bit 5: alarm interrupt enable,
+   bit 6: periodic interrupt enable */
+const RTC_STATUS_B: u8 = 0x0B;
+const RTC_24_HOURS_MODE: u8 = 0b00000010;
+const RTC_BINARY_MODE: u8 = 0b00000100;
+const RTC_ALARM_INTERRUPT: u8 = 0b00100000;
+const RTC_PERIODIC_INTERRUPT: u8 = 0b01000000;
+
+/* status register C, indicates the interrupt sources (must be read
+   after every interrupt, otherwise the RTC does not raise any new interrupt):
+   bit 5: alarm interrupt,
+   bit 6: periodic interrupt */
+const RTC_STATUS_C: u8 = 0x0C;
+
+/* the hours bit set for PM in 12 hours mode */
+const RTC_PM_HOUR: u8 = 0b10000000;
+
+/* the RTC is connected to the IR line 0 of the slave PIC */
+const RTC_IRQ: u8 = 8;
+
+/* kernel global variables (check README.md):
+   address of the function called at every periodic interrupt (0 if none),
+   address of the function called when the alarm rings (0 if none),
+   amount of periodic interrupts */
+const RTC_PERIODIC_CALLBACK_ADDRESS: u32 = 0xC0013050;
+const RTC_ALARM_CALLBACK_ADDRESS: u32 = 0xC0013054;
+const RTC_PERIODIC_INTERRUPTS_AMOUNT_ADDRESS: u32 = 0xC0013058;
+
+/// A function called from the deferred work of the RTC interrupt.
+pub type RtcCallback = fn(u32);
+
+/// A date and a time, as stored by the RTC.
+#[derive(Copy, Clone, PartialEq)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hours: u8,
+    minutes: u8,
+    seconds: u8,
+}
+
+impl DateTime {
+
+    /// Getter of the year (four digits).
+    ///
+    /// Returns:
+    ///
+    /// the year
+    pub fn get_year(&self) -> u16 {
+        self.year
+    }
+
+    /// Getter of the month (from 1 to 12).
+    ///
+    /// Returns:
+    ///
+    /// the month
+    pub fn get_month(&self) -> u8 {
+        self.month
+    }
+
+    /// Getter of the day of the month (from 1 to 31).
+    ///
+    /// Returns:
+    ///
+    /// the day
+    pub fn get_day(&self) -> u8 {
+        self.day
+    }
+
+    /// Getter of the hours (from 0 to 23).
+    ///
+    /// Returns:
+    ///
+    /// the hours
+    pub fn get_hours(&self) -> u8 {
+        self.hours
+    }
+
+    /// Getter of the minutes (from 0 to 59).
+    ///
+    /// Returns:
+    ///
+    /// the minutes
+    pub fn get_minutes(&self) -> u8 {
+        self.minutes
+    }
+
+    /// Getter of the seconds (from 0 to 59).
+    ///
+    /// Returns:
+    ///
+    /// the seconds
+    pub fn get_seconds(&self) -> u8 {
+        self.seconds
+    }
+}
+
+/// Reads one CMOS register.
+///
+/// Args:
+///
+/// `register` - the register index
+///
+/// Returns:
+///
+/// the register value
+fn read_cmos(register: u8) -> u8 {
+
+    unsafe {
+        outb(CMOS_INDEX_PORT, register);
+        inb(CMOS_DATA_PORT)
+    }
+}
+
+/// Writes one CMOS register.
+///
+/// Args:
+///
+/// `register` - the register index
+/// `value` - the value to write
+fn write_cmos(register: u8, value: u8) {
+
+    unsafe {
+        outb(CMOS_INDEX_PORT, CMOS_DISABLE_NMI | register);
+        outb(CMOS_DATA_PORT, value);
+        outb(CMOS_INDEX_PORT, RTC_STATUS_D);
+    }
+}
+
+/// Converts a BCD value (one decimal digit per 4 bits) into binary.
+///
+/// Args:
+///
+/// `value` - the BCD value
+///
+/// Returns:
+///
+/// the binary value
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Converts a binary value into BCD (one decimal digit per 4 bits).
+///
+/// Args:
+///
+/// `value` - the binary value (from 0 to 99)
+///
+/// Returns:
+///
+/// the BCD value
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Converts a value read from the RTC into binary, according to the RTC mode.
+///
+/// Args:
+///
+/// `value` - the read value
+/// `status_b` - the status register B value
+///
+/// Returns:
+///
+/// the binary value
+fn from_rtc_value(value: u8, status_b: u8) -> u8 {
+
+    if status_b & RTC_BINARY_MODE != 0 {
+        return value;
+    }
+
+    bcd_to_binary(value)
+}
+
+/// Converts a binary value into the RTC format, according to the RTC mode.
+///
+/// Args:
+///
+/// `value` - the binary value
+/// `status_b` - the status register B value
+///
+/// Returns:
+///
+/// the value to write into the RTC
+fn to_rtc_value(value: u8, status_b: u8) -> u8 {
+
+    if status_b & RTC_BINARY_MODE != 0 {
+        return value;
+    }
+
+    binary_to_bcd(value)
+}
+
+/// Converts hours read from the RTC into 24 hours binary format.
+///
+/// Args:
+///
+/// `value` - the hours register value
+/// `status_b` - the status register B value
+///
+/// Returns:
+///
+/// the hours, from 0 to 23
+fn from_rtc_hours(value: u8, status_b: u8) -> u8 {
+
+    if status_b & RTC_24_HOURS_MODE != 0 {
+        return from_rtc_value(value, status_b);
+    }
+
+    /* 12 hours mode: 12 AM is midnight, 12 PM is noon */
+    let hours = from_rtc_value(value & !RTC_PM_HOUR, status_b) % 12;
+
+    if value & RTC_PM_HOUR != 0 {
+        return hours + 12;
+    }
+
+    hours
+}
+
+/// Converts 24 hours binary hours into the RTC format.
+///
+/// Args:
+///
+/// `hours` - the hours, from 0 to 23
+/// `status_b` - the status register B value
+///
+/// Returns:
+///
+/// the hours register value
+fn to_rtc_hours(hours: u8, status_b: u8) -> u8 {
+
+    if status_b & RTC_24_HOURS_MODE != 0 {
+        return to_rtc_value(hours, status_b);
+    }
+
+    let twelve_hours = if hours % 12 == 0 { 12 } else { hours % 12 };
+    let value = to_rtc_value(twelve_hours, status_b);
+
+    if hours >= 12 {
+        return value | RTC_PM_HOUR;
+    }
+
+    value
+}
+
+/// Indicates if the RTC is updating its time registers.
+///
+/// Returns:
+///
+/// true if an update is in progress
+fn is_update_in_progress() -> bool {
+    read_cmos(RTC_STATUS_A) & RTC_UPDATE_IN_PROGRESS != 0
+}
+
+/// Reads the raw time registers (seconds, minutes, hours, day, month, year, century).
+///
+/// Returns:
+///
+/// the raw registers values
+fn read_raw_registers() -> [u8; 7] {
+
+    /* the registers must not be read during an update
+       (the update lasts less than 2 ms once the flag is set) */
+    while is_update_in_progress() {}
+
+    [
+        read_cmos(RTC_SECONDS),
+        read_cmos(RTC_MINUTES),
+        read_cmos(RTC_HOURS),
+        read_cmos(RTC_DAY_OF_MONTH),
+        read_cmos(RTC_MONTH),
+        read_cmos(RTC_YEAR),
+        read_cmos(RTC_CENTURY),
+    ]
+}
+
+/// Reads the current date and time from the RTC.
+///
+/// Returns:
+///
+/// the current date and time
+pub fn read_date_time() -> DateTime {
+
+    /* an update might start right after the update in progress flag
+       has been checked, so the registers are read until two consecutive
+       readings are the same */
+    let mut registers = read_raw_registers();
+    loop {
+        let next_registers = read_raw_registers();
+        if next_registers == registers {
+            break;
+        }
+
+        registers = next_registers;
+    }
+
+    let status_b = read_cmos(RTC_STATUS_B);
+
+    /* the century register is not available on every machine,
+       the 21st century is considered if its value is not valid */
+    let mut century = from_rtc_value(registers[6], status_b);
+    if century < 19 || century > 99 {
+        century = 20;
+    }
+
+    DateTime {
+        year: (century as u16) * 100 + from_rtc_value(registers[5], status_b) as u16,
+        month: from_rtc_value(registers[4], status_b),
+        day: from_rtc_value(registers[3], status_b),
+        hours: from_rtc_hours(registers[2], status_b),
+        minutes: from_rtc_value(registers[1], status_b),
+        seconds: from_rtc_value(registers[0], status_b),
+    }
+}
+
+/// Interrupt routine of the RTC (IRQ8), enqueues the callbacks of the raised interrupts.
+///
+/// Args:
+///
+/// `_frame` - the interrupted context
+fn handle_rtc_interrupt(_frame: &mut InterruptFrame) {
+
+    /* reading the status register C acknowledges the interrupt */
+    let status_c = read_cmos(RTC_STATUS_C);
+
+    unsafe {
+        if status_c & RTC_PERIODIC_INTERRUPT != 0 {
+
+            *(RTC_PERIODIC_INTERRUPTS_AMOUNT_ADDRESS as *mut u32) += 1;
+
+            let callback = *(RTC_PERIODIC_CALLBACK_ADDRESS as *const u32);
+            if callback != 0 {
+                defer_work(
+                    mem::transmute(callback as usize),
+                    *(RTC_PERIODIC_INTERRUPTS_AMOUNT_ADDRESS as *const u32),
+                );
+            }
+        }
+
+        if status_c & RTC_ALARM_INTERRUPT != 0 {
+
+            let callback = *(RTC_ALARM_CALLBACK_ADDRESS as *const u32);
+            if callback != 0 {
+                defer_work(mem::transmute(callback as usize), 0);
+            }
+        }
+    }
+
+    send_eoi(RTC_IRQ);
+}
+
+/// Initializes the RTC interrupt routine, the RTC interrupts are disabled
+/// until the periodic interrupt or the alarm is enabled.
+pub fn initialize_rtc() {
+
+    unsafe {
+        *(RTC_PERIODIC_CALLBACK_ADDRESS as *mut u32) = 0;
+        *(RTC_ALARM_CALLBACK_ADDRESS as *mut u32) = 0;
+        *(RTC_PERIODIC_INTERRUPTS_AMOUNT_ADDRESS as *mut u32) = 0;
+    }
+
+    set_interrupt_handler(
+        get_irq_vector(RTC_IRQ) as usize,
+        handle_rtc_interrupt,
+    );
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let status_b = read_cmos(RTC_STATUS_B);
+    write_cmos(
+        RTC_STATUS_B,
+        status_b & !(RTC_PERIODIC_INTERRUPT | RTC_ALARM_INTERRUPT),
+    );
+
+    /* clears any pending interrupt */
+    read_cmos(RTC_STATUS_C);
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Enables or disables RTC interrupt sources, the IRQ8 is unmasked as long as
+/// one source is enabled.
+///
+/// Args:
+///
+/// `source` - the status register B bit of the interrupt source
+/// `enabled` - true to enable the source
+fn set_interrupt_source(source: u8, enabled: bool) {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let mut status_b = read_cmos(RTC_STATUS_B);
+    if enabled {
+        status_b |= source;
+    } else {
+        status_b &= !source;
+    }
+
+    write_cmos(RTC_STATUS_B, status_b);
+    read_cmos(RTC_STATUS_C);
+
+    unsafe { restore_interrupts(flags); }
+
+    if status_b & (RTC_PERIODIC_INTERRUPT | RTC_ALARM_INTERRUPT) != 0 {
+        unmask_irq(RTC_IRQ);
+    } else {
+        mask_irq(RTC_IRQ);
+    }
+}
+
+/// Enables the RTC periodic interrupt. The callback is called (as deferred work)
+/// at every interrupt, with the amount of periodic interrupts as argument.
+///
+/// Args:
+///
+/// `rate` - the interrupt rate, from 3 (8192 Hz) to 15 (2 Hz), the frequency is `32768 >> (rate - 1)`
+/// `callback` - the function to call
+pub fn enable_rtc_periodic_interrupt(rate: u8, callback: RtcCallback) {
+
+    const MINIMUM_RATE: u8 = 3;
+    const MAXIMUM_RATE: u8 = 15;
+
+    let rate = if rate < MINIMUM_RATE {
+        MINIMUM_RATE
+    } else if rate > MAXIMUM_RATE {
+        MAXIMUM_RATE
+    } else {
+        rate
+    };
+
+    unsafe { *(RTC_PERIODIC_CALLBACK_ADDRESS as *mut u32) = (callback as *const ()) as u32; }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let status_a = read_cmos(RTC_STATUS_A);
+    write_cmos(RTC_STATUS_A, (status_a & 0xF0) | rate);
+
+    unsafe { restore_interrupts(flags); }
+
+    set_interrupt_source(RTC_PERIODIC_INTERRUPT, true);
+}
+
+/// Disables the RTC periodic interrupt.
+pub fn disable_rtc_periodic_interrupt() {
+    set_interrupt_source(RTC_PERIODIC_INTERRUPT, false);
+}
+
+/// Returns the amount of RTC periodic interrupts.
+///
+/// Returns:
+///
+/// the periodic interrupts amount
+pub fn get_rtc_periodic_interrupts_amount() -> u32 {
+    unsafe { *(RTC_PERIODIC_INTERRUPTS_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Enables the RTC alarm, raised everyday at the given time. The callback is called
+/// (as deferred work) when the alarm rings.
+///
+/// Args:
+///
+/// `hours` - the alarm hours, from 0 to 23
+/// `minutes` - the alarm minutes, from 0 to 59
+/// `seconds` - the alarm seconds, from 0 to 59
+/// `callback` - the function to call
+pub fn enable_rtc_alarm(hours: u8, minutes: u8, seconds: u8, callback: RtcCallback) {
+
+    unsafe { *(RTC_ALARM_CALLBACK_ADDRESS as *mut u32) = (callback as *const ()) as u32; }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let status_b = read_cmos(RTC_STATUS_B);
+    write_cmos(RTC_HOURS_ALARM, to_rtc_hours(hours, status_b));
+    write_cmos(RTC_MINUTES_ALARM, to_rtc_value(minutes, status_b));
+    write_cmos(RTC_SECONDS_ALARM, to_rtc_value(seconds, status_b));
+
+    unsafe { restore_interrupts(flags); }
+
+    set_interrupt_source(RTC_ALARM_INTERRUPT, true);
+}
+
+/// Disables the RTC alarm.
+pub fn disable_rtc_alarm() {
+    set_interrupt_source(RTC_ALARM_INTERRUPT, false);
+}
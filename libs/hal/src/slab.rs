@@ -0,0 +1,619 @@
+//! Slab allocator (objects caches)
+//!
+//! Every objects cache allocates objects of one fixed size. The objects are stored into slabs:
+//! one slab is one page frame, mapped into the slabs virtual area (from 0xE0000000),
+//! that starts with a header followed by the objects. The free objects of a slab are linked together
+//! (the first 4 bytes of every free object contain the address of the next free object).
+//!
+//! The slabs with free objects and the full slabs of a cache are kept into two lists,
+//! so an allocation never searches for a free object. Empty slabs are kept for the next allocations
+//! until the cache is shrunk.
+//!
+//! The allocator only uses the page frames allocator and the virtual memory manager,
+//! it does not require the kernel heap.
+
+use core::mem;
+
+use frames::{
+    alloc_frame,
+    free_frame,
+    FRAME_BYTES_SIZE,
+};
+
+use paging::{
+    map,
+    unmap,
+    PageFlags,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   the amount of created caches,
+   the caches descriptors,
+   the slabs pages bitmap (one bit per page of the slabs virtual area, 1 if used) */
+const CACHES_AMOUNT_ADDRESS: u32 = 0xC00130AC;
+const CACHES_ADDRESS: u32 = 0xC0014800;
+const SLABS_BITMAP_ADDRESS: u32 = 0xC0014C00;
+
+pub const CACHES_MAX_AMOUNT: u32 = 32;
+
+/* the slabs virtual area (16 MBytes, 4096 slabs) */
+const SLABS_AREA_START: u32 = 0xE0000000;
+const SLABS_PAGES_AMOUNT: u32 = 4096;
+
+/* the objects are stored after the slab header, every object address is aligned on 8 bytes */
+const OBJECTS_OFFSET: u32 = 24;
+const OBJECT_GRANULARITY: u32 = 8;
+
+/* one slab contains at least one object */
+pub const OBJECT_MAXIMUM_SIZE: u32 = 2048;
+
+/* the objects sizes of the general purpose caches (created by the initialization,
+   they use the first caches indices) */
+const SIZE_CACHES: [u32; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Function called on every allocated object before it is returned,
+/// the argument is the object address.
+pub type ObjectConstructor = fn(u32);
+
+/// The descriptor of one cache, stored into the kernel global variables.
+#[repr(C)]
+struct CacheDescriptor {
+    object_size: u32,
+    objects_per_slab: u32,
+    constructor: u32,
+    partial_slabs: u32,
+    full_slabs: u32,
+    slabs_amount: u32,
+    allocated_objects: u32,
+    allocations_amount: u32,
+}
+
+/// The header at the beginning of every slab.
+#[repr(C)]
+struct SlabHeader {
+    cache: u32,
+    previous: u32,
+    next: u32,
+    first_free: u32,
+    used_objects: u32,
+}
+
+/// The usage statistics of one cache.
+#[derive(Copy, Clone)]
+pub struct CacheStatistics {
+    pub object_size: u32,
+    pub objects_per_slab: u32,
+    pub slabs_amount: u32,
+    pub allocated_objects: u32,
+
+    /* amount of successful allocations since the cache creation */
+    pub allocations_amount: u32,
+}
+
+/// Returns the descriptor of the given cache.
+///
+/// Args:
+///
+/// `index` - the cache index
+///
+/// Returns:
+///
+/// the cache descriptor
+fn get_cache(index: u32) -> &'static mut CacheDescriptor {
+
+    let address = CACHES_ADDRESS + index * mem::size_of::<CacheDescriptor>() as u32;
+    unsafe { &mut *(address as *mut CacheDescriptor) }
+}
+
+/// Returns the header of the given slab.
+///
+/// Args:
+///
+/// `address` - the slab virtual address
+///
+/// Returns:
+///
+/// the slab header
+fn get_slab(address: u32) -> &'static mut SlabHeader {
+    unsafe { &mut *(address as *mut SlabHeader) }
+}
+
+/// Returns the amount of created caches.
+///
+/// Returns:
+///
+/// the caches amount
+fn get_caches_amount() -> u32 {
+    unsafe { *(CACHES_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the bitmap word containing the bit of the given slab page, and the bit mask of the page.
+///
+/// Args:
+///
+/// `page` - the page index into the slabs area
+///
+/// Returns:
+///
+/// the bitmap word and the page bit mask
+fn get_page_bit(page: u32) -> (&'static mut u32, u32) {
+
+    let address = SLABS_BITMAP_ADDRESS + (page / 32) * 4;
+    unsafe { (&mut *(address as *mut u32), 1 << (page % 32)) }
+}
+
+/// Returns the amount of mapped pages into the slabs area.
+///
+/// Returns:
+///
+/// the slabs pages amount
+pub fn get_slab_pages_amount() -> u32 {
+
+    (0..SLABS_PAGES_AMOUNT / 32)
+        .map(|word| {
+            let bits = unsafe { *((SLABS_BITMAP_ADDRESS + word * 4) as *const u32) };
+            bits.count_ones()
+        })
+        .fold(0, |total, amount| total + amount)
+}
+
+/// Maps one page of the slabs area on a new page frame.
+///
+/// Returns:
+///
+/// the page virtual address, None if there is no free page frame or no free page into the slabs area
+fn alloc_slab_page() -> Option<u32> {
+
+    let page = match (0..SLABS_PAGES_AMOUNT).find(|&page| {
+        let (word, mask) = get_page_bit(page);
+        *word & mask == 0
+    }) {
+        Some(page) => page,
+        None => return None,
+    };
+
+    let frame = match alloc_frame() {
+        Some(frame) => frame,
+        None => return None,
+    };
+
+    let address = SLABS_AREA_START + page * FRAME_BYTES_SIZE;
+
+    if !map(address, frame, PageFlags::WRITABLE | PageFlags::NO_EXECUTE) {
+        free_frame(frame);
+        return None;
+    }
+
+    let (word, mask) = get_page_bit(page);
+    *word |= mask;
+
+    Some(address)
+}
+
+/// Unmaps one page of the slabs area and frees its page frame.
+///
+/// Args:
+///
+/// `address` - the page virtual address
+fn free_slab_page(address: u32) {
+
+    if let Some(frame) = unmap(address) {
+        free_frame(frame);
+    }
+
+    let (word, mask) = get_page_bit((address - SLABS_AREA_START) / FRAME_BYTES_SIZE);
+    *word &= !mask;
+}
+
+/// Indicates if the given address is into a slab.
+///
+/// Args:
+///
+/// `address` - the virtual address
+///
+/// Returns:
+///
+/// true if the address is into a mapped page of the slabs area
+fn is_slab_address(address: u32) -> bool {
+
+    if address < SLABS_AREA_START ||
+        address >= SLABS_AREA_START + SLABS_PAGES_AMOUNT * FRAME_BYTES_SIZE {
+        return false;
+    }
+
+    let (word, mask) = get_page_bit((address - SLABS_AREA_START) / FRAME_BYTES_SIZE);
+    *word & mask != 0
+}
+
+/// Adds a slab at the beginning of a slabs list.
+///
+/// Args:
+///
+/// `list` - the list first slab address (0 if the list is empty)
+/// `slab` - the slab address
+fn push_slab(list: &mut u32, slab: u32) {
+
+    let header = get_slab(slab);
+    header.previous = 0;
+    header.next = *list;
+
+    if *list != 0 {
+        get_slab(*list).previous = slab;
+    }
+
+    *list = slab;
+}
+
+/// Removes a slab from a slabs list.
+///
+/// Args:
+///
+/// `list` - the list first slab address
+/// `slab` - the slab address
+fn remove_slab(list: &mut u32, slab: u32) {
+
+    let (previous, next) = {
+        let header = get_slab(slab);
+        (header.previous, header.next)
+    };
+
+    if previous == 0 {
+        *list = next;
+    } else {
+        get_slab(previous).next = next;
+    }
+
+    if next != 0 {
+        get_slab(next).previous = previous;
+    }
+}
+
+/// Creates a new slab for the given cache, every object of the slab is free.
+///
+/// Args:
+///
+/// `index` - the cache index
+///
+/// Returns:
+///
+/// the slab address, None if no page can be mapped
+fn create_slab(index: u32) -> Option<u32> {
+
+    let slab = match alloc_slab_page() {
+        Some(address) => address,
+        None => return None,
+    };
+
+    let cache = get_cache(index);
+
+    /* the free objects are linked in the address order */
+    let first_object = slab + OBJECTS_OFFSET;
+
+    for object in 0..cache.objects_per_slab {
+
+        let address = first_object + object * cache.object_size;
+        let next = if object + 1 == cache.objects_per_slab {
+            0
+        } else {
+            address + cache.object_size
+        };
+
+        unsafe { *(address as *mut u32) = next; }
+    }
+
+    let header = get_slab(slab);
+    header.cache = index;
+    header.first_free = first_object;
+    header.used_objects = 0;
+
+    cache.slabs_amount += 1;
+
+    Some(slab)
+}
+
+/// A cache of objects of one fixed size.
+#[derive(Copy, Clone)]
+pub struct ObjectCache {
+    index: u32,
+}
+
+impl ObjectCache {
+
+    /// Creates a new objects cache. The caches cannot be destroyed.
+    ///
+    /// Args:
+    ///
+    /// `object_size` - the size of every object (from 1 to 2048 bytes)
+    /// `constructor` - the function called on every allocated object, if any
+    ///
+    /// Returns:
+    ///
+    /// the cache, None if the size is not supported or if the maximum amount of caches is reached
+    pub fn create(object_size: u32, constructor: Option<ObjectConstructor>) -> Option<ObjectCache> {
+
+        if object_size == 0 || object_size > OBJECT_MAXIMUM_SIZE {
+            return None;
+        }
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let index = get_caches_amount();
+
+        if index == CACHES_MAX_AMOUNT {
+            unsafe { restore_interrupts(flags); }
+            return None;
+        }
+
+        /* every free object stores the address of the next free object */
+        let object_size = (object_size + OBJECT_GRANULARITY - 1) & !(OBJECT_GRANULARITY - 1);
+
+        let cache = get_cache(index);
+        cache.object_size = object_size;
+        cache.objects_per_slab = (FRAME_BYTES_SIZE - OBJECTS_OFFSET) / object_size;
+        cache.constructor = match constructor {
+            Some(constructor) => (constructor as *const ()) as u32,
+            None => 0,
+        };
+        cache.partial_slabs = 0;
+        cache.full_slabs = 0;
+        cache.slabs_amount = 0;
+        cache.allocated_objects = 0;
+        cache.allocations_amount = 0;
+
+        unsafe {
+            *(CACHES_AMOUNT_ADDRESS as *mut u32) = index + 1;
+            restore_interrupts(flags);
+        }
+
+        Some(ObjectCache { index: index })
+    }
+
+    /// Allocates one object, the constructor of the cache is called on the object.
+    ///
+    /// Returns:
+    ///
+    /// the object address, None if no slab can be created
+    pub fn alloc(&self) -> Option<u32> {
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let cache = get_cache(self.index);
+
+        if cache.partial_slabs == 0 {
+
+            match create_slab(self.index) {
+                Some(slab) => push_slab(&mut cache.partial_slabs, slab),
+                None => {
+                    unsafe { restore_interrupts(flags); }
+                    return None;
+                }
+            }
+        }
+
+        let slab = cache.partial_slabs;
+        let header = get_slab(slab);
+
+        let object = header.first_free;
+        header.first_free = unsafe { *(object as *const u32) };
+        header.used_objects += 1;
+
+        if header.first_free == 0 {
+            remove_slab(&mut cache.partial_slabs, slab);
+            push_slab(&mut cache.full_slabs, slab);
+        }
+
+        cache.allocated_objects += 1;
+        cache.allocations_amount = cache.allocations_amount.wrapping_add(1);
+
+        let constructor = cache.constructor;
+
+        unsafe { restore_interrupts(flags); }
+
+        if constructor != 0 {
+            let constructor: ObjectConstructor = unsafe { mem::transmute(constructor as usize) };
+            constructor(object);
+        }
+
+        Some(object)
+    }
+
+    /// Frees one object.
+    ///
+    /// Args:
+    ///
+    /// `address` - the object address (returned by `alloc`)
+    ///
+    /// Returns:
+    ///
+    /// false if the address is not an allocated object of the cache
+    pub fn free(&self, address: u32) -> bool {
+
+        if !is_slab_address(address) {
+            return false;
+        }
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let cache = get_cache(self.index);
+
+        let slab = address & !(FRAME_BYTES_SIZE - 1);
+        let header = get_slab(slab);
+
+        let offset = address - slab;
+        let valid_object = header.cache == self.index &&
+            offset >= OBJECTS_OFFSET &&
+            (offset - OBJECTS_OFFSET) % cache.object_size == 0 &&
+            (offset - OBJECTS_OFFSET) / cache.object_size < cache.objects_per_slab;
+
+        /* the object must not be free already */
+        let mut free_object = header.first_free;
+
+        while valid_object && free_object != 0 && free_object != address {
+            free_object = unsafe { *(free_object as *const u32) };
+        }
+
+        if !valid_object || free_object == address {
+            unsafe { restore_interrupts(flags); }
+            return false;
+        }
+
+        if header.first_free == 0 {
+            remove_slab(&mut cache.full_slabs, slab);
+            push_slab(&mut cache.partial_slabs, slab);
+        }
+
+        unsafe { *(address as *mut u32) = header.first_free; }
+        header.first_free = address;
+        header.used_objects -= 1;
+
+        cache.allocated_objects -= 1;
+
+        unsafe { restore_interrupts(flags); }
+
+        true
+    }
+
+    /// Releases the empty slabs of the cache (their page frames are freed).
+    ///
+    /// Returns:
+    ///
+    /// the amount of released slabs
+    pub fn shrink(&self) -> u32 {
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let cache = get_cache(self.index);
+
+        let mut released: u32 = 0;
+        let mut slab = cache.partial_slabs;
+
+        while slab != 0 {
+
+            let (next, used_objects) = {
+                let header = get_slab(slab);
+                (header.next, header.used_objects)
+            };
+
+            if used_objects == 0 {
+                remove_slab(&mut cache.partial_slabs, slab);
+                free_slab_page(slab);
+                cache.slabs_amount -= 1;
+                released += 1;
+            }
+
+            slab = next;
+        }
+
+        unsafe { restore_interrupts(flags); }
+
+        released
+    }
+
+    /// Returns the usage statistics of the cache.
+    ///
+    /// Returns:
+    ///
+    /// the cache statistics
+    pub fn get_statistics(&self) -> CacheStatistics {
+
+        let cache = get_cache(self.index);
+
+        CacheStatistics {
+            object_size: cache.object_size,
+            objects_per_slab: cache.objects_per_slab,
+            slabs_amount: cache.slabs_amount,
+            allocated_objects: cache.allocated_objects,
+            allocations_amount: cache.allocations_amount,
+        }
+    }
+}
+
+/// Initializes the slab allocator and creates the general purpose caches
+/// (from 16 bytes to 2048 bytes), must be called after paging initialization.
+pub fn initialize_slab_allocator() {
+
+    const SLABS_BITMAP_WORDS_AMOUNT: u32 = SLABS_PAGES_AMOUNT / 32;
+
+    for index in 0..SLABS_BITMAP_WORDS_AMOUNT {
+        unsafe { *((SLABS_BITMAP_ADDRESS + index * 4) as *mut u32) = 0; }
+    }
+
+    unsafe { *(CACHES_AMOUNT_ADDRESS as *mut u32) = 0; }
+
+    for &size in SIZE_CACHES.iter() {
+        ObjectCache::create(size, None);
+    }
+}
+
+/// Returns the general purpose cache of the smallest objects size large enough for the given size.
+///
+/// Args:
+///
+/// `size` - the requested size
+///
+/// Returns:
+///
+/// the cache, None if the size is larger than 2048 bytes
+pub fn get_size_cache(size: u32) -> Option<ObjectCache> {
+
+    SIZE_CACHES
+        .iter()
+        .position(|&cache_size| cache_size >= size)
+        .map(|index| ObjectCache { index: index as u32 })
+}
+
+/// Allocates an object from the general purpose caches.
+///
+/// Args:
+///
+/// `size` - the object size (2048 bytes at most)
+///
+/// Returns:
+///
+/// the object address, None if the size is not supported or if no slab can be created
+pub fn slab_alloc(size: u32) -> Option<u32> {
+    get_size_cache(size).and_then(|cache| cache.alloc())
+}
+
+/// Frees an object allocated from the general purpose caches.
+///
+/// Args:
+///
+/// `address` - the object address (returned by `slab_alloc`)
+/// `size` - the object size (used for the allocation)
+///
+/// Returns:
+///
+/// false if the address is not an allocated object of the cache used for the size
+pub fn slab_free(address: u32, size: u32) -> bool {
+
+    match get_size_cache(size) {
+        Some(cache) => cache.free(address),
+        None => false,
+    }
+}
+
+/// Returns the statistics of every created cache.
+///
+/// Args:
+///
+/// `statistics` - the array to fill
+///
+/// Returns:
+///
+/// the amount of caches
+pub fn get_caches_statistics(statistics: &mut [CacheStatistics]) -> usize {
+
+    let amount = (get_caches_amount() as usize).min(statistics.len());
+
+    for index in 0..amount {
+        statistics[index] = ObjectCache { index: index as u32 }.get_statistics();
+    }
+
+    amount
+}
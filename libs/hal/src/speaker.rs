@@ -0,0 +1,257 @@
+//! PC speaker driver, driven by the PIT counter 2
+//!
+//! The PIT counter 2 output is connected to the PC speaker: in square wave mode,
+//! the counter generates a tone at the PIT base frequency divided by its reload value.
+//! The sound is stopped by a kernel timer, so playing a tone or a notes sequence never blocks.
+
+use core::slice;
+use core::time::Duration;
+
+use io::{
+    inb,
+    outb,
+};
+
+use pit::PIT_BASE_FREQUENCY;
+
+use timer::Timer;
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   identifier of the current sound (incremented by every new sound),
+   address of the notes sequence being played,
+   amount of notes of the sequence,
+   index of the next note to play */
+const SPEAKER_SOUND_ID_ADDRESS: u32 = 0xC0013080;
+const SPEAKER_NOTES_ADDRESS: u32 = 0xC0013084;
+const SPEAKER_NOTES_AMOUNT_ADDRESS: u32 = 0xC0013088;
+const SPEAKER_NEXT_NOTE_ADDRESS: u32 = 0xC001308C;
+
+/* the PIT counter 2 data port, the PIT control word port
+   and the system control port B (counter 2 gate, PC speaker) */
+const PIT_COUNTER_2_PORT: u16 = 0x42;
+const PIT_CONTROL_WORD_PORT: u16 = 0x43;
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+
+/* system control port B:
+   bit 0: gate of the PIT counter 2 (the counter only counts when set),
+   bit 1: PC speaker data (the counter 2 output is sent to the speaker when set) */
+const COUNTER_2_GATE: u8 = 0b00000001;
+const SPEAKER_DATA: u8 = 0b00000010;
+
+/// One note of a sequence.
+#[derive(Copy, Clone)]
+pub struct Note {
+
+    /* the tone frequency in Hz, 0 for a silence */
+    pub frequency: u32,
+
+    pub duration: Duration,
+}
+
+/// Starts to generate a tone until `stop_tone` is called.
+///
+/// Args:
+///
+/// `frequency` - the tone frequency in Hz (from 19 Hz to 20000 Hz)
+fn start_tone(frequency: u32) {
+
+    /* counter 2 (bits 6-7 set to 10), low byte then high byte (bits 4-5 set to 11),
+       mode 3 (square wave generator, bits 1-3 set to 011), binary counting;
+       check the modes documentation into `set_timer_frequency`:
+       OUT is set to 1 and to 0 half time of the counter value,
+       so the speaker membrane moves at the requested frequency */
+    const PIT_COUNTER_2_ICW: u8 = 0b10110110;
+
+    /* 0 is considered as 65536 by the PIT,
+       the square wave mode does not support the reload value 1 */
+    const MAXIMUM_RELOAD_VALUE: u32 = 65536;
+    let mut reload_value = PIT_BASE_FREQUENCY / frequency;
+
+    if reload_value > MAXIMUM_RELOAD_VALUE {
+        reload_value = MAXIMUM_RELOAD_VALUE;
+    }
+
+    if reload_value < 2 {
+        reload_value = 2;
+    }
+
+    unsafe {
+        outb(PIT_CONTROL_WORD_PORT, PIT_COUNTER_2_ICW);
+        outb(PIT_COUNTER_2_PORT, (reload_value & 0xff) as u8);
+        outb(PIT_COUNTER_2_PORT, ((reload_value >> 8) & 0xff) as u8);
+
+        /* the counter 2 starts and its output goes to the speaker */
+        let port_b = inb(SYSTEM_CONTROL_PORT_B);
+        outb(SYSTEM_CONTROL_PORT_B, port_b | COUNTER_2_GATE | SPEAKER_DATA);
+    }
+}
+
+/// Stops the current tone.
+fn stop_tone() {
+
+    unsafe {
+        let port_b = inb(SYSTEM_CONTROL_PORT_B);
+        outb(SYSTEM_CONTROL_PORT_B, port_b & !(COUNTER_2_GATE | SPEAKER_DATA));
+    }
+}
+
+/// Returns the identifier of a new sound, the timers of the previous sounds are ignored.
+///
+/// Returns:
+///
+/// the new sound identifier
+fn get_new_sound_id() -> u32 {
+
+    unsafe {
+        let identifier = (*(SPEAKER_SOUND_ID_ADDRESS as *const u32)).wrapping_add(1);
+        *(SPEAKER_SOUND_ID_ADDRESS as *mut u32) = identifier;
+        identifier
+    }
+}
+
+/// Returns the identifier of the current sound.
+///
+/// Returns:
+///
+/// the current sound identifier
+fn get_sound_id() -> u32 {
+    unsafe { *(SPEAKER_SOUND_ID_ADDRESS as *const u32) }
+}
+
+/// Called by a timer when a beep is finished, stops the tone
+/// if no other sound has been started since the beep.
+///
+/// Args:
+///
+/// `sound_id` - the identifier of the finished beep
+fn end_beep(sound_id: u32) {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    if sound_id == get_sound_id() {
+        stop_tone();
+    }
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Called by a timer when a note is finished, plays the next note of the sequence
+/// (if no other sound has been started since the sequence).
+///
+/// Args:
+///
+/// `sound_id` - the identifier of the sequence
+fn play_next_note(sound_id: u32) {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    if sound_id != get_sound_id() {
+        unsafe { restore_interrupts(flags); }
+        return;
+    }
+
+    let (notes, index) = unsafe {
+        (
+            slice::from_raw_parts(
+                *(SPEAKER_NOTES_ADDRESS as *const u32) as *const Note,
+                *(SPEAKER_NOTES_AMOUNT_ADDRESS as *const u32) as usize,
+            ),
+            *(SPEAKER_NEXT_NOTE_ADDRESS as *const u32) as usize,
+        )
+    };
+
+    stop_tone();
+
+    if index < notes.len() {
+
+        let note = notes[index];
+        unsafe { *(SPEAKER_NEXT_NOTE_ADDRESS as *mut u32) += 1; }
+
+        if note.frequency != 0 {
+            start_tone(note.frequency);
+        }
+
+        /* the sequence stops if no timer is available */
+        if Timer::after_with_argument(note.duration, play_next_note, sound_id).is_none() {
+            stop_tone();
+        }
+    }
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Initializes the speaker, must be called after the PIT initialization.
+pub fn initialize_speaker() {
+
+    unsafe {
+        *(SPEAKER_SOUND_ID_ADDRESS as *mut u32) = 0;
+        *(SPEAKER_NOTES_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(SPEAKER_NEXT_NOTE_ADDRESS as *mut u32) = 0;
+    }
+
+    stop_tone();
+}
+
+/// Plays a tone during the given duration, returns immediately (the tone is stopped by a kernel timer).
+/// Stops the current sound.
+///
+/// Args:
+///
+/// `frequency` - the tone frequency in Hz (from 19 Hz to 20000 Hz), 0 for a silence
+/// `duration` - the tone duration
+pub fn beep(frequency: u32, duration: Duration) {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let sound_id = get_new_sound_id();
+    stop_tone();
+
+    if frequency != 0 {
+        start_tone(frequency);
+    }
+
+    if Timer::after_with_argument(duration, end_beep, sound_id).is_none() {
+        stop_tone();
+    }
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Plays the given notes one after the other, returns immediately (every note is started
+/// by a kernel timer when the previous one is finished). Stops the current sound.
+///
+/// Args:
+///
+/// `notes` - the notes sequence
+pub fn play_notes(notes: &'static [Note]) {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    let sound_id = get_new_sound_id();
+
+    unsafe {
+        *(SPEAKER_NOTES_ADDRESS as *mut u32) = notes.as_ptr() as u32;
+        *(SPEAKER_NOTES_AMOUNT_ADDRESS as *mut u32) = notes.len() as u32;
+        *(SPEAKER_NEXT_NOTE_ADDRESS as *mut u32) = 0;
+    }
+
+    play_next_note(sound_id);
+
+    unsafe { restore_interrupts(flags); }
+}
+
+/// Stops the current sound (tone or notes sequence).
+pub fn stop_speaker() {
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    get_new_sound_id();
+    stop_tone();
+
+    unsafe { restore_interrupts(flags); }
+}
@@ -0,0 +1,122 @@
+//! Kernel stack with a guard page
+//!
+//! Stage3 starts the kernel with a stack at 0xC009FFF0, nothing prevents this stack
+//! from overwriting the memory below it. The kernel switches to a stack mapped
+//! right below the kernel heap area: the page below the stack is never mapped (guard page),
+//! so a stack overflow raises a page fault instead of corrupting memory.
+//!
+//! The page fault handler cannot be called with an overflowed stack (the CPU pushes
+//! the interrupt frame on the current stack), so the CPU raises a double fault,
+//! handled by a dedicated task with its own stack (check `gdt.rs`).
+
+use frames::{
+    alloc_frame,
+    free_frame,
+    FRAME_BYTES_SIZE,
+};
+
+use paging::{
+    map,
+    PageFlags,
+};
+
+/* the kernel stack (64 KBytes), right below the kernel heap area,
+   the guard page is the page below the stack */
+const KERNEL_STACK_TOP: u32 = 0xD0000000;
+const KERNEL_STACK_SIZE: u32 = 0x10000;
+const KERNEL_STACK_GUARD_PAGE: u32 = KERNEL_STACK_TOP - KERNEL_STACK_SIZE - FRAME_BYTES_SIZE;
+
+/* the stack of the double fault task (16 KBytes), below the kernel stack guard page,
+   the page below is never mapped neither */
+pub const DOUBLE_FAULT_STACK_TOP: u32 = 0xCFFE4000;
+const DOUBLE_FAULT_STACK_SIZE: u32 = 0x4000;
+
+/// Maps the pages of the given stack on new page frames (not executable).
+///
+/// Args:
+///
+/// `top` - the address following the last byte of the stack
+/// `size` - the stack size (multiple of the page size)
+///
+/// Returns:
+///
+/// false if there is not enough free page frames
+fn map_stack(top: u32, size: u32) -> bool {
+
+    let mut address = top - size;
+
+    while address < top {
+
+        let frame = match alloc_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        if !map(address, frame, PageFlags::WRITABLE | PageFlags::NO_EXECUTE) {
+            free_frame(frame);
+            return false;
+        }
+
+        address += FRAME_BYTES_SIZE;
+    }
+
+    true
+}
+
+/// Maps the kernel stack and the double fault task stack,
+/// must be called after paging initialization.
+///
+/// Returns:
+///
+/// false if there is not enough free page frames
+pub fn initialize_kernel_stacks() -> bool {
+    map_stack(KERNEL_STACK_TOP, KERNEL_STACK_SIZE) &&
+        map_stack(DOUBLE_FAULT_STACK_TOP, DOUBLE_FAULT_STACK_SIZE)
+}
+
+/// Switches to the kernel stack and calls the given function, the current stack is abandoned.
+/// The kernel stack must have been mapped with `initialize_kernel_stacks`.
+///
+/// Args:
+///
+/// `entry` - the function to execute on the kernel stack (never returns)
+pub fn switch_to_kernel_stack(entry: fn() -> !) -> ! {
+
+    /* ebp is cleared, so the stack frames chain stops at the entry function;
+       the entry address is forced into eax, so it cannot be into the cleared ebp */
+    unsafe {
+        llvm_asm!("
+            mov esp, $0
+            xor ebp, ebp
+            call $1
+            "
+            :: "r" (KERNEL_STACK_TOP), "{eax}" (entry as u32)
+            : "memory"
+            : "intel", "volatile"
+        );
+    }
+
+    loop {}
+}
+
+/// Returns the size of the kernel stacks (kernel stack and double fault stack).
+///
+/// Returns:
+///
+/// the stacks size in bytes
+pub fn get_kernel_stacks_bytes_size() -> u32 {
+    KERNEL_STACK_SIZE + DOUBLE_FAULT_STACK_SIZE
+}
+
+/// Indicates if the given address is into the guard page of the kernel stack.
+///
+/// Args:
+///
+/// `address` - the virtual address
+///
+/// Returns:
+///
+/// true if the address is into the guard page (a page fault at this address is a stack overflow)
+pub fn is_kernel_stack_guard_page(address: u32) -> bool {
+    address >= KERNEL_STACK_GUARD_PAGE && address < KERNEL_STACK_GUARD_PAGE + FRAME_BYTES_SIZE
+}
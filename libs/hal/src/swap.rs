@@ -0,0 +1,470 @@
+//! Demand paging and swapping
+//!
+//! The pageable areas are virtual areas whose pages are only mapped when they are accessed
+//! (demand paging): their page table entries are not present, with the `ON_DEMAND` marker.
+//! The first access raises a page fault, the page fault handler maps a zeroed page frame.
+//!
+//! When there is no free page frame anymore, one page of the pageable areas is written into
+//! the swap area of the hard drive and its page frame is reused. The swapped page table entry
+//! is not present, with the `SWAPPED` marker and the swap slot index into its address bits:
+//! the page is read back from the swap area when it is accessed again.
+//!
+//! The swapped page is selected with the clock (second chance) algorithm: the clock hand goes
+//! through the pages of the pageable areas, a page accessed since the last pass of the hand
+//! (accessed bit set by the CPU) gets a second chance (its accessed bit is cleared),
+//! the first page that was not accessed is swapped.
+
+use ata::{
+    get_ata_sectors_amount,
+    read_sectors,
+    write_sectors,
+    SECTOR_BYTES_SIZE,
+};
+
+use frames::{
+    alloc_frame,
+    free_frame,
+    FRAME_BYTES_SIZE,
+};
+
+use paging::{
+    map,
+    unmap,
+    get_page_table_entry,
+    set_page_table_entry,
+    set_page_flags,
+    PageFlags,
+};
+
+use cow::get_frame_references;
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   the amount of free swap slots,
+   the virtual address of the page pointed by the clock hand,
+   the amount of pageable areas,
+   the amount of pages written into the swap area,
+   the amount of pages read from the swap area,
+   the amount of swap slots (0 if there is no swap area),
+   the pageable areas (start and end addresses),
+   the swap slots bitmap (1 if the slot is used) */
+const FREE_SWAP_SLOTS_AMOUNT_ADDRESS: u32 = 0xC00130BC;
+const CLOCK_HAND_ADDRESS: u32 = 0xC00130C0;
+const PAGEABLE_AREAS_AMOUNT_ADDRESS: u32 = 0xC00130C4;
+const SWAPPED_OUT_PAGES_AMOUNT_ADDRESS: u32 = 0xC00130C8;
+const SWAPPED_IN_PAGES_AMOUNT_ADDRESS: u32 = 0xC00130CC;
+const SWAP_SLOTS_AMOUNT_ADDRESS: u32 = 0xC00130D0;
+const PAGEABLE_AREAS_ADDRESS: u32 = 0xC0013680;
+const SWAP_SLOTS_BITMAP_ADDRESS: u32 = 0xC0013700;
+
+pub const PAGEABLE_AREAS_MAX_AMOUNT: u32 = 16;
+
+/* the swap area is after the FAT16 file system of the hard drive (sector 20160),
+   every slot contains one page (8 sectors) */
+const SWAP_FIRST_SECTOR: u32 = 20160;
+const SLOT_SECTORS_AMOUNT: u32 = FRAME_BYTES_SIZE / SECTOR_BYTES_SIZE;
+const SWAP_SLOTS_MAX_AMOUNT: u32 = 1024;
+
+/* virtual page used to fill a page frame before it is mapped */
+const PAGE_IN_ADDRESS: u32 = 0xEFFFE000;
+
+/// Returns the given pageable area.
+///
+/// Args:
+///
+/// `index` - the area index
+///
+/// Returns:
+///
+/// the area start and end addresses (end excluded)
+fn get_pageable_area(index: u32) -> (u32, u32) {
+
+    let address = PAGEABLE_AREAS_ADDRESS + index * 8;
+    unsafe { (*(address as *const u32), *((address + 4) as *const u32)) }
+}
+
+/// Returns the amount of pageable areas.
+///
+/// Returns:
+///
+/// the areas amount
+fn get_pageable_areas_amount() -> u32 {
+    unsafe { *(PAGEABLE_AREAS_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the bitmap word containing the bit of the given swap slot, and the bit mask of the slot.
+///
+/// Args:
+///
+/// `slot` - the slot index
+///
+/// Returns:
+///
+/// the bitmap word and the slot bit mask
+fn get_slot_bit(slot: u32) -> (&'static mut u32, u32) {
+
+    let address = SWAP_SLOTS_BITMAP_ADDRESS + (slot / 32) * 4;
+    unsafe { (&mut *(address as *mut u32), 1 << (slot % 32)) }
+}
+
+/// Allocates one swap slot.
+///
+/// Returns:
+///
+/// the slot index, None if the swap area is full
+fn alloc_slot() -> Option<u32> {
+
+    let slot = (0..get_swap_slots_amount()).find(|&slot| {
+        let (word, mask) = get_slot_bit(slot);
+        *word & mask == 0
+    });
+
+    if let Some(slot) = slot {
+        let (word, mask) = get_slot_bit(slot);
+        *word |= mask;
+        unsafe { *(FREE_SWAP_SLOTS_AMOUNT_ADDRESS as *mut u32) -= 1; }
+    }
+
+    slot
+}
+
+/// Frees one swap slot.
+///
+/// Args:
+///
+/// `slot` - the slot index
+fn free_slot(slot: u32) {
+
+    let (word, mask) = get_slot_bit(slot);
+
+    if *word & mask != 0 {
+        *word &= !mask;
+        unsafe { *(FREE_SWAP_SLOTS_AMOUNT_ADDRESS as *mut u32) += 1; }
+    }
+}
+
+/// Returns the page following the given page into the pageable areas
+/// (the first page of the next area after the last page of an area).
+///
+/// Args:
+///
+/// `page` - the current page
+///
+/// Returns:
+///
+/// the next page
+fn get_next_pageable_page(page: u32) -> u32 {
+
+    let amount = get_pageable_areas_amount();
+
+    for index in 0..amount {
+
+        let (start, end) = get_pageable_area(index);
+
+        if page >= start && page < end {
+
+            if page + FRAME_BYTES_SIZE < end {
+                return page + FRAME_BYTES_SIZE;
+            }
+
+            return get_pageable_area((index + 1) % amount).0;
+        }
+    }
+
+    get_pageable_area(0).0
+}
+
+/// Writes one page of the pageable areas into the swap area, selected with the clock algorithm,
+/// and frees its page frame. The shared pages (copy-on-write) are never swapped.
+///
+/// Returns:
+///
+/// false if no page can be swapped (no resident page, swap area full or drive error)
+pub fn swap_out_page() -> bool {
+
+    let areas_amount = get_pageable_areas_amount();
+    if areas_amount == 0 || get_free_swap_slots_amount() == 0 {
+        return false;
+    }
+
+    let flags = unsafe { save_and_disable_interrupts() };
+
+    /* two passes at most: every accessed page loses its second chance during the first pass */
+    let pages_amount = (0..areas_amount)
+        .map(|index| {
+            let (start, end) = get_pageable_area(index);
+            (end - start) / FRAME_BYTES_SIZE
+        })
+        .fold(0, |total, amount| total + amount);
+
+    let mut hand = unsafe { *(CLOCK_HAND_ADDRESS as *const u32) };
+    let mut swapped = false;
+
+    for _ in 0..pages_amount * 2 {
+
+        let page = hand;
+        hand = get_next_pageable_page(hand);
+
+        let entry = match get_page_table_entry(page) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        let page_flags = entry.get_flags();
+
+        if !entry.is_present() ||
+            page_flags.contains(PageFlags::COPY_ON_WRITE) ||
+            get_frame_references(entry.get_address() as u32) != 0 {
+            continue;
+        }
+
+        if page_flags.contains(PageFlags::ACCESSED) {
+            set_page_flags(page, page_flags & !PageFlags::ACCESSED);
+            continue;
+        }
+
+        let slot = match alloc_slot() {
+            Some(slot) => slot,
+            None => break,
+        };
+
+        let sector = SWAP_FIRST_SECTOR + slot * SLOT_SECTORS_AMOUNT;
+
+        if !write_sectors(sector, SLOT_SECTORS_AMOUNT as u8, page) {
+            free_slot(slot);
+            break;
+        }
+
+        let swapped_flags = (page_flags &
+            !(PageFlags::PRESENT | PageFlags::ACCESSED | PageFlags::DIRTY)) |
+            PageFlags::SWAPPED;
+
+        set_page_table_entry(page, slot * FRAME_BYTES_SIZE, swapped_flags);
+        free_frame(entry.get_address() as u32);
+
+        unsafe { *(SWAPPED_OUT_PAGES_AMOUNT_ADDRESS as *mut u32) += 1; }
+
+        swapped = true;
+        break;
+    }
+
+    unsafe {
+        *(CLOCK_HAND_ADDRESS as *mut u32) = hand;
+        restore_interrupts(flags);
+    }
+
+    swapped
+}
+
+/// Allocates one page frame, swaps one page out if there is no free page frame.
+///
+/// Returns:
+///
+/// the page frame physical address, None if there is no free page frame and no page can be swapped
+fn alloc_pageable_frame() -> Option<u32> {
+
+    if let Some(frame) = alloc_frame() {
+        return Some(frame);
+    }
+
+    if !swap_out_page() {
+        return None;
+    }
+
+    alloc_frame()
+}
+
+/// Maps the page at the given address if it is a demand page or a swapped page,
+/// called by the page fault handler when a not present page is accessed.
+///
+/// Args:
+///
+/// `address` - the accessed virtual address
+///
+/// Returns:
+///
+/// false if the page is neither a demand page nor a swapped page,
+/// or if it cannot be mapped (no page frame or drive error)
+pub fn handle_page_in(address: u32) -> bool {
+
+    let page = address & !(FRAME_BYTES_SIZE - 1);
+
+    let entry = match get_page_table_entry(page) {
+        Some(entry) => entry,
+        None => return false,
+    };
+
+    let page_flags = entry.get_flags();
+
+    if entry.is_present() ||
+        !(page_flags.contains(PageFlags::SWAPPED) || page_flags.contains(PageFlags::ON_DEMAND)) {
+        return false;
+    }
+
+    let frame = match alloc_pageable_frame() {
+        Some(frame) => frame,
+        None => return false,
+    };
+
+    /* the page frame is filled through a temporary mapping,
+       so read only pages are filled too */
+    if !map(PAGE_IN_ADDRESS, frame, PageFlags::WRITABLE | PageFlags::NO_EXECUTE) {
+        free_frame(frame);
+        return false;
+    }
+
+    if page_flags.contains(PageFlags::SWAPPED) {
+
+        let slot = entry.get_address() as u32 / FRAME_BYTES_SIZE;
+        let sector = SWAP_FIRST_SECTOR + slot * SLOT_SECTORS_AMOUNT;
+
+        if !read_sectors(sector, SLOT_SECTORS_AMOUNT as u8, PAGE_IN_ADDRESS) {
+            unmap(PAGE_IN_ADDRESS);
+            free_frame(frame);
+            return false;
+        }
+
+        free_slot(slot);
+        unsafe { *(SWAPPED_IN_PAGES_AMOUNT_ADDRESS as *mut u32) += 1; }
+
+    } else {
+
+        for offset in 0..FRAME_BYTES_SIZE / 4 {
+            unsafe { *((PAGE_IN_ADDRESS + offset * 4) as *mut u32) = 0; }
+        }
+    }
+
+    unmap(PAGE_IN_ADDRESS);
+
+    let resident_flags = page_flags & !(PageFlags::SWAPPED | PageFlags::ON_DEMAND);
+    map(page, frame, resident_flags)
+}
+
+/// Creates a pageable area: its pages are mapped on the first access,
+/// and might be swapped when there is no free page frame anymore.
+///
+/// Args:
+///
+/// `address` - the virtual address of the area (aligned on 4096 bytes)
+/// `length` - the area length in bytes
+/// `flags` - the properties of the pages (the present flag is ignored)
+///
+/// Returns:
+///
+/// false if the maximum amount of areas is reached, or if the page tables cannot be allocated
+pub fn create_pageable_area(address: u32, length: u32, flags: PageFlags) -> bool {
+
+    let amount = get_pageable_areas_amount();
+    let end = address + ((length + FRAME_BYTES_SIZE - 1) & !(FRAME_BYTES_SIZE - 1));
+
+    if amount == PAGEABLE_AREAS_MAX_AMOUNT || length == 0 {
+        return false;
+    }
+
+    let demand_flags = (flags & !PageFlags::PRESENT) | PageFlags::ON_DEMAND;
+    let mut page = address;
+
+    while page < end {
+
+        if !set_page_table_entry(page, 0, demand_flags) {
+            return false;
+        }
+
+        page += FRAME_BYTES_SIZE;
+    }
+
+    let interrupts = unsafe { save_and_disable_interrupts() };
+
+    unsafe {
+        let area_address = PAGEABLE_AREAS_ADDRESS + amount * 8;
+        *(area_address as *mut u32) = address;
+        *((area_address + 4) as *mut u32) = end;
+        *(PAGEABLE_AREAS_AMOUNT_ADDRESS as *mut u32) = amount + 1;
+
+        if amount == 0 {
+            *(CLOCK_HAND_ADDRESS as *mut u32) = address;
+        }
+
+        restore_interrupts(interrupts);
+    }
+
+    true
+}
+
+/// Initializes the swap area, after the FAT16 file system of the hard drive.
+/// Must be called after the ATA driver initialization.
+///
+/// Returns:
+///
+/// false if there is no swap area (demand paging still works without swapping)
+pub fn initialize_swap() -> bool {
+
+    const BITMAP_WORDS_AMOUNT: u32 = SWAP_SLOTS_MAX_AMOUNT / 32;
+
+    for index in 0..BITMAP_WORDS_AMOUNT {
+        unsafe { *((SWAP_SLOTS_BITMAP_ADDRESS + index * 4) as *mut u32) = 0; }
+    }
+
+    let sectors_amount = get_ata_sectors_amount();
+
+    let mut slots_amount = if sectors_amount > SWAP_FIRST_SECTOR {
+        (sectors_amount - SWAP_FIRST_SECTOR) / SLOT_SECTORS_AMOUNT
+    } else {
+        0
+    };
+
+    if slots_amount > SWAP_SLOTS_MAX_AMOUNT {
+        slots_amount = SWAP_SLOTS_MAX_AMOUNT;
+    }
+
+    unsafe {
+        *(SWAP_SLOTS_AMOUNT_ADDRESS as *mut u32) = slots_amount;
+        *(FREE_SWAP_SLOTS_AMOUNT_ADDRESS as *mut u32) = slots_amount;
+        *(PAGEABLE_AREAS_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(CLOCK_HAND_ADDRESS as *mut u32) = 0;
+        *(SWAPPED_OUT_PAGES_AMOUNT_ADDRESS as *mut u32) = 0;
+        *(SWAPPED_IN_PAGES_AMOUNT_ADDRESS as *mut u32) = 0;
+    }
+
+    slots_amount != 0
+}
+
+/// Returns the amount of slots of the swap area (one page per slot).
+///
+/// Returns:
+///
+/// the slots amount, 0 if there is no swap area
+pub fn get_swap_slots_amount() -> u32 {
+    unsafe { *(SWAP_SLOTS_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the amount of free slots of the swap area.
+///
+/// Returns:
+///
+/// the free slots amount
+pub fn get_free_swap_slots_amount() -> u32 {
+    unsafe { *(FREE_SWAP_SLOTS_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the amount of pages written into the swap area since the initialization.
+///
+/// Returns:
+///
+/// the swapped out pages amount
+pub fn get_swapped_out_pages_amount() -> u32 {
+    unsafe { *(SWAPPED_OUT_PAGES_AMOUNT_ADDRESS as *const u32) }
+}
+
+/// Returns the amount of pages read from the swap area since the initialization.
+///
+/// Returns:
+///
+/// the swapped in pages amount
+pub fn get_swapped_in_pages_amount() -> u32 {
+    unsafe { *(SWAPPED_IN_PAGES_AMOUNT_ADDRESS as *const u32) }
+}
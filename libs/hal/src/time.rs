@@ -0,0 +1,162 @@
+//! Monotonic clock and sleep routines, based on the PIT ticks
+
+use core::ops::{
+    Add,
+    Sub,
+};
+
+use core::time::Duration;
+
+use pit::{
+    get_monotonic_time,
+    get_tick_duration,
+};
+
+use tsc::get_tsc_time;
+
+use wait_for_interrupt;
+
+/// A point in time, measured from the PIT initialization. Never goes backward.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    nanoseconds: u64,
+}
+
+impl Instant {
+
+    /// Returns the current point in time.
+    ///
+    /// Returns:
+    ///
+    /// the current instant
+    pub fn now() -> Instant {
+        Instant {
+            nanoseconds: get_monotonic_time(),
+        }
+    }
+
+    /// Returns the current point in time with a nanosecond resolution (using the TSC if calibrated).
+    ///
+    /// Returns:
+    ///
+    /// the current instant
+    pub fn now_precise() -> Instant {
+        Instant {
+            nanoseconds: get_tsc_time(),
+        }
+    }
+
+    /// Returns the elapsed time since the PIT initialization.
+    ///
+    /// Returns:
+    ///
+    /// the elapsed time
+    pub fn since_boot(&self) -> Duration {
+        Duration::from_nanos(self.nanoseconds)
+    }
+
+    /// Returns the elapsed time between an earlier instant and this one.
+    ///
+    /// Args:
+    ///
+    /// `earlier` - the earlier instant
+    ///
+    /// Returns:
+    ///
+    /// the elapsed time, zero if `earlier` is after this instant
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanoseconds.saturating_sub(earlier.nanoseconds))
+    }
+
+    /// Returns the elapsed time since this instant.
+    ///
+    /// Returns:
+    ///
+    /// the elapsed time
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the elapsed time since the PIT initialization in milliseconds.
+    ///
+    /// Returns:
+    ///
+    /// the elapsed milliseconds
+    pub fn as_millis(&self) -> u64 {
+        self.nanoseconds / 1000000
+    }
+
+    /// Returns the elapsed time since the PIT initialization in microseconds.
+    ///
+    /// Returns:
+    ///
+    /// the elapsed microseconds
+    pub fn as_micros(&self) -> u64 {
+        self.nanoseconds / 1000
+    }
+}
+
+impl Add<Duration> for Instant {
+
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+
+        let nanoseconds = duration.as_secs() * 1000000000 + duration.subsec_nanos() as u64;
+
+        Instant {
+            nanoseconds: self.nanoseconds.saturating_add(nanoseconds),
+        }
+    }
+}
+
+impl Sub<Instant> for Instant {
+
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Waits for the given duration. The CPU is halted until the next tick as long as
+/// the remaining time is longer than one tick, the remaining time is spent polling the clock.
+/// Enables interrupts (the ticks are required to wake the CPU up).
+///
+/// Args:
+///
+/// `duration` - the time to wait
+pub fn sleep(duration: Duration) {
+
+    let deadline = Instant::now() + duration;
+
+    loop {
+
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+
+        if deadline.duration_since(now) >= Duration::from_nanos(get_tick_duration() as u64) {
+            unsafe { wait_for_interrupt(); }
+        }
+    }
+}
+
+/// Waits for the given amount of milliseconds (check `sleep`).
+///
+/// Args:
+///
+/// `milliseconds` - the time to wait in milliseconds
+pub fn sleep_ms(milliseconds: u64) {
+    sleep(Duration::from_millis(milliseconds));
+}
+
+/// Waits for the given amount of microseconds (check `sleep`).
+///
+/// Args:
+///
+/// `microseconds` - the time to wait in microseconds
+pub fn sleep_us(microseconds: u64) {
+    sleep(Duration::from_micros(microseconds));
+}
@@ -0,0 +1,449 @@
+//! Kernel software timers (one-shot and periodic), driven by the PIT ticks
+//!
+//! Timers are stored into a hashed timer wheel of 256 slots: a timer expiring
+//! at the tick `t` is linked into the slot `t % 256`. At every tick, only the
+//! timers of the current slot are checked, so expiring timers stays cheap
+//! whatever the amount of pending timers. A timer expiring more than 256 ticks
+//! later stays into its slot during several rounds of the wheel.
+//!
+//! Expired timers callbacks are not called from the PIT interrupt routine,
+//! they are enqueued as deferred work (so they run with interrupts enabled).
+//! When the deferred work queue is full, the remaining expired timers are delayed
+//! to the next tick instead of being dropped.
+//!
+//! The PIT must be initialized before any timer is created (the durations are converted
+//! into ticks): until then, no timer can be created or rescheduled.
+
+use core::mem;
+use core::time::Duration;
+
+use deferred::defer_work;
+use pit::get_tick_duration;
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   index of the first free timer (0xFFFF if none),
+   the timers pool,
+   the first timer index of every wheel slot (0xFFFF if none) */
+const FREE_TIMERS_HEAD_ADDRESS: u32 = 0xC0013048;
+const TIMERS_ADDRESS: u32 = 0xC0020000;
+const TIMER_WHEEL_ADDRESS: u32 = 0xC0038000;
+
+const TIMERS_AMOUNT: u16 = 4096;
+
+/* the amount of slots must be a power of 2,
+   so the slot of a tick is computed with a simple mask */
+const TIMER_WHEEL_SLOTS_AMOUNT: u32 = 256;
+
+/* index used to indicate the end of a timers list */
+const NO_TIMER: u16 = 0xFFFF;
+
+/// A function called when a timer expires, with the argument given when the timer was created.
+pub type TimerCallback = fn(u32);
+
+/// One timer of the pool, linked into a wheel slot when pending,
+/// linked into the free list otherwise.
+#[repr(C)]
+struct TimerEntry {
+    expiration_tick: u32,
+
+    /* 0 for one-shot timers */
+    period_ticks: u32,
+
+    callback: u32,
+    argument: u32,
+
+    previous: u16,
+    next: u16,
+
+    /* incremented everytime the entry is released,
+       so an old handle cannot modify a new timer using the same entry */
+    generation: u16,
+
+    pending: bool,
+}
+
+/// Handle of one kernel timer.
+#[derive(Copy, Clone)]
+pub struct Timer {
+    index: u16,
+    generation: u16,
+}
+
+/// Returns the timer entry at the given index.
+///
+/// Args:
+///
+/// `index` - the entry index into the pool
+///
+/// Returns:
+///
+/// the timer entry
+fn get_entry(index: u16) -> &'static mut TimerEntry {
+
+    let address = TIMERS_ADDRESS + (index as u32) * mem::size_of::<TimerEntry>() as u32;
+    unsafe { &mut *(address as *mut TimerEntry) }
+}
+
+/// Returns the first timer of the given wheel slot.
+///
+/// Args:
+///
+/// `slot` - the wheel slot
+///
+/// Returns:
+///
+/// the slot list head
+fn get_slot_head(slot: u32) -> &'static mut u16 {
+    unsafe { &mut *((TIMER_WHEEL_ADDRESS + slot * 2) as *mut u16) }
+}
+
+/// Returns the first free timer index.
+///
+/// Returns:
+///
+/// the free list head
+fn get_free_head() -> &'static mut u16 {
+    unsafe { &mut *(FREE_TIMERS_HEAD_ADDRESS as *mut u16) }
+}
+
+/// Returns the current ticks amount.
+///
+/// Returns:
+///
+/// the ticks amount
+fn get_current_tick() -> u32 {
+    unsafe { *(0xC0011806 as *const u32) }
+}
+
+/// Converts a duration into an amount of ticks (rounded up, at least one tick).
+/// The tick duration must not be null (the PIT must be initialized).
+///
+/// Args:
+///
+/// `duration` - the duration to convert
+///
+/// Returns:
+///
+/// the ticks amount
+fn duration_to_ticks(duration: Duration) -> u32 {
+
+    let nanoseconds = duration.as_secs() * 1000000000 + duration.subsec_nanos() as u64;
+    let tick_duration = get_tick_duration() as u64;
+    let ticks = (nanoseconds + tick_duration - 1) / tick_duration;
+
+    if ticks == 0 {
+        return 1;
+    }
+
+    if ticks > 0x7FFFFFFF {
+        return 0x7FFFFFFF;
+    }
+
+    ticks as u32
+}
+
+/// Links the given pending timer into the slot of its expiration tick.
+///
+/// Args:
+///
+/// `index` - the timer index
+fn link_timer(index: u16) {
+
+    let entry = get_entry(index);
+    let head = get_slot_head(entry.expiration_tick & (TIMER_WHEEL_SLOTS_AMOUNT - 1));
+
+    entry.previous = NO_TIMER;
+    entry.next = *head;
+
+    if *head != NO_TIMER {
+        get_entry(*head).previous = index;
+    }
+
+    *head = index;
+}
+
+/// Removes the given pending timer from its wheel slot.
+///
+/// Args:
+///
+/// `index` - the timer index
+fn unlink_timer(index: u16) {
+
+    let entry = get_entry(index);
+
+    if entry.previous == NO_TIMER {
+        *get_slot_head(entry.expiration_tick & (TIMER_WHEEL_SLOTS_AMOUNT - 1)) = entry.next;
+    } else {
+        get_entry(entry.previous).next = entry.next;
+    }
+
+    if entry.next != NO_TIMER {
+        get_entry(entry.next).previous = entry.previous;
+    }
+}
+
+/// Puts the given timer back into the free list.
+///
+/// Args:
+///
+/// `index` - the timer index
+fn release_timer(index: u16) {
+
+    let entry = get_entry(index);
+    entry.pending = false;
+    entry.generation = entry.generation.wrapping_add(1);
+    entry.next = *get_free_head();
+    *get_free_head() = index;
+}
+
+/// Initializes the timers pool and the timer wheel, must be called before the PIT
+/// starts to call `expire_timers`.
+pub fn initialize_timers() {
+
+    for slot in 0..TIMER_WHEEL_SLOTS_AMOUNT {
+        *get_slot_head(slot) = NO_TIMER;
+    }
+
+    for index in 0..TIMERS_AMOUNT {
+
+        let entry = get_entry(index);
+        entry.pending = false;
+        entry.generation = 0;
+        entry.next = if index + 1 == TIMERS_AMOUNT { NO_TIMER } else { index + 1 };
+    }
+
+    *get_free_head() = 0;
+}
+
+/// Expires the timers of the given tick, called by the PIT interrupt routine at every tick.
+/// The callbacks of the expired timers are enqueued as deferred work,
+/// the periodic timers are linked again for their next expiration.
+/// The timers that cannot be enqueued (deferred work queue full) are delayed by one tick.
+///
+/// Args:
+///
+/// `tick` - the current tick
+pub fn expire_timers(tick: u32) {
+
+    let mut index = *get_slot_head(tick & (TIMER_WHEEL_SLOTS_AMOUNT - 1));
+
+    while index != NO_TIMER {
+
+        let entry = get_entry(index);
+        let next = entry.next;
+
+        /* timers of the next rounds of the wheel stay into the slot */
+        if entry.expiration_tick == tick {
+
+            unlink_timer(index);
+
+            let callback: TimerCallback = unsafe { mem::transmute(entry.callback as usize) };
+
+            /* when the deferred work queue is full, the timer stays pending
+               and expires again at the next tick, so its callback is never lost */
+            if !defer_work(callback, entry.argument) {
+                entry.expiration_tick = tick.wrapping_add(1);
+                link_timer(index);
+            } else if entry.period_ticks != 0 {
+                entry.expiration_tick = tick.wrapping_add(entry.period_ticks);
+                link_timer(index);
+            } else {
+                release_timer(index);
+            }
+        }
+
+        index = next;
+    }
+}
+
+impl Timer {
+
+    /// Creates a pending timer.
+    ///
+    /// Args:
+    ///
+    /// `delay` - the time before the first expiration
+    /// `period` - the time between two expirations, zero for one-shot timers
+    /// `callback` - the function to call when the timer expires
+    /// `argument` - the argument given to the function
+    ///
+    /// Returns:
+    ///
+    /// the timer handle, None if every timer of the pool is used
+/// or if the PIT is not initialized yet
+    fn create(
+        delay: Duration,
+        period: Duration,
+        callback: TimerCallback,
+        argument: u32,
+    ) -> Option<Timer> {
+
+        if get_tick_duration() == 0 {
+            return None;
+        }
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let index = *get_free_head();
+        if index == NO_TIMER {
+            unsafe { restore_interrupts(flags); }
+            return None;
+        }
+
+        let entry = get_entry(index);
+        *get_free_head() = entry.next;
+
+        entry.expiration_tick = get_current_tick().wrapping_add(duration_to_ticks(delay));
+        entry.period_ticks = if period == Duration::from_secs(0) {
+            0
+        } else {
+            duration_to_ticks(period)
+        };
+        entry.callback = (callback as *const ()) as u32;
+        entry.argument = argument;
+        entry.pending = true;
+        link_timer(index);
+
+        let timer = Timer {
+            index: index,
+            generation: entry.generation,
+        };
+
+        unsafe { restore_interrupts(flags); }
+
+        Some(timer)
+    }
+
+    /// Creates a one-shot timer, the callback is called with the argument 0.
+    ///
+    /// Args:
+    ///
+    /// `delay` - the time before the expiration
+    /// `callback` - the function to call when the timer expires
+    ///
+    /// Returns:
+    ///
+    /// the timer handle, None if every timer of the pool is used
+/// or if the PIT is not initialized yet
+    pub fn after(delay: Duration, callback: TimerCallback) -> Option<Timer> {
+        Timer::create(delay, Duration::from_secs(0), callback, 0)
+    }
+
+    /// Creates a one-shot timer.
+    ///
+    /// Args:
+    ///
+    /// `delay` - the time before the expiration
+    /// `callback` - the function to call when the timer expires
+    /// `argument` - the argument given to the function
+    ///
+    /// Returns:
+    ///
+    /// the timer handle, None if every timer of the pool is used
+/// or if the PIT is not initialized yet
+    pub fn after_with_argument(
+        delay: Duration,
+        callback: TimerCallback,
+        argument: u32,
+    ) -> Option<Timer> {
+        Timer::create(delay, Duration::from_secs(0), callback, argument)
+    }
+
+    /// Creates a periodic timer.
+    ///
+    /// Args:
+    ///
+    /// `period` - the time between two expirations (and before the first one)
+    /// `callback` - the function to call everytime the timer expires
+    /// `argument` - the argument given to the function
+    ///
+    /// Returns:
+    ///
+    /// the timer handle, None if every timer of the pool is used
+/// or if the PIT is not initialized yet
+    pub fn every(period: Duration, callback: TimerCallback, argument: u32) -> Option<Timer> {
+        Timer::create(period, period, callback, argument)
+    }
+
+    /// Indicates if the handle still refers to a pending timer.
+    /// Must be called with interrupts disabled.
+    ///
+    /// Returns:
+    ///
+    /// true if the timer has not expired (or is periodic) and has not been canceled
+    fn is_valid(&self) -> bool {
+
+        let entry = get_entry(self.index);
+        entry.pending && entry.generation == self.generation
+    }
+
+    /// Indicates if the timer is still pending.
+    ///
+    /// Returns:
+    ///
+    /// false if the timer has expired (one-shot timers) or has been canceled
+    pub fn is_pending(&self) -> bool {
+
+        let flags = unsafe { save_and_disable_interrupts() };
+        let pending = self.is_valid();
+        unsafe { restore_interrupts(flags); }
+
+        pending
+    }
+
+    /// Cancels the timer, its callback is not called anymore
+    /// (except if it has already been enqueued as deferred work).
+    ///
+    /// Returns:
+    ///
+    /// false if the timer was not pending anymore
+    pub fn cancel(&self) -> bool {
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let valid = self.is_valid();
+        if valid {
+            unlink_timer(self.index);
+            release_timer(self.index);
+        }
+
+        unsafe { restore_interrupts(flags); }
+
+        valid
+    }
+
+    /// Moves the next expiration of the timer (the period of periodic timers is unchanged).
+    ///
+    /// Args:
+    ///
+    /// `delay` - the time from now before the next expiration
+    ///
+    /// Returns:
+    ///
+    /// false if the timer was not pending anymore or if the PIT is not initialized yet
+    pub fn reschedule(&self, delay: Duration) -> bool {
+
+        if get_tick_duration() == 0 {
+            return false;
+        }
+
+        let flags = unsafe { save_and_disable_interrupts() };
+
+        let valid = self.is_valid();
+        if valid {
+            unlink_timer(self.index);
+            get_entry(self.index).expiration_tick =
+                get_current_tick().wrapping_add(duration_to_ticks(delay));
+            link_timer(self.index);
+        }
+
+        unsafe { restore_interrupts(flags); }
+
+        valid
+    }
+}
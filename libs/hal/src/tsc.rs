@@ -0,0 +1,286 @@
+//! TSC (Time Stamp Counter) calibration and high-resolution timestamps
+//!
+//! The TSC is a 64 bits counter incremented by the CPU at a constant rate
+//! (if the TSC is invariant) and read with one single instruction (rdtsc).
+//! Its frequency is unknown, so it is measured at boot against the PIT counter 2
+//! (the PIT oscillator frequency is known), then the TSC values are converted into nanoseconds.
+
+use io::{
+    inb,
+    outb,
+};
+
+use cpu::{
+    get_cpu_features,
+    CpuFeatures,
+};
+
+use pit::{
+    get_monotonic_time,
+    PIT_BASE_FREQUENCY,
+};
+
+use {
+    save_and_disable_interrupts,
+    restore_interrupts,
+};
+
+/* kernel global variables (check README.md):
+   the TSC frequency (in Hz), 0 if the TSC is not calibrated,
+   the TSC value at the calibration,
+   the monotonic time at the calibration (in nanoseconds),
+   1 if the TSC is invariant */
+const TSC_FREQUENCY_ADDRESS: u32 = 0xC0013060;
+const TSC_REFERENCE_ADDRESS: u32 = 0xC0013068;
+const TSC_REFERENCE_TIME_ADDRESS: u32 = 0xC0013070;
+const TSC_INVARIANT_ADDRESS: u32 = 0xC0013078;
+
+/* the PIT counter 2 data port, the PIT control word port
+   and the system control port B (counter 2 gate and output, PC speaker) */
+const PIT_COUNTER_2_PORT: u16 = 0x42;
+const PIT_CONTROL_WORD_PORT: u16 = 0x43;
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+
+/* the PIT counter 2 counts during 50 ms for the calibration */
+const CALIBRATION_FREQUENCY: u32 = 20;
+
+const NANOSECONDS_PER_SECOND: u64 = 1000000000;
+
+/// Reads the current TSC value.
+///
+/// Returns:
+///
+/// the amount of cycles since the CPU reset
+pub fn read_tsc() -> u64 {
+
+    let mut low: u32 = 0;
+    let mut high: u32 = 0;
+
+    /* rdtsc loads the counter into edx (high bits) and eax (low bits) */
+    unsafe {
+        llvm_asm!("rdtsc"
+            : "={eax}" (low), "={edx}" (high)
+            :::
+            "intel", "volatile"
+        );
+    }
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// Indicates if the CPU has a TSC.
+///
+/// Returns:
+///
+/// true if the rdtsc instruction is supported
+fn has_tsc() -> bool {
+    get_cpu_features().contains(CpuFeatures::TSC)
+}
+
+/// Detects if the TSC is invariant, so it runs at a constant rate
+/// whatever the CPU power state and frequency changes.
+///
+/// Returns:
+///
+/// true if the TSC is invariant
+fn detect_invariant_tsc() -> bool {
+    get_cpu_features().contains(CpuFeatures::INVARIANT_TSC)
+}
+
+/// Measures the amount of TSC cycles during 50 ms using the PIT counter 2.
+///
+/// Returns:
+///
+/// the TSC frequency in Hz
+fn measure_tsc_frequency() -> u64 {
+
+    /* system control port B:
+       bit 0: gate of the PIT counter 2 (the counter only counts when set),
+       bit 1: PC speaker data (the counter 2 output is sent to the speaker when set),
+       bit 5: output of the PIT counter 2 (read only) */
+    const COUNTER_2_GATE: u8 = 0b00000001;
+    const SPEAKER_DATA: u8 = 0b00000010;
+    const COUNTER_2_OUTPUT: u8 = 0b00100000;
+
+    /* counter 2 (bits 6-7 set to 10), low byte then high byte (bits 4-5 set to 11),
+       mode 0 (interrupt on terminal count, bits 1-3 set to 000), binary counting;
+       check the modes documentation into `set_timer_frequency`,
+       the OUT line goes to 1 when the counter reaches 0 */
+    const PIT_COUNTER_2_ICW: u8 = 0b10110000;
+
+    let reload_value = PIT_BASE_FREQUENCY / CALIBRATION_FREQUENCY;
+
+    unsafe {
+        let port_b = inb(SYSTEM_CONTROL_PORT_B);
+
+        /* the gate is disabled while the counter is loaded, the speaker stays silent */
+        outb(SYSTEM_CONTROL_PORT_B, port_b & !(COUNTER_2_GATE | SPEAKER_DATA));
+
+        outb(PIT_CONTROL_WORD_PORT, PIT_COUNTER_2_ICW);
+        outb(PIT_COUNTER_2_PORT, (reload_value & 0xff) as u8);
+        outb(PIT_COUNTER_2_PORT, ((reload_value >> 8) & 0xff) as u8);
+
+        /* the countdown starts when the gate is enabled */
+        outb(SYSTEM_CONTROL_PORT_B, (port_b & !SPEAKER_DATA) | COUNTER_2_GATE);
+        let start = read_tsc();
+
+        while inb(SYSTEM_CONTROL_PORT_B) & COUNTER_2_OUTPUT == 0 {}
+
+        let end = read_tsc();
+
+        outb(SYSTEM_CONTROL_PORT_B, port_b & !(COUNTER_2_GATE | SPEAKER_DATA));
+
+        /* the elapsed time is reload value / PIT base frequency */
+        end.wrapping_sub(start) * (PIT_BASE_FREQUENCY as u64) / (reload_value as u64)
+    }
+}
+
+/// Detects and calibrates the TSC, must be called after the PIT initialization.
+/// Interrupts are disabled during the calibration (50 ms).
+///
+/// Returns:
+///
+/// true if the TSC has been calibrated, false if the CPU has no TSC
+/// (the high-resolution time falls back to the PIT monotonic clock)
+pub fn initialize_tsc() -> bool {
+
+    unsafe {
+        *(TSC_FREQUENCY_ADDRESS as *mut u64) = 0;
+        *(TSC_INVARIANT_ADDRESS as *mut u8) = detect_invariant_tsc() as u8;
+    }
+
+    if !has_tsc() {
+        return false;
+    }
+
+    unsafe {
+        let flags = save_and_disable_interrupts();
+
+        let frequency = measure_tsc_frequency();
+
+        *(TSC_REFERENCE_ADDRESS as *mut u64) = read_tsc();
+        *(TSC_REFERENCE_TIME_ADDRESS as *mut u64) = get_monotonic_time();
+        *(TSC_FREQUENCY_ADDRESS as *mut u64) = frequency;
+
+        restore_interrupts(flags);
+    }
+
+    true
+}
+
+/// Indicates if the TSC is invariant (constant rate whatever the CPU power state).
+/// A non invariant TSC is still calibrated, but its values might drift.
+///
+/// Returns:
+///
+/// true if the TSC is invariant
+pub fn is_tsc_invariant() -> bool {
+    unsafe { *(TSC_INVARIANT_ADDRESS as *const u8) != 0 }
+}
+
+/// Returns the calibrated TSC frequency.
+///
+/// Returns:
+///
+/// the frequency in Hz, 0 if the TSC is not calibrated
+pub fn get_tsc_frequency() -> u64 {
+    unsafe { *(TSC_FREQUENCY_ADDRESS as *const u64) }
+}
+
+/// Converts an amount of TSC cycles into nanoseconds.
+///
+/// Args:
+///
+/// `cycles` - the amount of cycles
+///
+/// Returns:
+///
+/// the duration in nanoseconds, 0 if the TSC is not calibrated
+pub fn cycles_to_nanoseconds(cycles: u64) -> u64 {
+
+    let frequency = get_tsc_frequency();
+    if frequency == 0 {
+        return 0;
+    }
+
+    /* the seconds and the remaining cycles are converted separately,
+       so the multiplication does not overflow for long durations */
+    (cycles / frequency) * NANOSECONDS_PER_SECOND +
+        (cycles % frequency) * NANOSECONDS_PER_SECOND / frequency
+}
+
+/// Returns the elapsed time since the PIT initialization with a nanosecond resolution,
+/// using the TSC (or the PIT monotonic clock if the TSC is not calibrated).
+///
+/// Returns:
+///
+/// the elapsed time in nanoseconds
+pub fn get_tsc_time() -> u64 {
+
+    if get_tsc_frequency() == 0 {
+        return get_monotonic_time();
+    }
+
+    let (reference, reference_time) = unsafe {
+        (
+            *(TSC_REFERENCE_ADDRESS as *const u64),
+            *(TSC_REFERENCE_TIME_ADDRESS as *const u64),
+        )
+    };
+
+    reference_time + cycles_to_nanoseconds(read_tsc().wrapping_sub(reference))
+}
+
+/// Counts the CPU cycles spent by a piece of code (for benchmarks):
+/// the counter is started just before the measured code
+/// and the elapsed cycles (or nanoseconds) are read just after.
+#[derive(Copy, Clone)]
+pub struct CycleCounter {
+    start: u64,
+}
+
+impl CycleCounter {
+
+    /// Starts to count the cycles.
+    ///
+    /// Returns:
+    ///
+    /// the counter
+    pub fn start() -> CycleCounter {
+        CycleCounter {
+            start: read_tsc(),
+        }
+    }
+
+    /// Returns the amount of cycles since the counter started.
+    ///
+    /// Returns:
+    ///
+    /// the elapsed cycles
+    pub fn elapsed_cycles(&self) -> u64 {
+        read_tsc().wrapping_sub(self.start)
+    }
+
+    /// Returns the elapsed time since the counter started.
+    ///
+    /// Returns:
+    ///
+    /// the elapsed time in nanoseconds, 0 if the TSC is not calibrated
+    pub fn elapsed_nanoseconds(&self) -> u64 {
+        cycles_to_nanoseconds(self.elapsed_cycles())
+    }
+
+    /// Restarts the counter.
+    ///
+    /// Returns:
+    ///
+    /// the amount of cycles since the counter started (before the restart)
+    pub fn restart(&mut self) -> u64 {
+
+        let now = read_tsc();
+        let elapsed = now.wrapping_sub(self.start);
+        self.start = now;
+
+        elapsed
+    }
+}
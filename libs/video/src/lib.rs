@@ -12,7 +12,7 @@ extern crate rlibc;
 /// `string` - the message to print
 pub fn print(offset: u32, string: &str) {
 
-    let mut offset: u32 = 0xB8000 + (offset * 2) as u32;
+    let mut offset: u32 = 0xC00B8000 + (offset * 2) as u32;
 
     for byte in string.bytes() {
 
@@ -33,14 +33,14 @@ pub fn clear_screen() {
     /* ensure every character on the screen
        is displayed in white (with intensity) */
 
-    const START_OFFSET: u32 = 0xB8000;
+    const START_OFFSET: u32 = 0xC00B8000;
     let mut offset = START_OFFSET;
 
     /* screen text resolution is 80 x 25,
        so there are 2000 items to set,
        one time for the character, one time for the color,
-       0xB8001 + (2000 * 2) = 0xB8FA0 */
-    const END_OFFSET: u32 = 0xB8FA0;
+       0xC00B8000 + (2000 * 2) - 1 = 0xC00B8F9F */
+    const END_OFFSET: u32 = START_OFFSET + 80 * 25 * 2 - 1;
 
     /* every screen item should be written
        with white foreground, black background
@@ -75,7 +75,7 @@ pub fn printi32(offset: u32, mut value: u32) {
 
     const ASCII_OFFSET: u32 = 48;
 
-    let mut offset: u32 = 0xB8000 + (offset * 2) as u32;
+    let mut offset: u32 = 0xC00B8000 + (offset * 2) as u32;
 
     if value == 0 {
         unsafe { printb(offset, '0' as u8); };
@@ -126,7 +126,7 @@ pub fn printi32hex(mut offset: u32, mut value: u32) {
     print(offset, "0x");
     offset += 2;
 
-    let mut offset: u32 = 0xB8000 + (offset * 2) as u32;
+    let mut offset: u32 = 0xC00B8000 + (offset * 2) as u32;
 
     if value == 0 {
         unsafe { printb(offset, '0' as u8) };